@@ -1,15 +1,27 @@
 use std::fmt;
+use std::io::Write;
 use std::ops::Deref;
+use std::path::Path;
+#[cfg(unix)]
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use url::Url;
 
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use crate::body::ContentEncoding;
+use crate::error::Error;
 use crate::middleware::Middleware;
 use crate::pool::ConnectionPool;
-use crate::proxy::Proxy;
+use crate::proxy::{EnvProxy, Proxy, ProxySelector};
 use crate::request::Request;
 use crate::resolve::{ArcResolver, StdResolver};
-use crate::stream::TlsConnector;
+use crate::response::Limits;
+use crate::retry::RetryPolicy;
+use crate::sse::EventSource;
+use crate::stream::{Connector, TlsConnector};
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocket;
 
 #[cfg(feature = "cookies")]
 use {
@@ -45,6 +57,8 @@ pub struct AgentBuilder {
     #[cfg(feature = "cookies")]
     cookie_store: Option<CookieStore>,
     resolver: ArcResolver,
+    connector: Option<Arc<dyn Connector>>,
+    proxy_selector: Option<Arc<dyn ProxySelector>>,
     middleware: Vec<Box<dyn Middleware>>,
 }
 
@@ -69,16 +83,24 @@ impl Deref for TlsConfig {
 #[derive(Clone, Debug)]
 pub(crate) struct AgentConfig {
     pub proxy: Option<Proxy>,
+    pub env_proxy: Option<EnvProxy>,
+    #[cfg(unix)]
+    pub unix_socket: Option<PathBuf>,
     pub timeout_connect: Option<Duration>,
     pub timeout_read: Option<Duration>,
     pub timeout_write: Option<Duration>,
     pub timeout: Option<Duration>,
+    pub timeout_expect_continue: Duration,
     pub https_only: bool,
     pub no_delay: bool,
     pub redirects: u32,
     pub redirect_auth_headers: RedirectAuthHeaders,
     pub user_agent: String,
     pub tls_config: TlsConfig,
+    pub limits: Limits,
+    pub retry: Option<RetryPolicy>,
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub compress: Option<ContentEncoding>,
 }
 
 /// Agents keep state between requests.
@@ -126,6 +148,8 @@ pub(crate) struct AgentState {
     #[cfg(feature = "cookies")]
     pub(crate) cookie_tin: CookieTin,
     pub(crate) resolver: ArcResolver,
+    pub(crate) connector: Option<Arc<dyn Connector>>,
+    pub(crate) proxy_selector: Option<Arc<dyn ProxySelector>>,
     pub(crate) middleware: Vec<Box<dyn Middleware>>,
 }
 
@@ -214,6 +238,79 @@ impl Agent {
         self.request("DELETE", path)
     }
 
+    /// Download the body at `path` to `writer`, resuming it when the connection is lost.
+    ///
+    /// The request is a GET. See [`Request::download()`].
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::agent();
+    /// let mut body = vec![];
+    /// agent.download("http://example.com/artifact.tar", &mut body)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn download(&self, path: &str, writer: impl Write) -> Result<u64, Error> {
+        self.get(path).download(writer)
+    }
+
+    /// Download the body at `path` to the file at `file`, resuming it when the
+    /// connection is lost.
+    ///
+    /// The request is a GET. See [`Request::download_to_file()`].
+    pub fn download_to_file(&self, path: &str, file: impl AsRef<Path>) -> Result<u64, Error> {
+        self.get(path).download_to_file(file)
+    }
+
+    /// Subscribe to the Server-Sent Events at `path`, reconnecting when the
+    /// connection is lost.
+    ///
+    /// The request is a GET with `Accept: text/event-stream`. See [`EventSource`].
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::agent();
+    /// for event in agent.event_source("http://example.com/stream") {
+    ///     match event {
+    ///         Ok(event) => println!("{}", event.data()),
+    ///         // The next iteration reconnects.
+    ///         Err(ureq::Error::Transport(e)) => eprintln!("disconnected: {}", e),
+    ///         Err(e) => return Err(e),
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn event_source(&self, path: &str) -> EventSource {
+        EventSource::new(self.get(path))
+    }
+
+    /// Open a WebSocket connection to `path`, a `ws://` or `wss://` URL.
+    ///
+    /// The handshake is an ordinary GET request made by this agent, so it goes through
+    /// the agent's proxy, TLS config, cookies and middleware. See [`WebSocket`].
+    ///
+    /// The handshake is sent over HTTP/1.1 even when the `http2` feature is enabled,
+    /// since an HTTP/2 connection cannot be upgraded.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["websocket"] }`
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::agent();
+    /// let mut socket = agent.websocket("ws://example.com/socket")?;
+    /// socket.send(ureq::Message::Text("hello".into()))?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "websocket")]
+    pub fn websocket(&self, path: &str) -> Result<WebSocket, crate::Error> {
+        self.get(path).websocket()
+    }
+
     /// Read access to the cookie store.
     ///
     /// Used to persist the cookies to an external writer.
@@ -253,21 +350,31 @@ impl AgentBuilder {
         AgentBuilder {
             config: AgentConfig {
                 proxy: None,
+                env_proxy: None,
+                #[cfg(unix)]
+                unix_socket: None,
                 timeout_connect: Some(Duration::from_secs(30)),
                 timeout_read: None,
                 timeout_write: None,
                 timeout: None,
+                timeout_expect_continue: Duration::from_secs(1),
                 https_only: false,
                 no_delay: true,
                 redirects: 5,
                 redirect_auth_headers: RedirectAuthHeaders::Never,
                 user_agent: format!("ureq/{}", env!("CARGO_PKG_VERSION")),
                 tls_config: TlsConfig(crate::default_tls_config()),
+                limits: Limits::default(),
+                retry: None,
+                #[cfg(any(feature = "gzip", feature = "brotli"))]
+                compress: None,
             },
             try_proxy_from_env: false,
             max_idle_connections: DEFAULT_MAX_IDLE_CONNECTIONS,
             max_idle_connections_per_host: DEFAULT_MAX_IDLE_CONNECTIONS_PER_HOST,
             resolver: StdResolver.into(),
+            connector: None,
+            proxy_selector: None,
             #[cfg(feature = "cookies")]
             cookie_store: None,
             middleware: vec![],
@@ -281,9 +388,7 @@ impl AgentBuilder {
     // built Agent.
     pub fn build(mut self) -> Agent {
         if self.config.proxy.is_none() && self.try_proxy_from_env {
-            if let Some(proxy) = Proxy::try_from_system() {
-                self.config.proxy = Some(proxy);
-            }
+            self.config.env_proxy = EnvProxy::try_from_system();
         }
         Agent {
             config: Arc::new(self.config),
@@ -295,6 +400,8 @@ impl AgentBuilder {
                 #[cfg(feature = "cookies")]
                 cookie_tin: CookieTin::new(self.cookie_store.unwrap_or_else(CookieStore::default)),
                 resolver: self.resolver,
+                connector: self.connector,
+                proxy_selector: self.proxy_selector,
                 middleware: self.middleware,
             }),
         }
@@ -320,8 +427,49 @@ impl AgentBuilder {
         self
     }
 
+    /// Choose the proxy for each request with a [`ProxySelector`].
+    ///
+    /// The selector takes precedence over [`proxy()`](Self::proxy) and
+    /// [`try_proxy_from_env()`](Self::try_proxy_from_env).
+    ///
+    /// Example:
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// use ureq::Proxy;
+    /// use url::Url;
+    ///
+    /// let primary = Proxy::new("http://proxy1.corp:3128")?;
+    /// let backup = Proxy::new("http://proxy2.corp:3128")?;
+    ///
+    /// let agent = ureq::AgentBuilder::new()
+    ///     .proxy_selector(move |url: &Url| {
+    ///         // Internal hosts are reached directly.
+    ///         match url.host_str() {
+    ///             Some(host) if host.ends_with(".corp") => vec![],
+    ///             _ => vec![primary.clone(), backup.clone()],
+    ///         }
+    ///     })
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn proxy_selector(mut self, selector: impl ProxySelector + 'static) -> Self {
+        self.proxy_selector = Some(Arc::new(selector));
+        self
+    }
+
     /// Attempt to detect proxy settings from the environment, i.e. HTTP_PROXY
     ///
+    /// Like curl, `HTTP_PROXY` is used for http URLs and `HTTPS_PROXY` for https URLs,
+    /// with `ALL_PROXY` as a fallback for both. Hosts listed in `NO_PROXY` are connected
+    /// to directly. The list may hold domain names, which also match their subdomains,
+    /// IP addresses, CIDR ranges such as `10.0.0.0/8`, or `*` to match every host.
+    /// The lower case variants of the variables are also recognized.
+    ///
+    /// The variables are read when the agent is built, and the proxy is picked for
+    /// each request (and each redirect) based on its URL.
+    ///
     /// The default is `false`, i.e. not detecting proxy from env since this is
     /// a potential security risk.
     ///
@@ -398,6 +546,56 @@ impl AgentBuilder {
         self
     }
 
+    /// Send all requests to a Unix domain socket, instead of the host in the url.
+    ///
+    /// This is how local daemons such as Docker are usually talked to. The url still
+    /// decides the path, the `Host` header and whether TLS is used, but the connection
+    /// is made to the socket at `path`. Connections are pooled per socket.
+    ///
+    /// To pick the socket per request instead, use an `http+unix` url with the
+    /// percent-encoded socket path as host, like
+    /// `http+unix://%2Fvar%2Frun%2Fdocker.sock/info`.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// let agent = ureq::builder()
+    ///     .unix_socket("/var/run/docker.sock")
+    ///     .build();
+    /// let info = agent.get("http://localhost/v1.43/info")
+    ///     .call()?
+    ///     .into_string()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(unix)]
+    pub fn unix_socket(mut self, path: impl AsRef<Path>) -> Self {
+        self.config.unix_socket = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Configures a custom [`Connector`] to open the connections of this agent,
+    /// instead of TCP connections to the host in the url.
+    ///
+    /// The connector returns the stream requests are written to, as is: for `https`
+    /// urls, it adds TLS itself, and proxies configured on the agent are not used.
+    ///
+    /// ```
+    /// use std::net::TcpStream;
+    /// use ureq::{Error, ReadWrite};
+    /// use url::Url;
+    ///
+    /// // Send all requests through a local sidecar, whatever the host.
+    /// let agent = ureq::builder()
+    ///     .connector(|_url: &Url, _host: &str, _port: u16| -> Result<Box<dyn ReadWrite>, Error> {
+    ///         Ok(Box::new(TcpStream::connect("127.0.0.1:15001")?))
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.connector = Some(Arc::new(connector));
+        self
+    }
+
     /// Timeout for the socket connection to be successful.
     /// If both this and `.timeout()` are both set, `.timeout_connect()`
     /// takes precedence.
@@ -465,9 +663,9 @@ impl AgentBuilder {
     }
 
     /// Timeout for the overall request, including DNS resolution, connection
-    /// time, redirects, and reading the response body. Slow DNS resolution
-    /// may cause a request to exceed the timeout, because the DNS request
-    /// cannot be interrupted with the available APIs.
+    /// time, redirects, and reading the response body. A custom [`Resolver`](crate::Resolver)
+    /// only keeps to the timeout if it implements
+    /// [`Resolver::resolve_with_deadline`](crate::Resolver::resolve_with_deadline).
     ///
     /// This takes precedence over `.timeout_read()` and `.timeout_write()`, but
     /// not `.timeout_connect()`.
@@ -488,6 +686,35 @@ impl AgentBuilder {
         self
     }
 
+    /// How long to wait for a `100 Continue` before sending the request body anyway.
+    ///
+    /// This only applies to requests with a body that have the `Expect: 100-continue`
+    /// header set. For those, the request headers are sent first, and the body is held
+    /// back until the server answers `100 Continue`. If the server instead answers
+    /// with a final status, such as `401` or `413`, that response is returned and the
+    /// body is never sent. Servers are not required to support the expectation, so
+    /// if nothing is heard within this timeout, the body is sent regardless.
+    ///
+    /// The default is 1 second.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .timeout_expect_continue(Duration::from_secs(3))
+    ///     .build();
+    /// let result = agent.put("http://httpbin.org/put")
+    ///     .set("Expect", "100-continue")
+    ///     .send_string("a large upload");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn timeout_expect_continue(mut self, timeout: Duration) -> Self {
+        self.config.timeout_expect_continue = timeout;
+        self
+    }
+
     /// Whether no_delay will be set on the tcp socket.
     /// Setting this to true disables Nagle's algorithm.
     ///
@@ -515,8 +742,11 @@ impl AgentBuilder {
     ///
     /// If the redirect count hits this limit (and it's > 0), TooManyRedirects is returned.
     ///
-    /// WARNING: for 307 and 308 redirects, this value is ignored for methods that have a body.
-    /// You must handle 307 redirects yourself when sending a PUT, POST, PATCH, or DELETE request.
+    /// 307 and 308 redirects keep the method and resend the body. Bodies sent from a reader
+    /// with [`Request::send()`](crate::Request::send) can only be sent once, so for those
+    /// the 307 or 308 response is returned, and you must handle the redirect yourself.
+    /// Use [`Request::send_seekable()`](crate::Request::send_seekable) for bodies that
+    /// can be read again. A 307 or 308 in answer to a DELETE is never followed.
     ///
     /// ```no_run
     /// # fn main() -> Result<(), ureq::Error> {
@@ -531,7 +761,7 @@ impl AgentBuilder {
     /// assert_ne!(result.status(), 301);
     ///
     /// let result = ureq::post("http://httpbin.org/status/307")
-    ///     .send_bytes(b"some data")?;
+    ///     .send(&b"some data"[..])?;
     /// assert_eq!(result.status(), 307);
     /// # Ok(())
     /// # }
@@ -583,6 +813,110 @@ impl AgentBuilder {
         self
     }
 
+    /// The largest response body this agent reads, after decompression.
+    ///
+    /// Reading past the limit fails with an `std::io::Error`, whose source is a
+    /// [`ErrorKind::LimitExceeded`](crate::ErrorKind::LimitExceeded) error. This applies
+    /// to [`Response::into_reader()`](crate::Response::into_reader) and the methods
+    /// built on it. Unlimited by default, except for
+    /// [`Response::into_string()`](crate::Response::into_string), which stops at 10 megabytes.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .max_body_size(1024 * 1024)
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.config.limits.max_body_size = Some(bytes);
+        self
+    }
+
+    /// The highest compression ratio accepted for response bodies, to protect
+    /// against decompression bombs.
+    ///
+    /// A response body that decodes to more than `ratio` times the size of the
+    /// encoded body fails like one that exceeds
+    /// [`max_body_size()`](AgentBuilder::max_body_size). The ratio is checked once
+    /// more than one megabyte has been decoded. Unlimited by default.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .max_compression_ratio(100)
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.config.limits.max_compression_ratio = Some(ratio);
+        self
+    }
+
+    /// The largest response header block this agent reads, in bytes, status line included.
+    ///
+    /// A larger header block fails the request with
+    /// [`ErrorKind::LimitExceeded`](crate::ErrorKind::LimitExceeded). Without this limit,
+    /// responses can have up to 100 header fields of up to 100 kilobytes each.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .max_header_size(64 * 1024)
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_header_size(mut self, bytes: usize) -> Self {
+        self.config.limits.max_header_size = Some(bytes);
+        self
+    }
+
+    /// Retry failed requests according to `policy`.
+    ///
+    /// Without a policy, the only retry is for a request that failed on a pooled
+    /// connection the server had already closed. See [`RetryPolicy`] for which
+    /// requests are retried, and when.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .retry(ureq::RetryPolicy::new().max_attempts(4))
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn retry(mut self, policy: RetryPolicy) -> Self {
+        self.config.retry = Some(policy);
+        self
+    }
+
+    /// Compress the bodies of all requests sent by this agent.
+    ///
+    /// Requests can opt out, or pick another encoding, using
+    /// [`Request::compress()`](crate::Request::compress).
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let agent = ureq::builder()
+    ///     .compress(ureq::ContentEncoding::Gzip)
+    ///     .build();
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub fn compress(mut self, encoding: ContentEncoding) -> Self {
+        self.config.compress = Some(encoding);
+        self
+    }
+
     /// Configure TLS options for rustls to use when making HTTPS connections from this Agent.
     ///
     /// This overrides any previous call to tls_config or tls_connector.
@@ -743,6 +1077,7 @@ mod tests {
         assert!(debug_format.contains("timeout_read:"));
         assert!(debug_format.contains("timeout_write:"));
         assert!(debug_format.contains("timeout:"));
+        assert!(debug_format.contains("timeout_expect_continue:"));
         assert!(debug_format.contains("https_only:"));
         assert!(debug_format.contains("no_delay:"));
         assert!(debug_format.contains("redirects:"));
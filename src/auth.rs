@@ -0,0 +1,307 @@
+//! Answering authentication challenges, as sent by a proxy in `Proxy-Authenticate`.
+//!
+//! Supports the `Basic` scheme, <https://www.rfc-editor.org/rfc/rfc7617>, and with the
+//! `digest-auth` feature, the `Digest` scheme with the MD5 and MD5-sess algorithms,
+//! <https://www.rfc-editor.org/rfc/rfc7616>.
+
+use std::fmt;
+#[cfg(feature = "digest-auth")]
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "digest-auth")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+#[cfg(feature = "digest-auth")]
+use md5::{Digest, Md5};
+
+/// One challenge of a `WWW-Authenticate` or `Proxy-Authenticate` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Challenge {
+    pub scheme: String,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    /// Parse all challenges in a header value. A single header can hold several,
+    /// like `Digest realm="a", nonce="b", Basic realm="a"`.
+    pub fn parse_all(value: &str) -> Vec<Challenge> {
+        let mut challenges: Vec<Challenge> = vec![];
+        let mut rest = value;
+
+        loop {
+            rest = rest.trim_start_matches(|c: char| c == ',' || c.is_whitespace());
+            if rest.is_empty() {
+                return challenges;
+            }
+
+            let end = rest
+                .find(|c: char| c == '=' || c == ',' || c.is_whitespace())
+                .unwrap_or(rest.len());
+            let token = &rest[..end];
+            rest = rest[end..].trim_start();
+
+            if !rest.starts_with('=') {
+                challenges.push(Challenge {
+                    scheme: token.to_string(),
+                    params: vec![],
+                });
+                continue;
+            }
+
+            rest = rest[1..].trim_start();
+            let value = if let Some(quoted) = rest.strip_prefix('"') {
+                let (value, len) = unquote(quoted);
+                rest = &quoted[len..];
+                value
+            } else {
+                let end = rest
+                    .find(|c: char| c == ',' || c.is_whitespace())
+                    .unwrap_or(rest.len());
+                let value = rest[..end].to_string();
+                rest = &rest[end..];
+                value
+            };
+
+            // A parameter before any scheme is malformed, skip it.
+            if let Some(challenge) = challenges.last_mut() {
+                challenge.params.push((token.to_ascii_lowercase(), value));
+            }
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    fn is_scheme(&self, scheme: &str) -> bool {
+        self.scheme.eq_ignore_ascii_case(scheme)
+    }
+
+    /// Whether we know how to answer this challenge.
+    fn is_supported(&self) -> bool {
+        if self.is_scheme("basic") {
+            return true;
+        }
+        cfg!(feature = "digest-auth")
+            && self.is_scheme("digest")
+            && self.param("nonce").is_some()
+            && matches!(
+                self.param("algorithm")
+                    .map(|a| a.to_ascii_lowercase())
+                    .as_deref(),
+                None | Some("md5") | Some("md5-sess")
+            )
+            && match self.param("qop") {
+                None => true,
+                Some(qop) => qop.split(',').any(|q| q.trim() == "auth"),
+            }
+    }
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.scheme)?;
+        for (i, (name, value)) in self.params.iter().enumerate() {
+            let sep = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}=\"{}\"", sep, name, quote(value))?;
+        }
+        Ok(())
+    }
+}
+
+/// Read a quoted string, starting after the opening quote. Returns the unescaped
+/// value, and how many bytes were consumed including the closing quote.
+fn unquote(s: &str) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, i + 1),
+            '\\' => {
+                if let Some((_, c)) = chars.next() {
+                    value.push(c);
+                }
+            }
+            c => value.push(c),
+        }
+    }
+    (value, s.len())
+}
+
+fn quote(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// The credentials to answer the best supported of `challenges` with, if any.
+///
+/// `method` and `uri` are those of the request being authorized, i.e. `CONNECT` and
+/// `host:port` for a tunnel.
+pub(crate) fn authorization(
+    challenges: &[Challenge],
+    user: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+) -> Option<String> {
+    let supported = || challenges.iter().filter(|c| c.is_supported());
+    // Prefer Digest, it doesn't send the password in the clear.
+    let challenge = supported()
+        .find(|c| c.is_scheme("digest"))
+        .or_else(|| supported().next())?;
+
+    if challenge.is_scheme("basic") {
+        return Some(basic(user, password));
+    }
+    #[cfg(feature = "digest-auth")]
+    if challenge.is_scheme("digest") {
+        return Some(digest(challenge, user, password, method, uri, &cnonce()));
+    }
+    let _ = (method, uri);
+    None
+}
+
+pub(crate) fn basic(user: &str, password: &str) -> String {
+    let creds = BASE64_STANDARD.encode(format!("{}:{}", user, password));
+    format!("Basic {}", creds)
+}
+
+#[cfg(feature = "digest-auth")]
+fn digest(
+    challenge: &Challenge,
+    user: &str,
+    password: &str,
+    method: &str,
+    uri: &str,
+    cnonce: &str,
+) -> String {
+    let realm = challenge.param("realm").unwrap_or_default();
+    let nonce = challenge.param("nonce").unwrap_or_default();
+    let algorithm = challenge.param("algorithm");
+    let is_sess = matches!(algorithm, Some(a) if a.eq_ignore_ascii_case("md5-sess"));
+    let has_qop = challenge.param("qop").is_some();
+    let nc = "00000001";
+
+    let mut ha1 = md5_hex(&format!("{}:{}:{}", user, realm, password));
+    if is_sess {
+        ha1 = md5_hex(&format!("{}:{}:{}", ha1, nonce, cnonce));
+    }
+    let ha2 = md5_hex(&format!("{}:{}", method, uri));
+    let response = if has_qop {
+        md5_hex(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+    } else {
+        md5_hex(&format!("{}:{}:{}", ha1, nonce, ha2))
+    };
+
+    let mut header = format!(
+        "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{}\"",
+        quote(user),
+        quote(realm),
+        quote(nonce),
+        quote(uri),
+        response
+    );
+    if let Some(algorithm) = algorithm {
+        header.push_str(&format!(", algorithm={}", algorithm));
+    }
+    if has_qop {
+        header.push_str(&format!(", qop=auth, nc={}, cnonce=\"{}\"", nc, cnonce));
+    }
+    if let Some(opaque) = challenge.param("opaque") {
+        header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+    }
+    header
+}
+
+#[cfg(feature = "digest-auth")]
+/// A client nonce, only needs to be unpredictable enough to not repeat.
+fn cnonce() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    md5_hex(&format!("{}:{}:{}", now, count, std::process::id()))[..16].to_string()
+}
+
+#[cfg(feature = "digest-auth")]
+fn md5_hex(input: &str) -> String {
+    Md5::digest(input.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "digest-auth")]
+    #[test]
+    fn md5_vectors() {
+        // From RFC 1321.
+        assert_eq!(md5_hex(""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex("abc"), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(
+            md5_hex(
+                "12345678901234567890123456789012345678901234567890123456789012345678901234567890"
+            ),
+            "57edf4a22be3c955ac49da2e2107b67a"
+        );
+    }
+
+    #[test]
+    fn parse_challenges() {
+        let challenges = Challenge::parse_all(
+            r#"Digest realm="squid, proxy", nonce="abc\"def", qop="auth,auth-int", stale=false, Basic realm=other"#,
+        );
+        assert_eq!(challenges.len(), 2);
+        assert_eq!(challenges[0].scheme, "Digest");
+        assert_eq!(challenges[0].param("realm"), Some("squid, proxy"));
+        assert_eq!(challenges[0].param("nonce"), Some("abc\"def"));
+        assert_eq!(challenges[0].param("qop"), Some("auth,auth-int"));
+        assert_eq!(challenges[0].param("stale"), Some("false"));
+        assert_eq!(challenges[1].scheme, "Basic");
+        assert_eq!(challenges[1].param("realm"), Some("other"));
+        assert_eq!(challenges[1].to_string(), r#"Basic realm="other""#);
+    }
+
+    #[cfg(feature = "digest-auth")]
+    #[test]
+    fn digest_rfc2617_example() {
+        // https://www.rfc-editor.org/rfc/rfc2617#section-3.5
+        let challenge = Challenge::parse_all(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .remove(0);
+        let header = digest(
+            &challenge,
+            "Mufasa",
+            "Circle Of Life",
+            "GET",
+            "/dir/index.html",
+            "0a4f113b",
+        );
+        assert!(header.contains(r#"response="6629fae49393a05397450978507c4ef1""#));
+        assert!(header.contains("qop=auth, nc=00000001"));
+        assert!(header.contains(r#"opaque="5ccc069c403ebaf9f0171e9517f40e41""#));
+    }
+
+    #[cfg(feature = "digest-auth")]
+    #[test]
+    fn prefers_digest() {
+        let challenges = Challenge::parse_all(r#"Basic realm="a", Digest realm="a", nonce="n""#);
+        let auth = authorization(&challenges, "u", "p", "CONNECT", "host:443").unwrap();
+        assert!(auth.starts_with("Digest "));
+
+        let challenges = Challenge::parse_all(r#"Digest nonce="n", algorithm=SHA-256, Basic"#);
+        let auth = authorization(&challenges, "u", "p", "CONNECT", "host:443").unwrap();
+        assert_eq!(auth, "Basic dTpw");
+
+        let challenges = Challenge::parse_all(r#"Negotiate"#);
+        assert_eq!(authorization(&challenges, "u", "p", "GET", "/"), None);
+    }
+}
@@ -1,11 +1,22 @@
+use crate::header::Header;
+use crate::multipart::Multipart;
 use crate::stream::Stream;
+use std::cell::RefCell;
 use std::fmt;
-use std::io::{self, copy, empty, Cursor, Read, Write};
+use std::io::{self, copy, empty, Cursor, Read, Seek, SeekFrom, Write};
+use std::rc::Rc;
 
 #[cfg(feature = "charset")]
 use crate::response::DEFAULT_CHARACTER_SET;
 #[cfg(feature = "charset")]
 use encoding_rs::Encoding;
+#[cfg(feature = "charset")]
+use std::borrow::Cow;
+
+#[cfg(feature = "brotli")]
+use brotli::CompressorReader;
+#[cfg(feature = "gzip")]
+use flate2::read::GzEncoder;
 
 /// The different kinds of bodies to send.
 ///
@@ -14,16 +25,40 @@ pub(crate) enum Payload<'a> {
     Empty,
     Text(&'a str, String),
     Reader(Box<dyn Read + 'a>),
+    ReaderWithTrailers(Box<dyn Read + 'a>, TrailersFn<'a>),
     Bytes(&'a [u8]),
+    Multipart(Multipart<'a>),
+    Seekable(SeekBody<'a>),
 }
 
+/// Produces the trailer fields of a request, once its body has been sent.
+///
+/// *Internal API*
+pub(crate) type TrailersFn<'a> = Box<dyn FnOnce() -> Vec<Header> + 'a>;
+
 impl fmt::Debug for Payload<'_> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Payload::Empty => write!(f, "Empty"),
             Payload::Text(t, _) => write!(f, "{}", t),
             Payload::Reader(_) => write!(f, "Reader"),
+            Payload::ReaderWithTrailers(_, _) => write!(f, "ReaderWithTrailers"),
             Payload::Bytes(v) => write!(f, "{:?}", v),
+            Payload::Multipart(m) => write!(f, "{:?}", m),
+            Payload::Seekable(b) => write!(f, "Seekable[size={}]", b.size),
+        }
+    }
+}
+
+impl<'a> Payload<'a> {
+    /// A copy of this payload to send again, if it is buffered or seekable.
+    pub fn try_clone(&self) -> Option<Payload<'a>> {
+        match self {
+            Payload::Empty => Some(Payload::Empty),
+            Payload::Text(t, charset) => Some(Payload::Text(t, charset.clone())),
+            Payload::Bytes(v) => Some(Payload::Bytes(v)),
+            Payload::Seekable(b) => Some(Payload::Seekable(b.clone())),
+            Payload::Reader(_) | Payload::ReaderWithTrailers(_, _) | Payload::Multipart(_) => None,
         }
     }
 }
@@ -51,6 +86,9 @@ pub(crate) enum BodySize {
 pub(crate) struct SizedReader<'a> {
     pub size: BodySize,
     pub reader: Box<dyn Read + 'a>,
+    pub trailers: Option<TrailersFn<'a>>,
+    /// Where to read the body from again, if it can be sent more than once.
+    pub replay: Option<Replay<'a>>,
 }
 
 impl fmt::Debug for SizedReader<'_> {
@@ -61,7 +99,122 @@ impl fmt::Debug for SizedReader<'_> {
 
 impl<'a> SizedReader<'a> {
     fn new(size: BodySize, reader: Box<dyn Read + 'a>) -> Self {
-        SizedReader { size, reader }
+        SizedReader {
+            size,
+            reader,
+            trailers: None,
+            replay: None,
+        }
+    }
+
+    fn buffered(bytes: Buffered<'a>) -> Self {
+        let len = bytes.as_ref().len() as u64;
+        SizedReader {
+            replay: Some(Replay::Bytes(bytes.clone())),
+            ..SizedReader::new(BodySize::Known(len), Box::new(Cursor::new(bytes)))
+        }
+    }
+
+    fn seekable(body: SeekBody<'a>) -> Self {
+        let reader = SeekRead {
+            body: body.clone(),
+            rewound: false,
+        };
+        SizedReader {
+            replay: Some(Replay::Seek(body.clone())),
+            ..SizedReader::new(BodySize::Known(body.size), Box::new(reader))
+        }
+    }
+
+    /// A reader of the same body from the start, to send it again.
+    ///
+    /// `None` for bodies read from a stream, which can only be sent once.
+    pub fn try_clone(&self) -> Option<SizedReader<'a>> {
+        match &self.replay {
+            Some(Replay::Bytes(bytes)) => Some(SizedReader::buffered(bytes.clone())),
+            Some(Replay::Seek(body)) => Some(SizedReader::seekable(body.clone())),
+            None if matches!(self.size, BodySize::Empty) => Some(Payload::Empty.into_read()),
+            None => None,
+        }
+    }
+}
+
+/// The source of a body that can be sent more than once.
+///
+/// *Internal API*
+#[derive(Clone)]
+pub(crate) enum Replay<'a> {
+    Bytes(Buffered<'a>),
+    Seek(SeekBody<'a>),
+}
+
+/// Body bytes held in memory.
+///
+/// *Internal API*
+#[derive(Clone)]
+pub(crate) enum Buffered<'a> {
+    Borrowed(&'a [u8]),
+    #[cfg_attr(
+        not(any(feature = "charset", feature = "gzip", feature = "brotli")),
+        allow(dead_code)
+    )]
+    Shared(Rc<[u8]>),
+}
+
+impl AsRef<[u8]> for Buffered<'_> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Buffered::Borrowed(bytes) => bytes,
+            Buffered::Shared(bytes) => bytes,
+        }
+    }
+}
+
+/// A reader that can seek, see [`Request::send_seekable()`](crate::Request::send_seekable).
+///
+/// *Internal API*
+pub(crate) trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// A seekable body, shared by all the attempts to send it.
+///
+/// *Internal API*
+#[derive(Clone)]
+pub(crate) struct SeekBody<'a> {
+    reader: Rc<RefCell<dyn ReadSeek + 'a>>,
+    start: u64,
+    size: u64,
+}
+
+impl<'a> SeekBody<'a> {
+    /// The body from the current position of `reader` to its end.
+    pub fn new(mut reader: impl ReadSeek + 'a) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        let end = reader.seek(SeekFrom::End(0))?;
+        reader.seek(SeekFrom::Start(start))?;
+        Ok(SeekBody {
+            reader: Rc::new(RefCell::new(reader)),
+            start,
+            size: end.saturating_sub(start),
+        })
+    }
+}
+
+/// Reads a [`SeekBody`], after seeking back to its start.
+struct SeekRead<'a> {
+    body: SeekBody<'a>,
+    rewound: bool,
+}
+
+impl Read for SeekRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut reader = self.body.reader.borrow_mut();
+        if !self.rewound {
+            reader.seek(SeekFrom::Start(self.body.start))?;
+            self.rewound = true;
+        }
+        reader.read(buf)
     }
 }
 
@@ -75,24 +228,109 @@ impl<'a> Payload<'a> {
                     let encoding = Encoding::for_label(_charset.as_bytes())
                         .or_else(|| Encoding::for_label(DEFAULT_CHARACTER_SET.as_bytes()))
                         .unwrap();
-                    encoding.encode(text).0
+                    match encoding.encode(text).0 {
+                        Cow::Borrowed(bytes) => Buffered::Borrowed(bytes),
+                        Cow::Owned(bytes) => Buffered::Shared(bytes.into()),
+                    }
                 };
                 #[cfg(not(feature = "charset"))]
-                let bytes = text.as_bytes();
-                let len = bytes.len();
-                let cursor = Cursor::new(bytes);
-                SizedReader::new(BodySize::Known(len as u64), Box::new(cursor))
+                let bytes = Buffered::Borrowed(text.as_bytes());
+                SizedReader::buffered(bytes)
             }
             Payload::Reader(read) => SizedReader::new(BodySize::Unknown, read),
-            Payload::Bytes(bytes) => {
-                let len = bytes.len();
-                let cursor = Cursor::new(bytes);
-                SizedReader::new(BodySize::Known(len as u64), Box::new(cursor))
+            Payload::ReaderWithTrailers(read, trailers) => SizedReader {
+                trailers: Some(trailers),
+                ..SizedReader::new(BodySize::Unknown, read)
+            },
+            Payload::Bytes(bytes) => SizedReader::buffered(Buffered::Borrowed(bytes)),
+            Payload::Multipart(multipart) => match multipart.into_reader() {
+                (Some(size), reader) => SizedReader::new(BodySize::Known(size), reader),
+                (None, reader) => SizedReader::new(BodySize::Unknown, reader),
+            },
+            Payload::Seekable(body) => SizedReader::seekable(body),
+        }
+    }
+}
+
+/// Compression that can be applied to request bodies.
+///
+/// See [`Request::compress()`](crate::Request::compress) and
+/// [`AgentBuilder::compress()`](crate::AgentBuilder::compress).
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ContentEncoding {
+    /// `Content-Encoding: gzip`
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// `Content-Encoding: br`
+    #[cfg(feature = "brotli")]
+    Brotli,
+}
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+impl ContentEncoding {
+    fn header_value(self) -> &'static str {
+        match self {
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => "gzip",
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => "br",
+        }
+    }
+
+    fn wrap_reader<'a>(self, reader: Box<dyn Read + 'a>) -> Box<dyn Read + 'a> {
+        match self {
+            #[cfg(feature = "gzip")]
+            ContentEncoding::Gzip => {
+                Box::new(GzEncoder::new(reader, flate2::Compression::default()))
             }
+            // Quality 5 of 11, the highest ones are too slow to compress on the fly.
+            #[cfg(feature = "brotli")]
+            ContentEncoding::Brotli => Box::new(CompressorReader::new(reader, 4096, 5, 22)),
         }
     }
 }
 
+/// Compress a request body and set `Content-Encoding` accordingly.
+///
+/// Bodies already in memory are compressed up front, so they are still sent with a
+/// Content-Length. Other bodies, which may be large files, are compressed as they are
+/// sent, using the chunked transfer encoding. Empty bodies, and bodies that already have a `Content-Encoding`, are left alone.
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub(crate) fn compress<'a>(
+    body: SizedReader<'a>,
+    encoding: ContentEncoding,
+    headers: &mut Vec<Header>,
+) -> io::Result<SizedReader<'a>> {
+    if matches!(body.size, BodySize::Empty | BodySize::Known(0))
+        || headers.iter().any(|h| h.is_name("content-encoding"))
+    {
+        return Ok(body);
+    }
+
+    // Any Content-Length set by the user is the length before compression.
+    headers.retain(|h| !h.is_name("content-length"));
+    headers.push(Header::new("Content-Encoding", encoding.header_value()));
+
+    let in_memory = matches!(body.replay, Some(Replay::Bytes(_)));
+    let mut reader = encoding.wrap_reader(body.reader);
+    Ok(match body.size {
+        BodySize::Known(_) if in_memory => {
+            let mut buf = vec![];
+            reader.read_to_end(&mut buf)?;
+            SizedReader {
+                trailers: body.trailers,
+                ..SizedReader::buffered(Buffered::Shared(buf.into()))
+            }
+        }
+        _ => SizedReader {
+            trailers: body.trailers,
+            ..SizedReader::new(BodySize::Unknown, reader)
+        },
+    })
+}
+
 const CHUNK_MAX_SIZE: usize = 0x4000; // Maximum size of a TLS fragment
 const CHUNK_HEADER_MAX_SIZE: usize = 6; // four hex digits plus "\r\n"
 const CHUNK_FOOTER_SIZE: usize = 2; // "\r\n"
@@ -104,7 +342,12 @@ const CHUNK_MAX_PAYLOAD_SIZE: usize = CHUNK_MAX_SIZE - CHUNK_HEADER_MAX_SIZE - C
 // 2) chunked_transfer's Encoder issues 4 separate write() per chunk. This is costly
 //    overhead. Instead, we do a single write() per chunk.
 // The measured benefit on a Linux machine is a 50% reduction in CPU usage on a https connection.
-fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<u64> {
+fn copy_chunked<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    trailers: Option<TrailersFn>,
+) -> io::Result<u64> {
+    let mut trailers = trailers;
     // The chunk layout is:
     // header:header_max_size | payload:max_payload_size | footer:footer_size
     let mut chunk = Vec::with_capacity(CHUNK_MAX_SIZE);
@@ -123,6 +366,21 @@ fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> io::Result
         let start_index = CHUNK_HEADER_MAX_SIZE - header.len();
         (&mut chunk[start_index..]).write_all(header).unwrap();
 
+        // The last chunk is followed by the trailer fields, if any.
+        if payload_size == 0 {
+            if let Some(trailers) = trailers.take() {
+                for header in trailers() {
+                    header
+                        .validate()
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+                    chunk.extend_from_slice(header.name().as_bytes());
+                    chunk.extend_from_slice(b": ");
+                    chunk.extend_from_slice(header.value_raw());
+                    chunk.extend_from_slice(b"\r\n");
+                }
+            }
+        }
+
         // And add the footer
         chunk.extend_from_slice(b"\r\n");
 
@@ -144,8 +402,14 @@ pub(crate) fn send_body(
     stream: &mut Stream,
 ) -> io::Result<()> {
     if do_chunk {
-        copy_chunked(&mut body.reader, stream)?;
+        copy_chunked(&mut body.reader, stream, body.trailers)?;
     } else {
+        if body.trailers.is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "trailers are only sent with a chunked body",
+            ));
+        }
         copy(&mut body.reader, stream)?;
     };
 
@@ -163,7 +427,7 @@ mod tests {
         source.extend_from_slice(b"hello world");
 
         let mut dest = Vec::<u8>::new();
-        copy_chunked(&mut &source[..], &mut dest).unwrap();
+        copy_chunked(&mut &source[..], &mut dest, None).unwrap();
 
         let mut dest_expected = Vec::<u8>::new();
         dest_expected.extend_from_slice(format!("{:x}\r\n", CHUNK_MAX_PAYLOAD_SIZE).as_bytes());
@@ -175,4 +439,40 @@ mod tests {
 
         assert_eq!(dest, dest_expected);
     }
+
+    #[test]
+    fn seekable_body_replays() {
+        let mut reader = Cursor::new(b"skip data".to_vec());
+        reader.set_position(5);
+        let mut first = Payload::Seekable(SeekBody::new(reader).unwrap()).into_read();
+        assert!(matches!(first.size, BodySize::Known(4)));
+        let mut replay = first.try_clone().unwrap();
+
+        let mut body = String::new();
+        first.reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "data");
+        body.clear();
+        replay.reader.read_to_string(&mut body).unwrap();
+        assert_eq!(body, "data");
+    }
+
+    #[test]
+    fn streamed_body_does_not_replay() {
+        let body = Payload::Reader(Box::new(&b"data"[..])).into_read();
+        assert!(body.try_clone().is_none());
+        let body = Payload::Bytes(b"data").into_read();
+        assert!(body.try_clone().is_some());
+    }
+
+    #[test]
+    fn test_copy_chunked_trailers() {
+        let mut dest = Vec::<u8>::new();
+        let trailers: TrailersFn = Box::new(|| vec![Header::new("X-Checksum", "abc")]);
+        copy_chunked(&mut &b"hello"[..], &mut dest, Some(trailers)).unwrap();
+        assert_eq!(dest, b"5\r\nhello\r\n0\r\nX-Checksum: abc\r\n\r\n");
+
+        let trailers: TrailersFn = Box::new(|| vec![Header::new("X-Bad", "a\r\nb")]);
+        let err = copy_chunked(&mut &b""[..], &mut dest, Some(trailers)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
 }
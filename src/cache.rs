@@ -0,0 +1,827 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Cursor, Read};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::debug;
+use url::Url;
+
+use crate::error::Error;
+use crate::header::{get_all_headers, get_header, parse_http_date, Header, HeaderLine};
+use crate::middleware::{Middleware, MiddlewareNext};
+use crate::request::Request;
+use crate::response::Response;
+
+/// The largest body stored by default, 10 megabytes.
+const DEFAULT_MAX_ENTRY_SIZE: u64 = 10 * 1024 * 1024;
+
+/// The longest heuristic freshness lifetime, for responses without an explicit one.
+const MAX_HEURISTIC_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Statuses that can be cached without explicit freshness information,
+/// <https://www.rfc-editor.org/rfc/rfc9110#section-15.1>
+const HEURISTIC_STATUSES: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// Header fields that only concern a single connection, and are not stored.
+const HOP_BY_HOP: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// An HTTP cache, <https://www.rfc-editor.org/rfc/rfc9111>
+///
+/// The cache is a [`Middleware`]: add it to an agent with
+/// [`AgentBuilder::middleware()`](crate::AgentBuilder::middleware), and `GET` responses are
+/// stored according to their `Cache-Control`, `Expires` and `Vary` header fields. Fresh
+/// responses are served from the cache without a request. Stale responses are revalidated
+/// with `If-None-Match` and `If-Modified-Since`, and a `304 Not Modified` answer is turned
+/// into the full cached response. Responses without explicit freshness get a heuristic
+/// lifetime of 10% of the time since they were last modified, at most one day.
+///
+/// This is a private cache, as kept by a browser: responses marked `private` are stored too.
+/// Requests with a `Range` header or conditional headers of their own are passed through,
+/// and a successful `POST`, `PUT`, `PATCH` or `DELETE` removes the stored response for its URL.
+///
+/// Responses are stored once their body has been read to the end, without delaying the
+/// response, so streamed bodies are passed on as they arrive. Only responses that can be
+/// served or revalidated later are stored: those with freshness information, an `ETag` or
+/// a `Last-Modified`.
+///
+/// Bodies are stored decoded, so a response from the cache has no `Content-Encoding`. Its
+/// remote and local addresses are unknown, and reported as `0.0.0.0:0`.
+///
+/// ```no_run
+/// # fn main() -> Result<(), ureq::Error> {
+/// use ureq::{Cache, MemoryStorage};
+///
+/// let agent = ureq::builder()
+///     .middleware(Cache::new(MemoryStorage::new(1000)))
+///     .build();
+///
+/// let resp = agent.get("http://httpbin.org/cache/60").call()?;
+/// // Served from the cache.
+/// let resp = agent.get("http://httpbin.org/cache/60").call()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Cache {
+    storage: Arc<dyn CacheStorage>,
+    max_entry_size: u64,
+}
+
+impl Cache {
+    /// A cache keeping its responses in `storage`.
+    pub fn new(storage: impl CacheStorage) -> Self {
+        Cache {
+            storage: Arc::new(storage),
+            max_entry_size: DEFAULT_MAX_ENTRY_SIZE,
+        }
+    }
+
+    /// The largest response body to store, in bytes. Defaults to 10 megabytes.
+    ///
+    /// Larger responses are passed on without being stored.
+    pub fn max_entry_size(mut self, bytes: u64) -> Self {
+        self.max_entry_size = bytes;
+        self
+    }
+
+    /// Store `response` once its body has been read to the end, unless the body is too
+    /// large.
+    fn store(
+        &self,
+        key: &str,
+        request: &Request,
+        mut response: Response,
+        request_time: SystemTime,
+    ) -> Response {
+        let mut headers = response.headers.clone();
+        headers.retain(|h| !HOP_BY_HOP.iter().any(|name| h.is_name(name)));
+        let vary = get_all_headers(&headers, "vary")
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .flat_map(|name| {
+                request
+                    .all(name)
+                    .into_iter()
+                    .map(move |v| Header::new(name, v))
+            })
+            .collect();
+        let entry = CacheEntry {
+            url: response.get_url().to_string(),
+            status_line: response.status_line.clone(),
+            headers,
+            vary,
+            body: Arc::new([]),
+            request_time,
+            response_time: SystemTime::now(),
+        };
+        let reader = std::mem::replace(&mut response.reader, Box::new(io::empty()));
+        response.reader = Box::new(StoringRead {
+            reader,
+            body: vec![],
+            max_size: self.max_entry_size,
+            pending: Some((key.to_string(), entry, self.storage.clone())),
+        });
+        response
+    }
+}
+
+/// Passes on a response body, and stores the response once the body is read to the end.
+struct StoringRead {
+    reader: Box<dyn Read + Send + Sync + 'static>,
+    body: Vec<u8>,
+    max_size: u64,
+    pending: Option<(String, CacheEntry, Arc<dyn CacheStorage>)>,
+}
+
+impl Read for StoringRead {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.reader.read(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                self.pending = None;
+                return Err(e);
+            }
+        };
+        if let Some((key, _, _)) = &self.pending {
+            if n == 0 {
+                let (key, mut entry, storage) = self.pending.take().unwrap();
+                entry.body = std::mem::take(&mut self.body).into();
+                debug!("caching {}", key);
+                storage.put(&key, entry);
+            } else if (self.body.len() + n) as u64 > self.max_size {
+                debug!("not caching {}: body too large", key);
+                self.pending = None;
+                self.body = vec![];
+            } else {
+                self.body.extend_from_slice(&buf[..n]);
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("max_entry_size", &self.max_entry_size)
+            .finish()
+    }
+}
+
+impl Middleware for Cache {
+    fn handle(&self, request: Request, next: MiddlewareNext) -> Result<Response, Error> {
+        let key = match request.request_url() {
+            Ok(url) => cache_key(url.as_url()),
+            Err(_) => return next.handle(request),
+        };
+
+        let method = request.method().to_ascii_uppercase();
+        if method != "GET" {
+            let response = next.handle(request)?;
+            // https://www.rfc-editor.org/rfc/rfc9111#section-4.4
+            let safe = matches!(&method[..], "HEAD" | "OPTIONS" | "TRACE");
+            if !safe && (200..400).contains(&response.status()) {
+                debug!("{} invalidates cached {}", method, key);
+                self.storage.remove(&key);
+            }
+            return Ok(response);
+        }
+
+        let request_directives = CacheControl::new(&request.all("cache-control"));
+        let conditional = ["if-none-match", "if-modified-since", "if-match", "range"];
+        if request_directives.has("no-store") || conditional.iter().any(|h| request.has(h)) {
+            return next.handle(request);
+        }
+
+        let request_time = SystemTime::now();
+        let stored = self
+            .storage
+            .get(&key)
+            .filter(|entry| entry.matches(&request));
+        let mut conditional_request = request.clone();
+        if let Some(entry) = &stored {
+            let age = entry.current_age(request_time);
+            if entry.is_fresh(age, &request_directives) {
+                debug!("cache hit {}", key);
+                return entry.to_response(age);
+            }
+            if let Some(etag) = entry.header("etag") {
+                conditional_request = conditional_request.set("If-None-Match", etag);
+            }
+            if let Some(last_modified) = entry.header("last-modified") {
+                conditional_request = conditional_request.set("If-Modified-Since", last_modified);
+            }
+            debug!("revalidating cached {}", key);
+        }
+
+        let response = next.handle(conditional_request)?;
+
+        if let (304, Some(mut entry)) = (response.status(), stored) {
+            entry.refresh(&response, request_time);
+            let age = entry.current_age(SystemTime::now());
+            let refreshed = entry.to_response(age);
+            self.storage.put(&key, entry);
+            return refreshed;
+        }
+
+        if is_storable(&response) {
+            Ok(self.store(&key, &request, response, request_time))
+        } else {
+            Ok(response)
+        }
+    }
+}
+
+/// The key of the responses for `url`.
+fn cache_key(url: &Url) -> String {
+    let mut url = url.clone();
+    url.set_fragment(None);
+    url.to_string()
+}
+
+/// Whether a response to a `GET` can be stored, <https://www.rfc-editor.org/rfc/rfc9111#section-3>
+fn is_storable(response: &Response) -> bool {
+    let status = response.status();
+    if status < 200 || status == 206 || status == 304 {
+        return false;
+    }
+    let directives = CacheControl::new(&response.all("cache-control"));
+    if directives.has("no-store") {
+        return false;
+    }
+    if response
+        .all("vary")
+        .iter()
+        .any(|v| v.split(',').any(|name| name.trim() == "*"))
+    {
+        return false;
+    }
+    let explicit = directives.has("max-age") || response.has("expires");
+    if !explicit && !HEURISTIC_STATUSES.contains(&status) && !directives.has("public") {
+        return false;
+    }
+    // Without freshness information or a validator, a stored response could never be
+    // used. Last-Modified is both the validator and what heuristic freshness is based on.
+    explicit || response.has("etag") || response.has("last-modified")
+}
+
+/// Where a [`Cache`] keeps its responses.
+///
+/// Storage errors are not errors of the request, so implementations should log them and
+/// carry on, treating a failed `get()` as a miss.
+pub trait CacheStorage: Send + Sync + 'static {
+    /// The response stored under `key`, if any.
+    fn get(&self, key: &str) -> Option<CacheEntry>;
+    /// Store `entry` under `key`, replacing any previous entry.
+    fn put(&self, key: &str, entry: CacheEntry);
+    /// Remove the entry stored under `key`.
+    fn remove(&self, key: &str);
+}
+
+/// A response stored in a [`Cache`].
+///
+/// Use [`to_bytes()`](CacheEntry::to_bytes) and [`from_bytes()`](CacheEntry::from_bytes)
+/// to keep entries outside of memory.
+#[derive(Clone)]
+pub struct CacheEntry {
+    url: String,
+    status_line: String,
+    headers: Vec<Header>,
+    /// The request header fields selected by the `Vary` response header.
+    vary: Vec<Header>,
+    body: Arc<[u8]>,
+    request_time: SystemTime,
+    response_time: SystemTime,
+}
+
+impl CacheEntry {
+    /// The URL of the response.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// The size of the body, in bytes.
+    pub fn body_len(&self) -> usize {
+        self.body.len()
+    }
+
+    /// Encode the entry, to store it.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = format!(
+            "{}\r\n{} {}\r\n",
+            self.url,
+            unix_millis(self.request_time),
+            unix_millis(self.response_time)
+        )
+        .into_bytes();
+        write_headers(&mut bytes, &self.vary);
+        bytes.extend_from_slice(self.status_line.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+        write_headers(&mut bytes, &self.headers);
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+
+    /// Decode an entry encoded by [`to_bytes()`](CacheEntry::to_bytes).
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<CacheEntry> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cache entry");
+        let mut rest = bytes;
+        let mut next_line = || -> io::Result<&[u8]> {
+            let end = rest
+                .windows(2)
+                .position(|w| w == b"\r\n")
+                .ok_or_else(invalid)?;
+            let line = &rest[..end];
+            rest = &rest[end + 2..];
+            Ok(line)
+        };
+        let text = |line: &[u8]| String::from_utf8(line.to_vec()).map_err(|_| invalid());
+
+        let url = text(next_line()?)?;
+        let times = text(next_line()?)?;
+        let mut times = times.split(' ').map(|t| t.parse::<u64>().ok());
+        let (request_time, response_time) = match (times.next(), times.next()) {
+            (Some(Some(request)), Some(Some(response))) => (
+                UNIX_EPOCH + Duration::from_millis(request),
+                UNIX_EPOCH + Duration::from_millis(response),
+            ),
+            _ => return Err(invalid()),
+        };
+
+        let mut vary = vec![];
+        loop {
+            let line = next_line()?;
+            if line.is_empty() {
+                break;
+            }
+            vary.push(parse_header(line).ok_or_else(invalid)?);
+        }
+        let status_line = text(next_line()?)?;
+        let mut headers = vec![];
+        loop {
+            let line = next_line()?;
+            if line.is_empty() {
+                break;
+            }
+            headers.push(parse_header(line).ok_or_else(invalid)?);
+        }
+        Ok(CacheEntry {
+            url,
+            status_line,
+            headers,
+            vary,
+            body: rest.into(),
+            request_time,
+            response_time,
+        })
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        get_header(&self.headers, name)
+    }
+
+    /// Whether the request header fields selected by `Vary` match those of `request`.
+    fn matches(&self, request: &Request) -> bool {
+        get_all_headers(&self.headers, "vary")
+            .iter()
+            .flat_map(|v| v.split(','))
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .all(|name| {
+                let stored = get_all_headers(&self.vary, name);
+                stored == request.all(name)
+            })
+    }
+
+    /// The age of the response, <https://www.rfc-editor.org/rfc/rfc9111#section-4.2.3>
+    fn current_age(&self, now: SystemTime) -> Duration {
+        let since = |later: SystemTime, earlier: SystemTime| {
+            later.duration_since(earlier).unwrap_or(Duration::ZERO)
+        };
+        let date = self.date();
+        let age_value = self
+            .header("age")
+            .and_then(|v| v.trim().parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::ZERO);
+        let apparent_age = since(self.response_time, date);
+        let response_delay = since(self.response_time, self.request_time);
+        let corrected_age_value = age_value + response_delay;
+        let corrected_initial_age = apparent_age.max(corrected_age_value);
+        let resident_time = since(now, self.response_time);
+        corrected_initial_age + resident_time
+    }
+
+    /// The `Date` of the response, or when it was received.
+    fn date(&self) -> SystemTime {
+        self.header("date")
+            .and_then(parse_http_date)
+            .unwrap_or(self.response_time)
+    }
+
+    /// How long the response is fresh, <https://www.rfc-editor.org/rfc/rfc9111#section-4.2.1>
+    fn freshness_lifetime(&self) -> Duration {
+        let directives = CacheControl::new(&get_all_headers(&self.headers, "cache-control"));
+        if let Some(max_age) = directives.seconds("max-age") {
+            return max_age;
+        }
+        if let Some(expires) = self.header("expires") {
+            // An invalid date means the response is already expired.
+            return parse_http_date(expires)
+                .and_then(|expires| expires.duration_since(self.date()).ok())
+                .unwrap_or(Duration::ZERO);
+        }
+        let status = self
+            .status_line
+            .split(' ')
+            .nth(1)
+            .and_then(|s| s.parse().ok());
+        let last_modified = self.header("last-modified").and_then(parse_http_date);
+        match (status, last_modified) {
+            (Some(status), Some(last_modified)) if HEURISTIC_STATUSES.contains(&status) => self
+                .date()
+                .duration_since(last_modified)
+                .map(|d| (d / 10).min(MAX_HEURISTIC_LIFETIME))
+                .unwrap_or(Duration::ZERO),
+            _ => Duration::ZERO,
+        }
+    }
+
+    /// Whether the response can be used without revalidating it.
+    fn is_fresh(&self, age: Duration, request_directives: &CacheControl) -> bool {
+        let directives = CacheControl::new(&get_all_headers(&self.headers, "cache-control"));
+        if directives.has("no-cache") || request_directives.has("no-cache") {
+            return false;
+        }
+        if let Some(max_age) = request_directives.seconds("max-age") {
+            if age > max_age {
+                return false;
+            }
+        }
+        self.freshness_lifetime() > age
+    }
+
+    /// Update the entry with a `304 Not Modified` response,
+    /// <https://www.rfc-editor.org/rfc/rfc9111#section-4.3.4>
+    fn refresh(&mut self, response: &Response, request_time: SystemTime) {
+        let skip = |h: &Header| {
+            h.is_name("content-length") || HOP_BY_HOP.iter().any(|name| h.is_name(name))
+        };
+        let updated: Vec<_> = response.headers.iter().filter(|h| !skip(h)).collect();
+        self.headers
+            .retain(|h| !updated.iter().any(|u| u.is_name(h.name())));
+        self.headers.extend(updated.into_iter().cloned());
+        self.request_time = request_time;
+        self.response_time = SystemTime::now();
+    }
+
+    /// A response with the stored status, header fields and body.
+    fn to_response(&self, age: Duration) -> Result<Response, Error> {
+        let url: Url = self.url.parse().map_err(|e| {
+            crate::ErrorKind::InvalidUrl
+                .msg(format!("cached url {}", self.url))
+                .src(e)
+        })?;
+        let mut headers = self.headers.clone();
+        headers.retain(|h| !h.is_name("age"));
+        headers.push(Header::new("Age", &age.as_secs().to_string()));
+        let body = Box::new(Cursor::new(self.body.clone()));
+        Response::from_cache(url, self.status_line.clone(), headers, body)
+    }
+}
+
+impl fmt::Debug for CacheEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CacheEntry")
+            .field("url", &self.url)
+            .field("status_line", &self.status_line)
+            .field("headers", &self.headers)
+            .field("body_len", &self.body.len())
+            .finish()
+    }
+}
+
+/// Write header fields, followed by a blank line.
+fn write_headers(bytes: &mut Vec<u8>, headers: &[Header]) {
+    for header in headers {
+        bytes.extend_from_slice(header.name().as_bytes());
+        bytes.extend_from_slice(b": ");
+        bytes.extend_from_slice(header.value_raw());
+        bytes.extend_from_slice(b"\r\n");
+    }
+    bytes.extend_from_slice(b"\r\n");
+}
+
+fn parse_header(line: &[u8]) -> Option<Header> {
+    HeaderLine::from(line.to_vec()).into_header().ok()
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// The directives of `Cache-Control` header fields.
+struct CacheControl(Vec<(String, Option<String>)>);
+
+impl CacheControl {
+    fn new(values: &[&str]) -> Self {
+        let directives = values
+            .iter()
+            .flat_map(|v| v.split(','))
+            .filter_map(|directive| {
+                let mut parts = directive.splitn(2, '=');
+                let name = parts.next()?.trim().to_ascii_lowercase();
+                let value = parts.next().map(|v| v.trim().trim_matches('"').to_string());
+                Some((name, value)).filter(|(name, _)| !name.is_empty())
+            })
+            .collect();
+        CacheControl(directives)
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.0.iter().any(|(n, _)| n == name)
+    }
+
+    fn seconds(&self, name: &str) -> Option<Duration> {
+        self.0
+            .iter()
+            .find(|(n, _)| n == name)
+            .and_then(|(_, value)| value.as_ref()?.parse().ok())
+            .map(Duration::from_secs)
+    }
+}
+
+/// A [`CacheStorage`] in memory.
+///
+/// Once full, the oldest entry is evicted to make room for a new one.
+pub struct MemoryStorage {
+    entries: Mutex<HashMap<String, (u64, CacheEntry)>>,
+    max_entries: usize,
+    counter: AtomicU64,
+}
+
+impl MemoryStorage {
+    /// A storage of at most `max_entries` responses.
+    pub fn new(max_entries: usize) -> Self {
+        MemoryStorage {
+            entries: Mutex::new(HashMap::new()),
+            max_entries,
+            counter: AtomicU64::new(0),
+        }
+    }
+}
+
+impl fmt::Debug for MemoryStorage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryStorage")
+            .field("len", &self.entries.lock().unwrap().len())
+            .field("max_entries", &self.max_entries)
+            .finish()
+    }
+}
+
+impl CacheStorage for MemoryStorage {
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(key).map(|(_, entry)| entry.clone())
+    }
+
+    fn put(&self, key: &str, entry: CacheEntry) {
+        if self.max_entries == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(key) && entries.len() >= self.max_entries {
+            let oldest = entries
+                .iter()
+                .min_by_key(|(_, (n, _))| *n)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        entries.insert(key.to_string(), (n, entry));
+    }
+
+    fn remove(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// A [`CacheStorage`] on disk, with a file per response.
+///
+/// Entries survive the process, and can be shared between agents using the same
+/// directory. Nothing is evicted: remove old files to limit the size of the directory.
+#[derive(Debug)]
+pub struct DiskStorage {
+    dir: PathBuf,
+}
+
+impl DiskStorage {
+    /// A storage in directory `dir`, which is created if missing.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(DiskStorage { dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // FNV-1a, which unlike the std hashers is stable across releases.
+        let hash = key.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| {
+            (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3)
+        });
+        self.dir.join(format!("{:016x}.cache", hash))
+    }
+}
+
+impl CacheStorage for DiskStorage {
+    fn get(&self, key: &str) -> Option<CacheEntry> {
+        let bytes = match fs::read(self.path(key)) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                debug!("reading cache entry for {}: {}", key, e);
+                return None;
+            }
+        };
+        // The file starts with the key, to tell apart keys with the same hash.
+        let line = format!("{}\r\n", key);
+        let entry = bytes.strip_prefix(line.as_bytes())?;
+        match CacheEntry::from_bytes(entry) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                debug!("reading cache entry for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    fn put(&self, key: &str, entry: CacheEntry) {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(key);
+        // Write to a temporary file first, so readers never see a partial entry.
+        let tmp = path.with_extension(format!(
+            "tmp{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let mut bytes = format!("{}\r\n", key).into_bytes();
+        bytes.extend_from_slice(&entry.to_bytes());
+        let result = fs::write(&tmp, bytes).and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = result {
+            debug!("writing cache entry for {}: {}", key, e);
+            fs::remove_file(&tmp).ok();
+        }
+    }
+
+    fn remove(&self, key: &str) {
+        if let Err(e) = fs::remove_file(self.path(key)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                debug!("removing cache entry for {}: {}", key, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(headers: &[&str], age: Duration) -> CacheEntry {
+        let now = SystemTime::now();
+        CacheEntry {
+            url: "http://example.com/".to_string(),
+            status_line: "HTTP/1.1 200 OK".to_string(),
+            headers: headers.iter().map(|h| h.parse().unwrap()).collect(),
+            vary: vec![],
+            body: b"body"[..].into(),
+            request_time: now - age,
+            response_time: now - age,
+        }
+    }
+
+    fn fresh(entry: &CacheEntry, request_directives: &[&str]) -> bool {
+        let age = entry.current_age(SystemTime::now());
+        entry.is_fresh(age, &CacheControl::new(request_directives))
+    }
+
+    fn http_date(time: SystemTime) -> String {
+        const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let secs = time.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        let days = secs / 86_400;
+        // civil_from_days, <http://howardhinnant.github.io/date_algorithms.html>
+        let z = days + 719_468;
+        let era = z / 146_097;
+        let doe = z - era * 146_097;
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + u64::from(month <= 2);
+        format!(
+            "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+            DAYS[(days % 7) as usize],
+            day,
+            MONTHS[month as usize - 1],
+            year,
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60
+        )
+    }
+
+    #[test]
+    fn max_age() {
+        let e = entry(&["Cache-Control: max-age=60"], Duration::from_secs(30));
+        assert!(fresh(&e, &[]));
+        assert!(!fresh(&e, &["max-age=10"]));
+        assert!(!fresh(&e, &["no-cache"]));
+        let e = entry(
+            &["Cache-Control: max-age=60", "Age: 40"],
+            Duration::from_secs(30),
+        );
+        assert!(!fresh(&e, &[]));
+        let e = entry(&["Cache-Control: max-age=60, no-cache"], Duration::ZERO);
+        assert!(!fresh(&e, &[]));
+    }
+
+    #[test]
+    fn expires() {
+        let now = SystemTime::now();
+        let expires = format!("Expires: {}", http_date(now + Duration::from_secs(120)));
+        let date = format!("Date: {}", http_date(now));
+        let e = entry(&[&date, &expires], Duration::ZERO);
+        assert!(fresh(&e, &[]));
+        let e = entry(&[&date, "Expires: 0"], Duration::ZERO);
+        assert!(!fresh(&e, &[]));
+    }
+
+    #[test]
+    fn heuristic_freshness() {
+        let now = SystemTime::now();
+        let last_modified = http_date(now - Duration::from_secs(1000));
+        let e = entry(
+            &[&format!("Last-Modified: {}", last_modified)],
+            Duration::from_secs(50),
+        );
+        assert!(fresh(&e, &[]));
+        let e = entry(
+            &[&format!("Last-Modified: {}", last_modified)],
+            Duration::from_secs(150),
+        );
+        assert!(!fresh(&e, &[]));
+        let e = entry(&[], Duration::ZERO);
+        assert!(!fresh(&e, &[]));
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let mut e = entry(
+            &["Cache-Control: max-age=60", "Vary: Accept"],
+            Duration::ZERO,
+        );
+        e.vary = vec![Header::new("Accept", "text/plain")];
+        let decoded = CacheEntry::from_bytes(&e.to_bytes()).unwrap();
+        assert_eq!(decoded.url, e.url);
+        assert_eq!(decoded.status_line, e.status_line);
+        assert_eq!(decoded.headers, e.headers);
+        assert_eq!(decoded.vary, e.vary);
+        assert_eq!(&decoded.body[..], b"body");
+        assert_eq!(
+            unix_millis(decoded.response_time),
+            unix_millis(e.response_time)
+        );
+        assert!(CacheEntry::from_bytes(b"garbage").is_err());
+    }
+
+    #[test]
+    fn memory_storage_evicts_oldest() {
+        let storage = MemoryStorage::new(2);
+        for key in ["a", "b", "c"] {
+            storage.put(key, entry(&[], Duration::ZERO));
+        }
+        assert!(storage.get("a").is_none());
+        assert!(storage.get("b").is_some());
+        assert!(storage.get("c").is_some());
+    }
+}
@@ -21,6 +21,9 @@ use std::io::ErrorKind;
 use std::io::Read;
 use std::io::Result as IoResult;
 
+use crate::header::HeaderLine;
+use crate::response::{Trailers, MAX_HEADER_COUNT, MAX_HEADER_SIZE};
+
 /// Reads HTTP chunks and sends back real data.
 ///
 /// # Example
@@ -44,6 +47,12 @@ pub struct Decoder<R> {
     // remaining size of the chunk being read
     // none if we are not in a chunk
     remaining_chunks_size: Option<usize>,
+
+    // set once the last chunk and the trailer section have been read
+    finished: bool,
+
+    // where the trailer fields go once they have been read
+    trailers: Trailers,
 }
 
 impl<R> Decoder<R>
@@ -51,9 +60,17 @@ where
     R: Read,
 {
     pub fn new(source: R) -> Decoder<R> {
+        Decoder::with_trailers(source, Trailers::default())
+    }
+
+    /// Creates a Decoder that stores the trailer fields following the last chunk
+    /// in `trailers`.
+    pub fn with_trailers(source: R, trailers: Trailers) -> Decoder<R> {
         Decoder {
             source,
             remaining_chunks_size: None,
+            finished: false,
+            trailers,
         }
     }
 
@@ -112,6 +129,52 @@ where
         Ok(chunk_size)
     }
 
+    /// Reads the trailer section after the last chunk, up to and including the
+    /// empty line that ends the message.
+    fn read_trailers(&mut self) -> IoResult<()> {
+        let mut trailers = Vec::new();
+        loop {
+            let line = self.read_trailer_line()?;
+            if line.is_empty() {
+                break;
+            }
+            if trailers.len() == MAX_HEADER_COUNT {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("more than {} trailer fields in response", MAX_HEADER_COUNT),
+                ));
+            }
+            // Like response headers, malformed fields are skipped.
+            if let Ok(header) = HeaderLine::from(line).into_header() {
+                trailers.push(header);
+            }
+        }
+        self.trailers.set(trailers);
+        Ok(())
+    }
+
+    fn read_trailer_line(&mut self) -> IoResult<Vec<u8>> {
+        let mut line = Vec::new();
+        let mut buf = [0];
+        loop {
+            if self.source.read(&mut buf)? == 0 {
+                return Err(IoError::new(ErrorKind::InvalidInput, DecoderError));
+            }
+            let byte = buf[0];
+            if byte == b'\r' {
+                self.read_line_feed()?;
+                return Ok(line);
+            }
+            if line.len() == MAX_HEADER_SIZE {
+                return Err(IoError::new(
+                    ErrorKind::InvalidData,
+                    format!("trailer field longer than {} bytes", MAX_HEADER_SIZE),
+                ));
+            }
+            line.push(byte);
+        }
+    }
+
     fn read_carriage_return(&mut self) -> IoResult<()> {
         match self.source.by_ref().bytes().next() {
             Some(Ok(b'\r')) => Ok(()),
@@ -132,6 +195,10 @@ where
     R: Read,
 {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.finished {
+            return Ok(0);
+        }
+
         let remaining_chunks_size = match self.remaining_chunks_size {
             Some(c) => c,
             None => {
@@ -139,10 +206,11 @@ where
                 // the chunks size
                 let chunk_size = self.read_chunk_size()?;
 
-                // if the chunk size is 0, we are at EOF
+                // if the chunk size is 0, we are at EOF, and the trailer
+                // section follows
                 if chunk_size == 0 {
-                    self.read_carriage_return()?;
-                    self.read_line_feed()?;
+                    self.read_trailers()?;
+                    self.finished = true;
                     return Ok(0);
                 }
 
@@ -194,6 +262,7 @@ impl Error for DecoderError {
 #[cfg(test)]
 mod test {
     use super::Decoder;
+    use crate::response::Trailers;
     use std::io;
     use std::io::Read;
 
@@ -303,4 +372,33 @@ mod test {
         let mut string = String::new();
         assert!(decoded.read_to_string(&mut string).is_err());
     }
+
+    #[test]
+    fn trailers() {
+        let source =
+            b"3\r\nhel\r\n0\r\ngrpc-status: 0\r\nbad header\r\nServer-Timing: db;dur=53\r\n\r\n";
+        let trailers = Trailers::default();
+        let mut decoder = Decoder::with_trailers(source as &[u8], trailers.clone());
+
+        let mut decoded = String::new();
+        decoder.read_exact(&mut [0; 3]).unwrap();
+        assert!(trailers.headers().is_empty());
+        decoder.read_to_string(&mut decoded).unwrap();
+
+        assert_eq!(trailers.header("grpc-status"), Some("0".to_string()));
+        assert_eq!(
+            trailers.header("server-timing"),
+            Some("db;dur=53".to_string())
+        );
+        assert_eq!(trailers.headers().len(), 2);
+        // Nothing is read past the end of the message.
+        assert_eq!(decoder.read(&mut [0; 8]).unwrap(), 0);
+    }
+
+    #[test]
+    fn trailers_unterminated() {
+        let source = b"0\r\ngrpc-status: 0\r\n";
+        let mut decoder = Decoder::new(source as &[u8]);
+        assert!(decoder.read(&mut [0; 8]).is_err());
+    }
 }
@@ -0,0 +1,207 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use log::debug;
+
+use crate::error::{Error, ErrorKind};
+use crate::request::Request;
+use crate::response::Response;
+
+/// Attempts that may fail in a row, without any progress, before a download gives up.
+const MAX_FAILED_ATTEMPTS: u32 = 3;
+
+/// Where a download is written.
+pub(crate) trait Destination: Write {
+    /// Discard everything written so far. `false` if that is not possible.
+    fn restart(&mut self) -> io::Result<bool>;
+}
+
+/// A writer that can only be appended to.
+pub(crate) struct Append<W>(pub W);
+
+impl<W: Write> Write for Append<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl<W: Write> Destination for Append<W> {
+    fn restart(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+impl Destination for File {
+    fn restart(&mut self) -> io::Result<bool> {
+        self.set_len(0)?;
+        self.seek(SeekFrom::Start(0))?;
+        Ok(true)
+    }
+}
+
+/// The header fields telling versions of a resource apart.
+#[derive(Debug, PartialEq, Eq)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn of(response: &Response) -> Self {
+        Validators {
+            etag: response.header("etag").map(String::from),
+            last_modified: response.header("last-modified").map(String::from),
+        }
+    }
+
+    /// The value for `If-Range`, which needs a strong ETag or a date.
+    fn if_range(&self) -> Option<&str> {
+        let strong = self.etag.as_deref().filter(|etag| !etag.starts_with("W/"));
+        strong.or(self.last_modified.as_deref())
+    }
+}
+
+/// The first byte position of a `Content-Range: bytes <first>-<last>/<length>` value.
+fn content_range_start(value: &str) -> Option<u64> {
+    let range = value.trim().strip_prefix("bytes ")?;
+    let (first, rest) = range.split_once('-')?;
+    let (last, _length) = rest.split_once('/')?;
+    let first = first.trim().parse::<u64>().ok()?;
+    let last = last.trim().parse::<u64>().ok()?;
+    if last < first {
+        return None;
+    }
+    Some(first)
+}
+
+/// Download the body of `request` to `dest`, returning the number of bytes written.
+///
+/// When reading the body fails, the request is made again for the rest of it, with
+/// `Range` and `If-Range`. A `206 Partial Content` response is appended to what was
+/// written. A full response to the range request means the server ignored it, and the
+/// bytes already written are skipped; or that the resource changed, and the download
+/// starts over if `dest` allows it.
+pub(crate) fn download(request: Request, dest: &mut dyn Destination) -> Result<u64, Error> {
+    // Ranges are positions in the encoded body, so it must not be decoded.
+    let request = if request.has("accept-encoding") {
+        request
+    } else {
+        request.set("Accept-Encoding", "identity")
+    };
+
+    let mut buf = vec![0; 16 * 1024];
+    let mut written = 0;
+    let mut validators = None;
+    let mut failures = 0;
+    loop {
+        let mut attempt = request.clone();
+        if written > 0 {
+            attempt = attempt.set("Range", &format!("bytes={}-", written));
+            if let Some(value) = validators.as_ref().and_then(Validators::if_range) {
+                attempt = attempt.set("If-Range", value);
+            }
+        }
+        let response = match attempt.call() {
+            Ok(response) => response,
+            // Only the first request fails right away, later ones resume an interrupted read.
+            Err(Error::Transport(e)) if failures > 0 && failures < MAX_FAILED_ATTEMPTS => {
+                debug!("resuming download failed: {}", e);
+                failures += 1;
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+
+        let current = Validators::of(&response);
+        let mut skip = 0;
+        if written > 0 && response.status() == 206 {
+            let start = response
+                .header("content-range")
+                .and_then(content_range_start);
+            if start != Some(written) {
+                return Err(ErrorKind::BadHeader.msg(format!(
+                    "Content-Range does not continue the download at byte {}",
+                    written
+                )));
+            }
+        } else if written > 0 && validators.as_ref() == Some(&current) {
+            debug!("server ignored the range, skipping {} bytes", written);
+            skip = written;
+        } else if written > 0 {
+            if !dest.restart()? {
+                let msg = "resource changed during the download, and the writer can't start over";
+                return Err(ErrorKind::Io.msg(msg));
+            }
+            debug!(
+                "resource changed, restarting download of {}",
+                response.get_url()
+            );
+            written = 0;
+            validators = Some(current);
+        } else {
+            validators = Some(current);
+        }
+
+        let mut reader = response.into_reader();
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) if skip > 0 => {
+                    return Err(ErrorKind::Io.msg("response is shorter than the download"));
+                }
+                Ok(0) => {
+                    dest.flush()?;
+                    return Ok(written);
+                }
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                // A corrupt body, or an exceeded limit, would fail again.
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => return Err(e.into()),
+                Err(e) if failures < MAX_FAILED_ATTEMPTS => {
+                    debug!("download interrupted after {} bytes: {}", written, e);
+                    failures += 1;
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let skipped = skip.min(n as u64) as usize;
+            skip -= skipped as u64;
+            if skipped < n {
+                dest.write_all(&buf[skipped..n])?;
+                written += (n - skipped) as u64;
+                failures = 0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_range() {
+        assert_eq!(content_range_start("bytes 100-199/200"), Some(100));
+        assert_eq!(content_range_start("bytes 0-99/*"), Some(0));
+        assert_eq!(content_range_start("bytes 100-99/200"), None);
+        assert_eq!(content_range_start("bytes */200"), None);
+        assert_eq!(content_range_start("items 0-1/2"), None);
+    }
+
+    #[test]
+    fn if_range() {
+        let validators = |etag: Option<&str>, date: Option<&str>| Validators {
+            etag: etag.map(String::from),
+            last_modified: date.map(String::from),
+        };
+        let date = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let strong = validators(Some("\"v1\""), Some(date));
+        assert_eq!(strong.if_range(), Some("\"v1\""));
+        let weak = validators(Some("W/\"v1\""), Some(date));
+        assert_eq!(weak.if_range(), Some(date));
+        assert_eq!(validators(Some("W/\"v1\""), None).if_range(), None);
+    }
+}
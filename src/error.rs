@@ -354,6 +354,12 @@ pub enum ErrorKind {
     ProxyConnect,
     /// Incorrect credentials for proxy
     ProxyUnauthorized,
+    /// The response exceeded a configured limit, like
+    /// [`AgentBuilder::max_body_size()`](crate::AgentBuilder::max_body_size).
+    ///
+    /// Limits on the body are checked while it is read, so the error is the source of
+    /// the `std::io::Error` returned by the body's reader.
+    LimitExceeded,
     /// HTTP status code indicating an error (e.g. 4xx, 5xx)
     /// Read the inner response body for details and to return
     /// the connection to the pool.
@@ -415,6 +421,7 @@ impl fmt::Display for ErrorKind {
             ErrorKind::InvalidProxyUrl => write!(f, "Malformed proxy"),
             ErrorKind::ProxyConnect => write!(f, "Proxy failed to connect"),
             ErrorKind::ProxyUnauthorized => write!(f, "Provided proxy credentials are incorrect"),
+            ErrorKind::LimitExceeded => write!(f, "Limit Exceeded"),
             ErrorKind::HTTP => write!(f, "HTTP status error"),
         }
     }
@@ -1,6 +1,7 @@
 use crate::error::{Error, ErrorKind};
 use std::fmt;
 use std::str::{from_utf8, FromStr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 /// Since a status line or header can contain non-utf8 characters the
 /// backing store is a `Vec<u8>`
@@ -30,6 +31,10 @@ impl HeaderLine {
         self.0.is_empty()
     }
 
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
     fn as_bytes(&self) -> &[u8] {
         &self.0
     }
@@ -235,6 +240,44 @@ fn is_field_vchar_or_obs_fold(b: &u8) -> bool {
     }
 }
 
+/// Parse an HTTP-date in the preferred IMF-fixdate format, such as
+/// `Sun, 06 Nov 1994 08:49:37 GMT`. <https://www.rfc-editor.org/rfc/rfc9110#section-5.6.7>
+pub(crate) fn parse_http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let mut parts = value.split_whitespace();
+    let _weekday = parts.next()?.strip_suffix(',')?;
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = parts.next()?;
+    let month = MONTHS.iter().position(|m| *m == month)? as u64 + 1;
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':').map(|t| t.parse::<u64>().ok());
+    let (hour, min, sec) = (time.next()??, time.next()??, time.next()??);
+    if parts.next()? != "GMT" || parts.next().is_some() || time.next().is_some() {
+        return None;
+    }
+    if year < 1970 || !(1..=31).contains(&day) || hour > 23 || min > 59 || sec > 60 {
+        return None;
+    }
+
+    // Days since the epoch of a proleptic Gregorian date,
+    // <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146_097 + doe - 719_468;
+
+    let secs = days * 86_400 + hour * 3_600 + min * 60 + sec;
+    UNIX_EPOCH.checked_add(Duration::from_secs(secs))
+}
+
 impl FromStr for Header {
     type Err = Error;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
@@ -334,6 +377,18 @@ mod tests {
         assert!(header.is_name("X-FORWARDED-FOR"));
     }
 
+    #[test]
+    fn http_date() {
+        let date = parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(784_111_777));
+        let date = parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT").unwrap();
+        assert_eq!(date, UNIX_EPOCH + Duration::from_secs(1_709_164_800));
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49 GMT"), None);
+        assert_eq!(parse_http_date("120"), None);
+    }
+
     #[test]
     fn test_iso8859_utf8_mixup() {
         // C2 A5 is ¥ in UTF-8 and Â¥ in ISO-8859-1
@@ -0,0 +1,813 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use httlib_hpack::{Decoder, Encoder};
+use log::debug;
+
+use super::frame::*;
+use crate::header::{Header, HeaderLine};
+use crate::stream::{time_until_deadline, Stream};
+
+/// How long a thread reading on behalf of the connection holds on to the
+/// socket before giving writers (and other readers) a chance.
+const READ_SLICE: Duration = Duration::from_millis(100);
+
+/// Upper bound for a single header block (HEADERS + CONTINUATION frames).
+const MAX_HEADER_BLOCK: usize = 256 * 1_024;
+
+/// A multiplexed HTTP/2 connection.
+///
+/// Any number of requests can be in flight on the connection at once. There is no
+/// background thread: whichever thread is waiting for something (response headers,
+/// body data or flow control window) takes turns reading frames off the socket and
+/// dispatching them to the streams they belong to. A reading thread holds the socket
+/// for at most [`READ_SLICE`] at a time, so that other threads can write requests.
+///
+/// Lock order is always `io` before `state`.
+///
+/// *Internal API*
+pub(crate) struct Connection {
+    io: Mutex<Io>,
+    state: Mutex<State>,
+    // Signalled whenever `state` changed because frames were read.
+    cond: Condvar,
+    pub(crate) remote_addr: SocketAddr,
+    pub(crate) local_addr: SocketAddr,
+}
+
+struct Io {
+    stream: Stream,
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    // Bytes read from the socket that do not make up a full frame yet.
+    read_buf: Vec<u8>,
+    // A header block that has been started by HEADERS, but awaits CONTINUATION frames.
+    partial_headers: Option<PartialHeaders>,
+}
+
+struct PartialHeaders {
+    stream_id: u32,
+    end_stream: bool,
+    block: Vec<u8>,
+}
+
+struct State {
+    next_stream_id: u32,
+    streams: HashMap<u32, StreamState>,
+    // Settings sent to us by the server.
+    max_frame_size: u32,
+    max_concurrent_streams: u32,
+    initial_window_size: u32,
+    // Connection level send window.
+    send_window: i64,
+    // Whether some thread is currently reading frames.
+    reading: bool,
+    // Highest stream id the server will process, once GOAWAY has been received.
+    goaway: Option<u32>,
+    // A fatal error that ended the connection.
+    error: Option<(io::ErrorKind, String)>,
+}
+
+#[derive(Default)]
+struct StreamState {
+    status: Option<u16>,
+    headers: Vec<Header>,
+    trailers: Vec<Header>,
+    data: Vec<u8>,
+    end_stream: bool,
+    reset: Option<u32>,
+    send_window: i64,
+    // Receive window we have granted the server for this stream.
+    recv_window: i64,
+    // Bytes handed to the user that have not yet been given back as window.
+    recv_unacked: u32,
+}
+
+impl fmt::Debug for Connection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connection")
+            .field("remote_addr", &self.remote_addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl State {
+    fn check_error(&self) -> io::Result<()> {
+        match &self.error {
+            Some((kind, msg)) => Err(io::Error::new(*kind, msg.clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn stream(&mut self, stream_id: u32) -> io::Result<&mut StreamState> {
+        self.streams
+            .get_mut(&stream_id)
+            .ok_or_else(|| protocol_error("unknown stream"))
+    }
+}
+
+impl StreamState {
+    fn check_reset(&self) -> io::Result<()> {
+        match self.reset {
+            Some(code) => Err(reset_error(code)),
+            None => Ok(()),
+        }
+    }
+}
+
+fn reset_error(code: u32) -> io::Error {
+    if code == REFUSED_STREAM {
+        // The server guarantees a refused stream was not processed, so
+        // this is safe to retry on a new connection.
+        io::Error::new(
+            io::ErrorKind::ConnectionAborted,
+            "HTTP/2 stream refused by server",
+        )
+    } else {
+        io::Error::new(
+            io::ErrorKind::Other,
+            format!("HTTP/2 stream reset by server (error code {})", code),
+        )
+    }
+}
+
+impl Connection {
+    /// Start an HTTP/2 connection over a stream that negotiated `h2` via ALPN.
+    pub(crate) fn handshake(mut stream: Stream) -> io::Result<Arc<Connection>> {
+        // The stream belongs to this connection from now on. It must never end up in
+        // the HTTP/1.1 part of the pool.
+        stream.set_unpoolable();
+        let remote_addr = stream.remote_addr;
+        let local_addr = match stream.socket() {
+            Some(sock) => sock.local_addr()?,
+            None => std::net::SocketAddrV4::new(std::net::Ipv4Addr::new(127, 0, 0, 1), 0).into(),
+        };
+
+        let mut preface = PREFACE.to_vec();
+        Frame::settings(&[(SETTINGS_ENABLE_PUSH, 0)]).encode(&mut preface);
+        stream.write_all(&preface)?;
+        stream.flush()?;
+        debug!("started HTTP/2 connection to {}", remote_addr);
+
+        Ok(Arc::new(Connection {
+            io: Mutex::new(Io {
+                stream,
+                encoder: Encoder::default(),
+                decoder: Decoder::default(),
+                read_buf: Vec::new(),
+                partial_headers: None,
+            }),
+            state: Mutex::new(State {
+                next_stream_id: 1,
+                streams: HashMap::new(),
+                max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+                max_concurrent_streams: u32::MAX,
+                initial_window_size: DEFAULT_WINDOW_SIZE,
+                send_window: DEFAULT_WINDOW_SIZE as i64,
+                reading: false,
+                goaway: None,
+                error: None,
+            }),
+            cond: Condvar::new(),
+            remote_addr,
+            local_addr,
+        }))
+    }
+
+    /// Whether new requests can be started on this connection.
+    pub(crate) fn is_usable(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.error.is_none() && state.goaway.is_none() && state.next_stream_id <= MAX_STREAM_ID
+    }
+
+    /// Open a new stream by sending a request header block.
+    ///
+    /// Each field is (name, value, sensitive). Names must be lowercase.
+    pub(crate) fn open_stream(
+        &self,
+        fields: &[(String, Vec<u8>, bool)],
+        end_stream: bool,
+        deadline: Option<Instant>,
+    ) -> io::Result<u32> {
+        loop {
+            // Wait until the server allows another concurrent stream.
+            self.wait(deadline, |state| {
+                if state.goaway.is_some() || state.next_stream_id > MAX_STREAM_ID {
+                    return Err(reset_error(REFUSED_STREAM));
+                }
+                if state.streams.len() < state.max_concurrent_streams as usize {
+                    Ok(Some(()))
+                } else {
+                    Ok(None)
+                }
+            })?;
+
+            let mut io = self.io.lock().unwrap();
+            let mut state = self.state.lock().unwrap();
+            state.check_error()?;
+            if state.streams.len() >= state.max_concurrent_streams as usize {
+                // Another thread took the free slot.
+                continue;
+            }
+            let stream_id = state.next_stream_id;
+            state.next_stream_id += 2;
+            let send_window = state.initial_window_size as i64;
+            state.streams.insert(
+                stream_id,
+                StreamState {
+                    send_window,
+                    recv_window: DEFAULT_WINDOW_SIZE as i64,
+                    ..Default::default()
+                },
+            );
+            let max_frame_size = state.max_frame_size as usize;
+            drop(state);
+
+            let out = encode_header_block(
+                &mut io.encoder,
+                fields,
+                stream_id,
+                end_stream,
+                max_frame_size,
+            )?;
+            self.write_locked(&mut io, &out, deadline)?;
+            return Ok(stream_id);
+        }
+    }
+
+    /// Send trailer fields, which end our side of the stream.
+    ///
+    /// Nothing is sent if the server is no longer interested in the request.
+    pub(crate) fn send_trailers(
+        &self,
+        stream_id: u32,
+        fields: &[(String, Vec<u8>, bool)],
+        deadline: Option<Instant>,
+    ) -> io::Result<()> {
+        let mut io = self.io.lock().unwrap();
+        let mut state = self.state.lock().unwrap();
+        state.check_error()?;
+        let max_frame_size = state.max_frame_size as usize;
+        let s = state.stream(stream_id)?;
+        if s.reset.is_some() || s.end_stream {
+            return Ok(());
+        }
+        drop(state);
+
+        let out = encode_header_block(&mut io.encoder, fields, stream_id, true, max_frame_size)?;
+        self.write_locked(&mut io, &out, deadline)
+    }
+
+    /// Send request body data on a stream, respecting flow control.
+    ///
+    /// Returns `false` if the server is no longer interested in the body, which happens
+    /// when it responded early and closed or reset its side of the stream.
+    pub(crate) fn send_data(
+        &self,
+        stream_id: u32,
+        mut data: &[u8],
+        end_stream: bool,
+        deadline: Option<Instant>,
+    ) -> io::Result<bool> {
+        loop {
+            // Reserve as much send window as we can, up to the max frame size.
+            let want = data.len();
+            let allowed = self.wait(deadline, |state| {
+                let max = state.max_frame_size as i64;
+                let conn_window = state.send_window;
+                let s = state.stream(stream_id)?;
+                if s.reset.is_some() || s.end_stream {
+                    if s.status.is_some() || s.reset == Some(NO_ERROR) {
+                        return Ok(Some(None));
+                    }
+                    s.check_reset()?;
+                }
+                let n = (want as i64).min(conn_window).min(s.send_window).min(max);
+                if n <= 0 && want > 0 {
+                    return Ok(None);
+                }
+                let n = n.max(0);
+                s.send_window -= n;
+                state.send_window -= n;
+                Ok(Some(Some(n as usize)))
+            })?;
+
+            let n = match allowed {
+                Some(n) => n,
+                None => return Ok(false),
+            };
+
+            let (chunk, rest) = data.split_at(n);
+            data = rest;
+            let flags = if data.is_empty() && end_stream {
+                FLAG_END_STREAM
+            } else {
+                0
+            };
+            let mut out = Vec::with_capacity(HEADER_LEN + chunk.len());
+            Frame::new(DATA, flags, stream_id, chunk.to_vec()).encode(&mut out);
+            let mut io = self.io.lock().unwrap();
+            self.write_locked(&mut io, &out, deadline)?;
+
+            if data.is_empty() {
+                return Ok(true);
+            }
+        }
+    }
+
+    /// Wait for the final (non-informational) response header block of a stream.
+    pub(crate) fn recv_headers(
+        &self,
+        stream_id: u32,
+        deadline: Option<Instant>,
+    ) -> io::Result<(u16, Vec<Header>)> {
+        self.wait(deadline, |state| {
+            let s = state.stream(stream_id)?;
+            if let Some(status) = s.status {
+                return Ok(Some((status, std::mem::take(&mut s.headers))));
+            }
+            s.check_reset()?;
+            if s.end_stream {
+                return Err(protocol_error("stream ended without response headers"));
+            }
+            Ok(None)
+        })
+    }
+
+    /// Read response body data from a stream. Returns 0 at the end of the stream.
+    pub(crate) fn recv_data(
+        &self,
+        stream_id: u32,
+        buf: &mut [u8],
+        deadline: Option<Instant>,
+    ) -> io::Result<usize> {
+        let (n, window_update) = self.wait(deadline, |state| {
+            let s = state.stream(stream_id)?;
+            if !s.data.is_empty() {
+                let n = buf.len().min(s.data.len());
+                buf[..n].copy_from_slice(&s.data[..n]);
+                s.data.drain(..n);
+                s.recv_unacked += n as u32;
+                // Give back window once half of it is used up, unless the server is done sending.
+                let mut update = 0;
+                if !s.end_stream && s.recv_unacked >= DEFAULT_WINDOW_SIZE / 2 {
+                    update = s.recv_unacked;
+                    s.recv_unacked = 0;
+                    s.recv_window += update as i64;
+                }
+                return Ok(Some((n, update)));
+            }
+            if s.end_stream {
+                return Ok(Some((0, 0)));
+            }
+            s.check_reset()?;
+            Ok(None)
+        })?;
+
+        if window_update > 0 {
+            self.write_frame(Frame::window_update(stream_id, window_update), deadline)?;
+        }
+
+        Ok(n)
+    }
+
+    /// Take the trailers of a stream, once all its data has been read.
+    pub(crate) fn take_trailers(&self, stream_id: u32) -> Vec<Header> {
+        match self.state.lock() {
+            Ok(mut state) => match state.streams.get_mut(&stream_id) {
+                Some(s) => std::mem::take(&mut s.trailers),
+                None => vec![],
+            },
+            Err(_) => vec![],
+        }
+    }
+
+    /// Forget about a stream. If the server hasn't finished sending, the stream is cancelled.
+    pub(crate) fn close_stream(&self, stream_id: u32) {
+        let cancel = match self.state.lock() {
+            Ok(mut state) => match state.streams.remove(&stream_id) {
+                Some(s) => !s.end_stream && s.reset.is_none() && state.error.is_none(),
+                None => false,
+            },
+            Err(_) => false,
+        };
+        // Wake up threads waiting for a free stream slot.
+        self.cond.notify_all();
+        if cancel {
+            debug!("cancelling HTTP/2 stream {}", stream_id);
+            if let Err(e) = self.write_frame(Frame::rst_stream(stream_id, CANCEL), None) {
+                debug!("failed to cancel HTTP/2 stream {}: {}", stream_id, e);
+            }
+        }
+    }
+
+    fn write_frame(&self, frame: Frame, deadline: Option<Instant>) -> io::Result<()> {
+        let mut out = Vec::with_capacity(HEADER_LEN + frame.payload.len());
+        frame.encode(&mut out);
+        let mut io = self.io.lock().unwrap();
+        self.write_locked(&mut io, &out, deadline)
+    }
+
+    fn write_locked(&self, io: &mut Io, buf: &[u8], deadline: Option<Instant>) -> io::Result<()> {
+        let timeout = match deadline {
+            Some(deadline) => Some(time_until_deadline(deadline)?),
+            None => None,
+        };
+        io.stream.set_write_timeout(timeout)?;
+        let result = io.stream.write_all(buf).and_then(|_| io.stream.flush());
+        if let Err(e) = &result {
+            self.fail(io::Error::new(e.kind(), e.to_string()));
+        }
+        result
+    }
+
+    /// Mark the connection as dead, failing all streams.
+    fn fail(&self, err: io::Error) {
+        debug!("HTTP/2 connection to {} failed: {}", self.remote_addr, err);
+        let mut state = self.state.lock().unwrap();
+        if state.error.is_none() {
+            state.error = Some((err.kind(), err.to_string()));
+        }
+        self.cond.notify_all();
+    }
+
+    /// Block until `ready` returns a value, reading frames from the connection as needed.
+    ///
+    /// `ready` is called with the state lock held, every time the state might have changed.
+    fn wait<T>(
+        &self,
+        deadline: Option<Instant>,
+        mut ready: impl FnMut(&mut State) -> io::Result<Option<T>>,
+    ) -> io::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(value) = ready(&mut state)? {
+                return Ok(value);
+            }
+            state.check_error()?;
+
+            let timeout = match deadline {
+                Some(deadline) => time_until_deadline(deadline)?.min(READ_SLICE),
+                None => READ_SLICE,
+            };
+
+            if state.reading {
+                state = self.cond.wait_timeout(state, timeout).unwrap().0;
+                continue;
+            }
+
+            state.reading = true;
+            drop(state);
+            let result = self.read_frames(timeout);
+            state = self.state.lock().unwrap();
+            state.reading = false;
+            if let Err(e) = result {
+                drop(state);
+                self.fail(e);
+                state = self.state.lock().unwrap();
+            }
+            self.cond.notify_all();
+        }
+    }
+
+    /// Read from the socket for at most `timeout` and handle all complete frames.
+    fn read_frames(&self, timeout: Duration) -> io::Result<()> {
+        let mut io = self.io.lock().unwrap();
+        io.stream.set_read_timeout(Some(timeout))?;
+
+        let mut buf = [0; 16_384];
+        let n = match io.stream.read(&mut buf) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ConnectionAborted,
+                    "HTTP/2 connection closed by server",
+                ))
+            }
+            Ok(n) => n,
+            Err(e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Ok(())
+            }
+            Err(e) => return Err(e),
+        };
+        io.read_buf.extend_from_slice(&buf[..n]);
+
+        loop {
+            let frame = match Frame::parse(&mut io.read_buf, DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(()),
+                Err(e) => {
+                    self.send_goaway(&mut io, FRAME_SIZE_ERROR);
+                    return Err(e);
+                }
+            };
+            if let Err(e) = self.handle_frame(&mut io, frame) {
+                if e.kind() == io::ErrorKind::InvalidData {
+                    self.send_goaway(&mut io, PROTOCOL_ERROR);
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    fn send_goaway(&self, io: &mut Io, error_code: u32) {
+        // We never accept streams from the server, so the last stream id is always 0.
+        let _ = Frame::goaway(0, error_code).write_to(&mut io.stream);
+    }
+
+    fn handle_frame(&self, io: &mut Io, frame: Frame) -> io::Result<()> {
+        if io.partial_headers.is_some() && frame.kind != CONTINUATION {
+            return Err(protocol_error("expected CONTINUATION frame"));
+        }
+
+        match frame.kind {
+            DATA => {
+                let data = frame.unpadded()?;
+                let len = frame.payload.len() as u32;
+                let mut state = self.state.lock().unwrap();
+                if let Some(s) = state.streams.get_mut(&frame.stream_id) {
+                    s.recv_window -= len as i64;
+                    if s.recv_window < 0 {
+                        drop(state);
+                        self.send_goaway(io, FLOW_CONTROL_ERROR);
+                        return Err(protocol_error("stream flow control window exceeded"));
+                    }
+                    if s.reset.is_none() && !s.end_stream {
+                        s.data.extend_from_slice(data);
+                        // Padding counts against the window, but never reaches the user.
+                        s.recv_unacked += len - data.len() as u32;
+                        s.end_stream = frame.has_flag(FLAG_END_STREAM);
+                    }
+                }
+                drop(state);
+                // The connection window is given back as soon as data arrives. Flow control
+                // towards slow readers happens on the stream level.
+                if len > 0 {
+                    Frame::window_update(0, len).write_to(&mut io.stream)?;
+                    io.stream.flush()?;
+                }
+            }
+            HEADERS => {
+                let block = frame.unpadded()?.to_vec();
+                let partial = PartialHeaders {
+                    stream_id: frame.stream_id,
+                    end_stream: frame.has_flag(FLAG_END_STREAM),
+                    block,
+                };
+                if frame.has_flag(FLAG_END_HEADERS) {
+                    self.handle_header_block(io, partial)?;
+                } else {
+                    io.partial_headers = Some(partial);
+                }
+            }
+            CONTINUATION => {
+                let mut partial = match io.partial_headers.take() {
+                    Some(p) if p.stream_id == frame.stream_id => p,
+                    _ => return Err(protocol_error("unexpected CONTINUATION frame")),
+                };
+                partial.block.extend_from_slice(&frame.payload);
+                if partial.block.len() > MAX_HEADER_BLOCK {
+                    return Err(protocol_error("header block too large"));
+                }
+                if frame.has_flag(FLAG_END_HEADERS) {
+                    self.handle_header_block(io, partial)?;
+                } else {
+                    io.partial_headers = Some(partial);
+                }
+            }
+            RST_STREAM => {
+                let code = frame.u32_at(0)?;
+                let mut state = self.state.lock().unwrap();
+                if let Some(s) = state.streams.get_mut(&frame.stream_id) {
+                    s.reset = Some(code);
+                }
+            }
+            SETTINGS => {
+                if frame.has_flag(FLAG_ACK) {
+                    return Ok(());
+                }
+                let mut state = self.state.lock().unwrap();
+                for (id, value) in frame.parse_settings()? {
+                    match id {
+                        SETTINGS_MAX_CONCURRENT_STREAMS => state.max_concurrent_streams = value,
+                        SETTINGS_INITIAL_WINDOW_SIZE => {
+                            if value > MAX_WINDOW_SIZE {
+                                return Err(protocol_error("initial window size too large"));
+                            }
+                            let delta = value as i64 - state.initial_window_size as i64;
+                            state.initial_window_size = value;
+                            for s in state.streams.values_mut() {
+                                s.send_window += delta;
+                            }
+                        }
+                        SETTINGS_MAX_FRAME_SIZE => {
+                            if !(DEFAULT_MAX_FRAME_SIZE..=16_777_215).contains(&value) {
+                                return Err(protocol_error("invalid max frame size"));
+                            }
+                            state.max_frame_size = value;
+                        }
+                        // We never insert into the dynamic table, so its size doesn't matter.
+                        SETTINGS_HEADER_TABLE_SIZE => {}
+                        _ => {}
+                    }
+                }
+                drop(state);
+                Frame::new(SETTINGS, FLAG_ACK, 0, vec![]).write_to(&mut io.stream)?;
+                io.stream.flush()?;
+            }
+            PING => {
+                if frame.payload.len() != 8 {
+                    return Err(protocol_error("PING payload must be 8 bytes"));
+                }
+                if !frame.has_flag(FLAG_ACK) {
+                    Frame::new(PING, FLAG_ACK, 0, frame.payload).write_to(&mut io.stream)?;
+                    io.stream.flush()?;
+                }
+            }
+            GOAWAY => {
+                let last_stream_id = frame.u32_at(0)? & MAX_STREAM_ID;
+                let code = frame.u32_at(4)?;
+                debug!(
+                    "HTTP/2 GOAWAY from {} (last stream {}, error code {})",
+                    self.remote_addr, last_stream_id, code
+                );
+                let mut state = self.state.lock().unwrap();
+                state.goaway = Some(last_stream_id);
+                for (id, s) in state.streams.iter_mut() {
+                    if *id > last_stream_id && !s.end_stream {
+                        s.reset = Some(REFUSED_STREAM);
+                    }
+                }
+            }
+            WINDOW_UPDATE => {
+                let increment = (frame.u32_at(0)? & MAX_WINDOW_SIZE) as i64;
+                if increment == 0 {
+                    return Err(protocol_error("WINDOW_UPDATE with zero increment"));
+                }
+                let mut state = self.state.lock().unwrap();
+                let window = if frame.stream_id == 0 {
+                    Some(&mut state.send_window)
+                } else {
+                    state
+                        .streams
+                        .get_mut(&frame.stream_id)
+                        .map(|s| &mut s.send_window)
+                };
+                if let Some(window) = window {
+                    *window += increment;
+                    if *window > MAX_WINDOW_SIZE as i64 {
+                        drop(state);
+                        self.send_goaway(io, FLOW_CONTROL_ERROR);
+                        return Err(protocol_error("flow control window overflow"));
+                    }
+                }
+            }
+            PUSH_PROMISE => {
+                // We disable push in our SETTINGS.
+                return Err(protocol_error("unexpected PUSH_PROMISE"));
+            }
+            // PRIORITY and unknown frame types are ignored.
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_header_block(&self, io: &mut Io, partial: PartialHeaders) -> io::Result<()> {
+        // The block must always be decoded, even for streams we no longer care about,
+        // to keep the decoder's dynamic table in sync with the server.
+        let mut block = partial.block;
+        let mut fields = Vec::new();
+        if io.decoder.decode(&mut block, &mut fields).is_err() {
+            self.send_goaway(io, COMPRESSION_ERROR);
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "HTTP/2: failed to decode header block",
+            ));
+        }
+
+        let mut state = self.state.lock().unwrap();
+        let s = match state.streams.get_mut(&partial.stream_id) {
+            Some(s) => s,
+            None => return Ok(()),
+        };
+
+        let mut status = None;
+        let mut headers = Vec::with_capacity(fields.len());
+        for (name, value, _) in fields {
+            if name == b":status" {
+                status = std::str::from_utf8(&value)
+                    .ok()
+                    .and_then(|v| v.parse::<u16>().ok());
+                continue;
+            }
+            if name.first() == Some(&b':') {
+                continue;
+            }
+            let mut line = name;
+            line.extend_from_slice(b": ");
+            line.extend_from_slice(&value);
+            if let Ok(header) = HeaderLine::from(line).into_header() {
+                headers.push(header);
+            }
+        }
+
+        if s.status.is_none() {
+            match status {
+                // Informational responses, like 100 Continue, are skipped.
+                Some(100..=199) => {}
+                Some(status) => {
+                    s.status = Some(status);
+                    s.headers = headers;
+                }
+                None => return Err(protocol_error("response without :status")),
+            }
+        } else {
+            // A second header block is trailers, which end the stream.
+            s.trailers = headers;
+        }
+
+        if partial.end_stream {
+            s.end_stream = true;
+        }
+
+        Ok(())
+    }
+}
+
+/// Encode a header block as a HEADERS frame, followed by CONTINUATION frames if it
+/// doesn't fit in one frame.
+fn encode_header_block(
+    encoder: &mut Encoder,
+    fields: &[(String, Vec<u8>, bool)],
+    stream_id: u32,
+    end_stream: bool,
+    max_frame_size: usize,
+) -> io::Result<Vec<u8>> {
+    let mut block = Vec::new();
+    for (name, value, sensitive) in fields {
+        encode_field(encoder, name, value, *sensitive, &mut block)?;
+    }
+
+    // An empty block still needs a HEADERS frame.
+    let chunks: Vec<&[u8]> = if block.is_empty() {
+        vec![&[]]
+    } else {
+        block.chunks(max_frame_size).collect()
+    };
+    let mut out = Vec::with_capacity(block.len() + chunks.len() * HEADER_LEN);
+    for (i, chunk) in chunks.iter().enumerate() {
+        let kind = if i == 0 { HEADERS } else { CONTINUATION };
+        let mut flags = 0;
+        if i == 0 && end_stream {
+            flags |= FLAG_END_STREAM;
+        }
+        if i == chunks.len() - 1 {
+            flags |= FLAG_END_HEADERS;
+        }
+        Frame::new(kind, flags, stream_id, chunk.to_vec()).encode(&mut out);
+    }
+    Ok(out)
+}
+
+fn encode_field(
+    encoder: &mut Encoder,
+    name: &str,
+    value: &[u8],
+    sensitive: bool,
+    dst: &mut Vec<u8>,
+) -> io::Result<()> {
+    // Fields are never added to the dynamic table, which means the server's
+    // SETTINGS_HEADER_TABLE_SIZE never has to be tracked.
+    let flags = if sensitive {
+        Encoder::NEVER_INDEXED
+    } else {
+        Encoder::BEST_FORMAT
+    };
+    // Encode into a scratch buffer, since a failed attempt can leave partial output.
+    let mut buf = Vec::new();
+    let huffman = (
+        name.as_bytes().to_vec(),
+        value.to_vec(),
+        flags | Encoder::HUFFMAN_VALUE,
+    );
+    if encoder.encode(huffman, &mut buf).is_err() {
+        // Huffman coding only handles ASCII, fall back to a plain literal.
+        buf.clear();
+        encoder
+            .encode((name.as_bytes().to_vec(), value.to_vec(), flags), &mut buf)
+            .map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("HTTP/2: failed to encode header {}: {}", name, e),
+                )
+            })?;
+    }
+    dst.extend_from_slice(&buf);
+    Ok(())
+}
@@ -0,0 +1,278 @@
+use std::fmt;
+use std::io::{self, Write};
+
+/// Length of the fixed frame header that precedes every frame payload.
+pub(crate) const HEADER_LEN: usize = 9;
+
+/// The initial value of SETTINGS_MAX_FRAME_SIZE, and the one we advertise.
+pub(crate) const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+
+/// The initial flow control window for both connections and streams.
+pub(crate) const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+
+/// Largest value a flow control window may reach.
+pub(crate) const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+/// Stream identifiers are 31 bits, the high bit is reserved.
+pub(crate) const MAX_STREAM_ID: u32 = (1 << 31) - 1;
+
+/// The fixed client connection preface.
+/// <https://www.rfc-editor.org/rfc/rfc9113#section-3.4>
+pub(crate) const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+// Frame types, <https://www.rfc-editor.org/rfc/rfc9113#section-6>
+pub(crate) const DATA: u8 = 0x0;
+pub(crate) const HEADERS: u8 = 0x1;
+pub(crate) const RST_STREAM: u8 = 0x3;
+pub(crate) const SETTINGS: u8 = 0x4;
+pub(crate) const PUSH_PROMISE: u8 = 0x5;
+pub(crate) const PING: u8 = 0x6;
+pub(crate) const GOAWAY: u8 = 0x7;
+pub(crate) const WINDOW_UPDATE: u8 = 0x8;
+pub(crate) const CONTINUATION: u8 = 0x9;
+
+// Frame flags. ACK and END_STREAM share a bit, but are used on different frame types.
+pub(crate) const FLAG_END_STREAM: u8 = 0x1;
+pub(crate) const FLAG_ACK: u8 = 0x1;
+pub(crate) const FLAG_END_HEADERS: u8 = 0x4;
+pub(crate) const FLAG_PADDED: u8 = 0x8;
+pub(crate) const FLAG_PRIORITY: u8 = 0x20;
+
+// Settings identifiers, <https://www.rfc-editor.org/rfc/rfc9113#section-6.5.2>
+pub(crate) const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub(crate) const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub(crate) const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub(crate) const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub(crate) const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+
+// Error codes, <https://www.rfc-editor.org/rfc/rfc9113#section-7>
+pub(crate) const NO_ERROR: u32 = 0x0;
+pub(crate) const PROTOCOL_ERROR: u32 = 0x1;
+pub(crate) const FLOW_CONTROL_ERROR: u32 = 0x3;
+pub(crate) const FRAME_SIZE_ERROR: u32 = 0x6;
+pub(crate) const REFUSED_STREAM: u32 = 0x7;
+pub(crate) const CANCEL: u32 = 0x8;
+pub(crate) const COMPRESSION_ERROR: u32 = 0x9;
+
+/// A single HTTP/2 frame.
+///
+/// The payload is kept verbatim, padding included. Use [`Frame::unpadded`] to get at the
+/// content of DATA, HEADERS and PUSH_PROMISE frames.
+#[derive(Clone, PartialEq, Eq)]
+pub(crate) struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream_id: u32,
+    pub payload: Vec<u8>,
+}
+
+impl fmt::Debug for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Frame")
+            .field("kind", &self.kind)
+            .field("flags", &self.flags)
+            .field("stream_id", &self.stream_id)
+            .field("len", &self.payload.len())
+            .finish()
+    }
+}
+
+impl Frame {
+    pub fn new(kind: u8, flags: u8, stream_id: u32, payload: Vec<u8>) -> Self {
+        Frame {
+            kind,
+            flags,
+            stream_id,
+            payload,
+        }
+    }
+
+    pub fn settings(settings: &[(u16, u32)]) -> Self {
+        let mut payload = Vec::with_capacity(settings.len() * 6);
+        for (id, value) in settings {
+            payload.extend_from_slice(&id.to_be_bytes());
+            payload.extend_from_slice(&value.to_be_bytes());
+        }
+        Frame::new(SETTINGS, 0, 0, payload)
+    }
+
+    pub fn window_update(stream_id: u32, increment: u32) -> Self {
+        Frame::new(
+            WINDOW_UPDATE,
+            0,
+            stream_id,
+            increment.to_be_bytes().to_vec(),
+        )
+    }
+
+    pub fn rst_stream(stream_id: u32, error_code: u32) -> Self {
+        Frame::new(RST_STREAM, 0, stream_id, error_code.to_be_bytes().to_vec())
+    }
+
+    pub fn goaway(last_stream_id: u32, error_code: u32) -> Self {
+        let mut payload = Vec::with_capacity(8);
+        payload.extend_from_slice(&last_stream_id.to_be_bytes());
+        payload.extend_from_slice(&error_code.to_be_bytes());
+        Frame::new(GOAWAY, 0, 0, payload)
+    }
+
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag == flag
+    }
+
+    /// Try to split a complete frame off the front of `buf`.
+    ///
+    /// Returns `Ok(None)` if `buf` does not yet hold a full frame. Frames larger
+    /// than `max_frame_size` are a connection error.
+    pub fn parse(buf: &mut Vec<u8>, max_frame_size: u32) -> io::Result<Option<Frame>> {
+        if buf.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        if len > max_frame_size {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("HTTP/2 frame of {} bytes exceeds maximum frame size", len),
+            ));
+        }
+        let total = HEADER_LEN + len as usize;
+        if buf.len() < total {
+            return Ok(None);
+        }
+        let kind = buf[3];
+        let flags = buf[4];
+        let stream_id = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) & MAX_STREAM_ID;
+        let payload = buf[HEADER_LEN..total].to_vec();
+        buf.drain(..total);
+        Ok(Some(Frame::new(kind, flags, stream_id, payload)))
+    }
+
+    /// Append the wire representation of this frame to `out`.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        let len = (self.payload.len() as u32).to_be_bytes();
+        out.extend_from_slice(&len[1..]);
+        out.push(self.kind);
+        out.push(self.flags);
+        out.extend_from_slice(&self.stream_id.to_be_bytes());
+        out.extend_from_slice(&self.payload);
+    }
+
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.payload.len());
+        self.encode(&mut out);
+        w.write_all(&out)
+    }
+
+    /// The payload with any padding and priority fields removed.
+    pub fn unpadded(&self) -> io::Result<&[u8]> {
+        let mut payload = &self.payload[..];
+        let mut pad_len = 0;
+        if self.has_flag(FLAG_PADDED) {
+            let (first, rest) = payload
+                .split_first()
+                .ok_or_else(|| protocol_error("padded frame without pad length"))?;
+            pad_len = *first as usize;
+            payload = rest;
+        }
+        if self.kind == HEADERS && self.has_flag(FLAG_PRIORITY) {
+            if payload.len() < 5 {
+                return Err(protocol_error("HEADERS frame too short for priority"));
+            }
+            payload = &payload[5..];
+        }
+        if pad_len > payload.len() {
+            return Err(protocol_error("frame padding exceeds payload"));
+        }
+        Ok(&payload[..payload.len() - pad_len])
+    }
+
+    /// Decode the payload of a SETTINGS frame into (identifier, value) pairs.
+    pub fn parse_settings(&self) -> io::Result<Vec<(u16, u32)>> {
+        let chunks = self.payload.chunks_exact(6);
+        if !chunks.remainder().is_empty() {
+            return Err(protocol_error("SETTINGS payload not a multiple of 6"));
+        }
+        Ok(chunks
+            .map(|c| {
+                (
+                    u16::from_be_bytes([c[0], c[1]]),
+                    u32::from_be_bytes([c[2], c[3], c[4], c[5]]),
+                )
+            })
+            .collect())
+    }
+
+    /// Read a 32 bit big-endian value at `offset` in the payload.
+    pub fn u32_at(&self, offset: usize) -> io::Result<u32> {
+        match self.payload.get(offset..offset + 4) {
+            Some(b) => Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]])),
+            None => Err(protocol_error("frame payload too short")),
+        }
+    }
+}
+
+pub(crate) fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("HTTP/2: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let frame = Frame::new(DATA, FLAG_END_STREAM, 3, b"hello".to_vec());
+        let mut buf = vec![];
+        frame.encode(&mut buf);
+        assert_eq!(buf.len(), HEADER_LEN + 5);
+        // A partial frame is left alone.
+        let mut partial = buf[..10].to_vec();
+        assert_eq!(
+            Frame::parse(&mut partial, DEFAULT_MAX_FRAME_SIZE).unwrap(),
+            None
+        );
+        assert_eq!(partial.len(), 10);
+        buf.extend_from_slice(b"trailing");
+        let parsed = Frame::parse(&mut buf, DEFAULT_MAX_FRAME_SIZE).unwrap();
+        assert_eq!(parsed, Some(frame));
+        assert_eq!(buf, b"trailing");
+    }
+
+    #[test]
+    fn too_large() {
+        let frame = Frame::new(DATA, 0, 1, vec![0; 100]);
+        let mut buf = vec![];
+        frame.encode(&mut buf);
+        assert!(Frame::parse(&mut buf, 99).is_err());
+    }
+
+    #[test]
+    fn unpadded() {
+        let frame = Frame::new(DATA, FLAG_PADDED, 1, vec![2, b'a', b'b', 0, 0]);
+        assert_eq!(frame.unpadded().unwrap(), b"ab");
+        let frame = Frame::new(
+            HEADERS,
+            FLAG_PADDED | FLAG_PRIORITY,
+            1,
+            vec![1, 0, 0, 0, 0, 16, b'x', 0],
+        );
+        assert_eq!(frame.unpadded().unwrap(), b"x");
+        let frame = Frame::new(DATA, FLAG_PADDED, 1, vec![5, b'a']);
+        assert!(frame.unpadded().is_err());
+    }
+
+    #[test]
+    fn settings() {
+        let frame = Frame::settings(&[
+            (SETTINGS_ENABLE_PUSH, 0),
+            (SETTINGS_MAX_FRAME_SIZE, 1 << 20),
+        ]);
+        assert_eq!(
+            frame.parse_settings().unwrap(),
+            vec![
+                (SETTINGS_ENABLE_PUSH, 0),
+                (SETTINGS_MAX_FRAME_SIZE, 1 << 20)
+            ]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! HTTP/2 transport, used for https connections where the server picks `h2` via ALPN.
+//!
+//! Requests are multiplexed over one [`Connection`] per host, which is kept in the
+//! agent's [`ConnectionPool`](crate::pool::ConnectionPool). The response body is handed
+//! out as a regular [`Read`], so the blocking [`Response`] API is the same as for HTTP/1.1.
+
+use std::io::{self, Read};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::body::{BodySize, SizedReader};
+use crate::error::Error;
+use crate::header::Header;
+use crate::response::{Response, Trailers};
+use crate::unit::Unit;
+
+mod connection;
+pub(crate) mod frame;
+
+pub(crate) use connection::Connection;
+
+/// Send a request on an HTTP/2 connection and wait for the response headers.
+pub(crate) fn send_request(
+    conn: &Arc<Connection>,
+    unit: &Unit,
+    body: SizedReader,
+) -> Result<Response, Error> {
+    let config = &unit.agent.config;
+    let write_deadline = || deadline_or_timeout(unit.deadline, config.timeout_write);
+    let read_deadline = || deadline_or_timeout(unit.deadline, config.timeout_read);
+
+    let has_body = !matches!(body.size, BodySize::Empty | BodySize::Known(0));
+    let stream_id = conn.open_stream(&unit.http2_fields(), !has_body, write_deadline())?;
+
+    // Make sure the stream is cleaned up if anything below fails.
+    let mut reader = BodyReader {
+        conn: conn.clone(),
+        stream_id,
+        deadline: unit.deadline,
+        timeout_read: config.timeout_read,
+        done: false,
+        trailers: Trailers::default(),
+    };
+
+    if has_body {
+        send_body(conn, stream_id, body, write_deadline)?;
+    }
+
+    let (status, headers) = conn.recv_headers(stream_id, read_deadline())?;
+
+    // Responses to HEAD, and some statuses, never have a body.
+    if unit.method.eq_ignore_ascii_case("head") || status == 204 || status == 304 {
+        reader.finish();
+    }
+
+    let trailers = reader.trailers.clone();
+    Response::from_http2(
+        unit,
+        status,
+        headers,
+        Box::new(reader),
+        trailers,
+        conn.remote_addr,
+        conn.local_addr,
+    )
+}
+
+fn send_body(
+    conn: &Connection,
+    stream_id: u32,
+    mut body: SizedReader,
+    deadline: impl Fn() -> Option<Instant>,
+) -> io::Result<()> {
+    let mut buf = vec![0; 16_384];
+    loop {
+        let n = body.reader.read(&mut buf)?;
+        if n == 0 {
+            if let Some(trailers) = body.trailers.take() {
+                let fields = trailer_fields(trailers())?;
+                return conn.send_trailers(stream_id, &fields, deadline());
+            }
+        }
+        let end_stream = n == 0;
+        let wanted = conn.send_data(stream_id, &buf[..n], end_stream, deadline())?;
+        if end_stream || !wanted {
+            return Ok(());
+        }
+    }
+}
+
+/// Turn trailers into header fields. HTTP/2 field names are lowercase.
+fn trailer_fields(trailers: Vec<Header>) -> io::Result<Vec<(String, Vec<u8>, bool)>> {
+    let mut fields = Vec::with_capacity(trailers.len());
+    for header in trailers {
+        header
+            .validate()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let name = header.name().to_ascii_lowercase();
+        fields.push((name, header.value_raw().to_vec(), false));
+    }
+    Ok(fields)
+}
+
+fn deadline_or_timeout(deadline: Option<Instant>, timeout: Option<Duration>) -> Option<Instant> {
+    deadline.or_else(|| timeout.and_then(|t| Instant::now().checked_add(t)))
+}
+
+/// Reads the DATA of one stream. Dropping it before the end cancels the stream.
+struct BodyReader {
+    conn: Arc<Connection>,
+    stream_id: u32,
+    deadline: Option<Instant>,
+    timeout_read: Option<Duration>,
+    done: bool,
+    trailers: Trailers,
+}
+
+impl BodyReader {
+    fn finish(&mut self) {
+        if !self.done {
+            self.done = true;
+            self.conn.close_stream(self.stream_id);
+        }
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let deadline = deadline_or_timeout(self.deadline, self.timeout_read);
+        let n = self.conn.recv_data(self.stream_id, buf, deadline)?;
+        if n == 0 {
+            self.trailers.set(self.conn.take_trailers(self.stream_id));
+            self.finish();
+        }
+        Ok(n)
+    }
+}
+
+impl Drop for BodyReader {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
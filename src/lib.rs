@@ -155,15 +155,23 @@
 //! * `charset` enables interpreting the charset part of the Content-Type header
 //!    (e.g.  `Content-Type: text/plain; charset=iso-8859-1`). Without this, the
 //!    library defaults to Rust's built in `utf-8`.
-//! * `socks-proxy` enables proxy config using the `socks4://`, `socks4a://`, `socks5://` and `socks://` (equal to `socks5://`) prefix.
+//! * `socks-proxy` enables proxy config using the `socks4://`, `socks4a://`, `socks5://`, `socks5h://` and `socks://` (equal to `socks5://`) prefix.
 //! * `native-tls` enables an adapter so you can pass a `native_tls::TlsConnector` instance
 //!   to `AgentBuilder::tls_connector`. Due to the risk of diamond dependencies accidentally switching on an unwanted
 //!   TLS implementation, `native-tls` is never picked up as a default or used by the crate level
 //!   convenience calls (`ureq::get` etc) – it must be configured on the agent. The `native-certs` feature
 //!   does nothing for `native-tls`.
-//! * `gzip` enables requests of gzip-compressed responses and decompresses them. This is enabled by default.
-//! * `brotli` enables requests brotli-compressed responses and decompresses them.
+//! * `gzip` enables requests of gzip-compressed responses and decompresses them, and lets
+//!   request bodies be gzip-compressed with `compress()`. This is enabled by default.
+//! * `brotli` enables requests brotli-compressed responses and decompresses them, and lets
+//!   request bodies be brotli-compressed with `compress()`.
+//! * `deflate` enables requests of deflate-compressed responses and decompresses them.
+//! * `zstd` enables requests of zstd-compressed responses and decompresses them.
 //! * `http-interop` enables conversion methods to and from `http::Response` and `http::request::Builder`.
+//! * `http2` enables HTTP/2 for https requests. The default rustls config offers `h2` via ALPN, and
+//!   requests to servers that accept it are multiplexed over a single pooled connection per host.
+//! * `websocket` enables WebSocket connections with `Agent::websocket()`.
+//! * `digest-auth` lets proxy credentials answer `Digest` challenges, not only `Basic` ones.
 //!
 //! # Plain requests
 //!
@@ -178,6 +186,7 @@
 //! * [`.send_string()`][Request::send_string()] body as string.
 //! * [`.send_bytes()`][Request::send_bytes()] body as bytes.
 //! * [`.send_form()`][Request::send_form()] key-value pairs as application/x-www-form-urlencoded.
+//! * [`.send_multipart()`][Request::send_multipart()] a [Multipart] form as multipart/form-data.
 //!
 //! # JSON
 //!
@@ -231,6 +240,8 @@
 //! ureq supports two kinds of proxies,  [`HTTP`] ([`CONNECT`]), [`SOCKS4`] and [`SOCKS5`],
 //! the former is always available while the latter must be enabled using the feature
 //! `ureq = { version = "*", features = ["socks-proxy"] }`.
+//! HTTP proxies that expect TLS between the client and the proxy are configured
+//! with the `https://` prefix.
 //!
 //! Proxies settings are configured on an [Agent] (using [AgentBuilder]). All request sent
 //! through the agent will be proxied.
@@ -354,18 +365,29 @@
 //!
 
 mod agent;
+mod auth;
 mod body;
+mod cache;
 mod chunked;
+mod download;
 mod error;
 mod header;
+#[cfg(feature = "http2")]
+mod http2;
 mod middleware;
+mod multipart;
 mod pool;
 mod proxy;
+mod random;
 mod request;
 mod resolve;
 mod response;
+mod retry;
+mod sse;
 mod stream;
 mod unit;
+#[cfg(feature = "websocket")]
+mod websocket;
 
 // rustls is our default tls engine. If the feature is on, it will be
 // used for the shortcut calls the top of the crate (`ureq::get` etc).
@@ -423,14 +445,24 @@ mod http_interop;
 pub use crate::agent::Agent;
 pub use crate::agent::AgentBuilder;
 pub use crate::agent::RedirectAuthHeaders;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub use crate::body::ContentEncoding;
+pub use crate::cache::{Cache, CacheEntry, CacheStorage, DiskStorage, MemoryStorage};
 pub use crate::error::{Error, ErrorKind, OrAnyStatus, Transport};
 pub use crate::header::Header;
 pub use crate::middleware::{Middleware, MiddlewareNext};
-pub use crate::proxy::Proxy;
+pub use crate::multipart::{Multipart, Part};
+pub use crate::proxy::{Proxy, ProxySelector};
 pub use crate::request::{Request, RequestUrl};
 pub use crate::resolve::Resolver;
-pub use crate::response::Response;
-pub use crate::stream::{ReadWrite, TlsConnector};
+#[cfg(feature = "json")]
+pub use crate::response::JsonStream;
+pub use crate::response::{Response, Trailers};
+pub use crate::retry::RetryPolicy;
+pub use crate::sse::{Event, EventSource, Events};
+pub use crate::stream::{Connector, ReadWrite, TlsConnector};
+#[cfg(feature = "websocket")]
+pub use crate::websocket::{CloseFrame, Message, WebSocket};
 
 // re-export
 #[cfg(feature = "cookies")]
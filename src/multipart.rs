@@ -0,0 +1,297 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+
+use crate::random::random_u64;
+
+/// A `multipart/form-data` body, <https://www.rfc-editor.org/rfc/rfc7578>
+///
+/// Send it with [`Request::send_multipart()`](crate::Request::send_multipart). Parts are
+/// streamed in the order they were added. If the size of every part is known, the request
+/// gets a `Content-Length`, otherwise it is sent with the chunked transfer encoding.
+///
+/// ```
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # ureq::is_test(true);
+/// use ureq::{Multipart, Part};
+///
+/// let report = std::io::Cursor::new(b"a,b,c\n1,2,3\n".to_vec());
+/// let form = Multipart::new()
+///     .text("title", "Quarterly report")
+///     .part(
+///         "report",
+///         Part::reader(report)
+///             .file_name("report.csv")
+///             .content_type("text/csv"),
+///     );
+/// let resp = ureq::post("http://httpbin.org/post").send_multipart(form)?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Multipart<'a> {
+    boundary: String,
+    parts: Vec<(String, Part<'a>)>,
+}
+
+impl<'a> Multipart<'a> {
+    /// An empty form with a random boundary.
+    pub fn new() -> Self {
+        Multipart {
+            boundary: random_boundary(),
+            parts: vec![],
+        }
+    }
+
+    /// Add a text field.
+    pub fn text(self, name: &str, value: &str) -> Self {
+        self.part(name, Part::text(value))
+    }
+
+    /// Add the contents of a file, see [`Part::file()`].
+    pub fn file(self, name: &str, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(self.part(name, Part::file(path)?))
+    }
+
+    /// Add a part under the field `name`.
+    pub fn part(mut self, name: &str, part: Part<'a>) -> Self {
+        self.parts.push((name.to_string(), part));
+        self
+    }
+
+    /// The boundary separating the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The value of the `Content-Type` header for this form.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// The encoded body, and its size if all parts are sized.
+    pub(crate) fn into_reader(self) -> (Option<u64>, Box<dyn Read + 'a>) {
+        let mut pieces: Vec<(Box<dyn Read + 'a>, Option<u64>)> = vec![];
+        for (name, part) in self.parts {
+            let mut head = format!(
+                "--{}\r\nContent-Disposition: form-data; name=\"{}\"",
+                self.boundary,
+                escape_quoted(&name)
+            );
+            if let Some(file_name) = &part.file_name {
+                head.push_str(&format!("; filename=\"{}\"", escape_quoted(file_name)));
+            }
+            head.push_str("\r\n");
+            if let Some(content_type) = &part.content_type {
+                head.push_str(&format!(
+                    "Content-Type: {}\r\n",
+                    strip_newlines(content_type)
+                ));
+            }
+            head.push_str("\r\n");
+            pieces.push(bytes(head.into_bytes()));
+            pieces.push((part.reader, part.size));
+            pieces.push(bytes(b"\r\n".to_vec()));
+        }
+        pieces.push(bytes(format!("--{}--\r\n", self.boundary).into_bytes()));
+
+        let size = pieces.iter().map(|(_, size)| *size).sum();
+        let readers = pieces.into_iter().map(|(reader, _)| reader).collect();
+        (size, Box::new(ChainRead { readers }))
+    }
+}
+
+impl Default for Multipart<'_> {
+    fn default() -> Self {
+        Multipart::new()
+    }
+}
+
+impl fmt::Debug for Multipart<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multipart")
+            .field("boundary", &self.boundary)
+            .field("parts", &self.parts)
+            .finish()
+    }
+}
+
+/// A single part of a [`Multipart`] form.
+pub struct Part<'a> {
+    reader: Box<dyn Read + 'a>,
+    size: Option<u64>,
+    file_name: Option<String>,
+    content_type: Option<String>,
+}
+
+impl<'a> Part<'a> {
+    /// A part holding `text`.
+    pub fn text(text: &str) -> Self {
+        let bytes = text.as_bytes().to_vec();
+        Part::sized_reader(Cursor::new(bytes), text.len() as u64)
+    }
+
+    /// A part holding `bytes`.
+    pub fn bytes(bytes: &'a [u8]) -> Self {
+        Part::sized_reader(bytes, bytes.len() as u64)
+    }
+
+    /// A part streamed from `reader`.
+    ///
+    /// The size of the part is unknown, so the form is sent with the chunked
+    /// transfer encoding. Use [`Part::sized_reader()`] when the size is known.
+    pub fn reader(reader: impl Read + 'a) -> Self {
+        Part {
+            reader: Box::new(reader),
+            size: None,
+            file_name: None,
+            content_type: None,
+        }
+    }
+
+    /// A part streamed from `reader`, which yields exactly `size` bytes.
+    pub fn sized_reader(reader: impl Read + 'a, size: u64) -> Self {
+        Part {
+            size: Some(size),
+            ..Part::reader(reader)
+        }
+    }
+
+    /// A part streamed from the file at `path`.
+    ///
+    /// The file name is taken from `path` and the content type is
+    /// `application/octet-stream`, both can be changed.
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let mut part = Part::sized_reader(file, size).content_type("application/octet-stream");
+        part.file_name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned());
+        Ok(part)
+    }
+
+    /// Set the file name sent for this part.
+    pub fn file_name(mut self, file_name: &str) -> Self {
+        self.file_name = Some(file_name.to_string());
+        self
+    }
+
+    /// Set the content type of this part.
+    pub fn content_type(mut self, content_type: &str) -> Self {
+        self.content_type = Some(content_type.to_string());
+        self
+    }
+}
+
+impl fmt::Debug for Part<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Part")
+            .field("size", &self.size)
+            .field("file_name", &self.file_name)
+            .field("content_type", &self.content_type)
+            .finish()
+    }
+}
+
+fn bytes<'a>(bytes: Vec<u8>) -> (Box<dyn Read + 'a>, Option<u64>) {
+    let len = bytes.len() as u64;
+    (Box::new(Cursor::new(bytes)), Some(len))
+}
+
+/// Reads each reader to the end, in turn.
+struct ChainRead<'a> {
+    readers: VecDeque<Box<dyn Read + 'a>>,
+}
+
+impl Read for ChainRead<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while let Some(reader) = self.readers.front_mut() {
+            let n = reader.read(buf)?;
+            if n > 0 {
+                return Ok(n);
+            }
+            self.readers.pop_front();
+        }
+        Ok(0)
+    }
+}
+
+/// Escape a name for a quoted Content-Disposition parameter, the way browsers do.
+fn escape_quoted(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn strip_newlines(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// A boundary that is very unlikely to appear in the parts.
+fn random_boundary() -> String {
+    format!(
+        "------------------------{:016x}{:016x}",
+        random_u64(),
+        random_u64()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(form: Multipart) -> (Option<u64>, String) {
+        let (size, mut reader) = form.into_reader();
+        let mut body = String::new();
+        reader.read_to_string(&mut body).unwrap();
+        (size, body)
+    }
+
+    #[test]
+    fn sized() {
+        let form = Multipart::new().text("a", "1").part(
+            "b",
+            Part::bytes(b"xyz")
+                .file_name("b.txt")
+                .content_type("text/plain"),
+        );
+        let boundary = form.boundary().to_string();
+        let (size, body) = encode(form);
+        let expected = format!(
+            "--{0}\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n\
+             --{0}\r\nContent-Disposition: form-data; name=\"b\"; filename=\"b.txt\"\r\n\
+             Content-Type: text/plain\r\n\r\nxyz\r\n--{0}--\r\n",
+            boundary
+        );
+        assert_eq!(body, expected);
+        assert_eq!(size, Some(expected.len() as u64));
+    }
+
+    #[test]
+    fn unknown_size() {
+        let form = Multipart::new()
+            .text("a", "1")
+            .part("b", Part::reader(&b"xyz"[..]));
+        let (size, body) = encode(form);
+        assert_eq!(size, None);
+        assert!(body.contains("\r\n\r\nxyz\r\n"));
+    }
+
+    #[test]
+    fn escaped_names() {
+        let form = Multipart::new().part("a\"\r\n", Part::text("").file_name("\"b\""));
+        let (_, body) = encode(form);
+        assert!(body.contains("name=\"a%22%0D%0A\"; filename=\"%22b%22\"\r\n"));
+    }
+
+    #[test]
+    fn boundaries_differ() {
+        assert_ne!(Multipart::new().boundary(), Multipart::new().boundary());
+    }
+}
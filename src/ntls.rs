@@ -31,4 +31,9 @@ impl ReadWrite for native_tls::TlsStream<Box<dyn ReadWrite>> {
     fn socket(&self) -> Option<&TcpStream> {
         self.get_ref().socket()
     }
+
+    #[cfg(unix)]
+    fn unix_socket(&self) -> Option<&std::os::unix::net::UnixStream> {
+        self.get_ref().unix_socket()
+    }
 }
@@ -1,9 +1,16 @@
 use std::collections::hash_map::Entry;
 use std::collections::{HashMap, VecDeque};
 use std::io::{self, Read};
+#[cfg(unix)]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "http2")]
+use std::sync::Arc;
 use std::sync::Mutex;
 
 use crate::agent::AgentState;
+#[cfg(feature = "http2")]
+use crate::http2;
 use crate::stream::Stream;
 use crate::{Agent, Proxy};
 
@@ -32,9 +39,14 @@ use url::Url;
 ///  - The length of `lru` is less than or equal to [`Self::max_idle_connections`].
 ///  - The length of `recycle[K]` is less than or equal to [`Self::max_idle_connections_per_host`].
 ///
+/// HTTP/2 connections are kept separately in `http2`. They are shared between requests
+/// rather than taken out of the pool, so there is at most one per [`PoolKey`].
+///
 /// *Internal API*
 pub(crate) struct ConnectionPool {
     inner: Mutex<Inner>,
+    #[cfg(feature = "http2")]
+    http2: Mutex<HashMap<PoolKey, Arc<http2::Connection>>>,
     max_idle_connections: usize,
     max_idle_connections_per_host: usize,
 }
@@ -82,6 +94,8 @@ impl ConnectionPool {
                 recycle: HashMap::new(),
                 lru: VecDeque::new(),
             }),
+            #[cfg(feature = "http2")]
+            http2: Mutex::new(HashMap::new()),
             max_idle_connections,
             max_idle_connections_per_host,
         }
@@ -98,6 +112,43 @@ impl ConnectionPool {
         self.remove(&key)
     }
 
+    /// How the unit::connect tries to get a pooled connection to a unix socket.
+    #[cfg(unix)]
+    pub(crate) fn try_get_unix_connection(&self, path: &Path, url: &Url) -> Option<Stream> {
+        let key = PoolKey::from_unix_socket(path, url);
+        self.remove(&key)
+    }
+
+    /// A shared HTTP/2 connection for the url, if there is one that can take more requests.
+    #[cfg(feature = "http2")]
+    pub(crate) fn try_get_http2(
+        &self,
+        url: &Url,
+        proxy: Option<Proxy>,
+    ) -> Option<Arc<http2::Connection>> {
+        let key = PoolKey::new(url, proxy);
+        let mut http2 = self.http2.lock().unwrap();
+        match http2.get(&key) {
+            Some(conn) if conn.is_usable() => Some(conn.clone()),
+            Some(conn) => {
+                debug!("dropping unusable HTTP/2 connection from pool: {:?}", conn);
+                http2.remove(&key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    #[cfg(feature = "http2")]
+    pub(crate) fn add_http2(&self, url: &Url, proxy: Option<Proxy>, conn: Arc<http2::Connection>) {
+        if self.noop() {
+            return;
+        }
+        let key = PoolKey::new(url, proxy);
+        debug!("adding HTTP/2 connection to pool: {:?} -> {:?}", key, conn);
+        self.http2.lock().unwrap().insert(key, conn);
+    }
+
     fn remove(&self, key: &PoolKey) -> Option<Stream> {
         let mut inner = self.inner.lock().unwrap();
         match inner.recycle.entry(key.clone()) {
@@ -192,12 +243,22 @@ pub(crate) struct PoolKey {
     hostname: String,
     port: Option<u16>,
     proxy: Option<Proxy>,
+    // Connections to a unix socket are keyed by its path instead of the port.
+    unix_socket: Option<PathBuf>,
 }
 
 use std::fmt;
 
 impl fmt::Debug for PoolKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(path) = &self.unix_socket {
+            return f.write_fmt(format_args!(
+                "{}|{}|{}",
+                self.scheme,
+                self.hostname,
+                path.display()
+            ));
+        }
         f.write_fmt(format_args!(
             "{}|{}|{}",
             self.scheme,
@@ -208,22 +269,42 @@ impl fmt::Debug for PoolKey {
 }
 
 impl PoolKey {
-    fn new(url: &Url, proxy: Option<Proxy>) -> Self {
+    pub(crate) fn new(url: &Url, proxy: Option<Proxy>) -> Self {
         let port = url.port_or_known_default();
         PoolKey {
             scheme: url.scheme().to_string(),
             hostname: url.host_str().unwrap_or("").to_string(),
             port,
             proxy,
+            unix_socket: None,
         }
     }
 
+    #[cfg(test)]
     pub(crate) fn from_parts(scheme: &str, hostname: &str, port: u16) -> Self {
         PoolKey {
             scheme: scheme.to_string(),
             hostname: hostname.to_string(),
             port: Some(port),
             proxy: None,
+            unix_socket: None,
+        }
+    }
+
+    /// The key for connections to a unix socket. https connections are kept apart from
+    /// plain ones, and by the hostname their TLS session was made for.
+    #[cfg(unix)]
+    pub(crate) fn from_unix_socket(path: &Path, url: &Url) -> Self {
+        let hostname = match url.scheme() {
+            "https" => url.host_str().unwrap_or("localhost"),
+            _ => "",
+        };
+        PoolKey {
+            scheme: url.scheme().to_string(),
+            hostname: hostname.to_string(),
+            port: None,
+            proxy: None,
+            unix_socket: Some(path.to_path_buf()),
         }
     }
 }
@@ -364,6 +445,7 @@ mod tests {
             hostname,
             port: Some(999),
             proxy: None,
+            unix_socket: None,
         });
         for key in poolkeys.clone() {
             pool.add(&key, NoopStream::stream(PoolReturner::none()));
@@ -388,6 +470,7 @@ mod tests {
             hostname: "example.com".to_string(),
             port: Some(999),
             proxy: None,
+            unix_socket: None,
         };
 
         for _ in 0..pool.max_idle_connections_per_host * 2 {
@@ -0,0 +1,30 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A random number. Not cryptographically secure, but good enough for multipart
+/// boundaries, WebSocket masks and retry jitter.
+pub(crate) fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+    // RandomState is seeded randomly for every instance.
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(now);
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn does_not_repeat() {
+        let values: std::collections::HashSet<_> = (0..100).map(|_| random_u64()).collect();
+        assert_eq!(values.len(), 100);
+    }
+}
@@ -1,14 +1,25 @@
-use std::io::Read;
-use std::{fmt, time};
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::{fmt, thread, time};
 
+use log::debug;
 use url::{form_urlencoded, ParseError, Url};
 
 use crate::agent::Agent;
-use crate::body::Payload;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+use crate::body::{self, ContentEncoding};
+use crate::body::{Payload, SeekBody};
+use crate::download;
 use crate::error::{Error, ErrorKind};
 use crate::header::{self, Header};
 use crate::middleware::MiddlewareNext;
+use crate::multipart::Multipart;
+use crate::proxy::Proxy;
+use crate::response::Limits;
 use crate::unit::{self, Unit};
+#[cfg(feature = "websocket")]
+use crate::websocket::WebSocket;
 use crate::Response;
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -32,6 +43,10 @@ pub struct Request {
     url: String,
     pub(crate) headers: Vec<Header>,
     timeout: Option<time::Duration>,
+    proxy: Option<Option<Proxy>>,
+    limits: Limits,
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    compress: Option<Option<ContentEncoding>>,
 }
 
 impl fmt::Debug for Request {
@@ -52,6 +67,10 @@ impl Request {
             url,
             headers: vec![],
             timeout: None,
+            proxy: None,
+            limits: Limits::default(),
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            compress: None,
         }
     }
 
@@ -62,6 +81,87 @@ impl Request {
         self
     }
 
+    /// Sets the proxy for this request, overriding the agent's proxy configuration,
+    /// including any [`ProxySelector`](crate::ProxySelector). `None` connects directly.
+    ///
+    /// The override also applies to redirects followed by this request. It has no effect
+    /// on an agent with a custom [`Connector`](crate::Connector).
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let proxy = ureq::Proxy::new("http://proxy.corp:3128")?;
+    /// let resp = ureq::get("http://example.com/")
+    ///     .proxy(Some(proxy))
+    ///     .call()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn proxy(mut self, proxy: Option<Proxy>) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Compress the request body, overriding the agent's
+    /// [`compress()`](crate::AgentBuilder::compress) setting. `None` sends the body as is.
+    ///
+    /// Bodies in memory, like those of [`send_bytes()`](Request::send_bytes),
+    /// [`send_string()`](Request::send_string) or [`send_json()`](Request::send_json), are
+    /// compressed before sending and get a Content-Length for the compressed size. Other
+    /// bodies, like those of [`send()`](Request::send),
+    /// [`send_seekable()`](Request::send_seekable) or
+    /// [`send_multipart()`](Request::send_multipart), are compressed while sent, using the
+    /// chunked transfer encoding.
+    ///
+    /// Nothing is done for an empty body, or if the `Content-Encoding` header is already set.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let resp = ureq::post("http://httpbin.org/post")
+    ///     .compress(Some(ureq::ContentEncoding::Gzip))
+    ///     .send_string("a lot of text")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub fn compress(mut self, encoding: Option<ContentEncoding>) -> Self {
+        self.compress = Some(encoding);
+        self
+    }
+
+    /// The largest response body read for this request, overriding the agent's
+    /// [`max_body_size()`](crate::AgentBuilder::max_body_size).
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let resp = ureq::get("http://httpbin.org/bytes/100")
+    ///     .max_body_size(50)
+    ///     .call()?;
+    /// assert!(resp.into_string().is_err());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn max_body_size(mut self, bytes: u64) -> Self {
+        self.limits.max_body_size = Some(bytes);
+        self
+    }
+
+    /// The highest compression ratio accepted for the response body, overriding the
+    /// agent's [`max_compression_ratio()`](crate::AgentBuilder::max_compression_ratio).
+    pub fn max_compression_ratio(mut self, ratio: u32) -> Self {
+        self.limits.max_compression_ratio = Some(ratio);
+        self
+    }
+
+    /// The largest response header block read for this request, overriding the agent's
+    /// [`max_header_size()`](crate::AgentBuilder::max_header_size).
+    pub fn max_header_size(mut self, bytes: usize) -> Self {
+        self.limits.max_header_size = Some(bytes);
+        self
+    }
+
     /// Sends the request with no body and blocks the caller until done.
     ///
     /// Use this with GET, HEAD, OPTIONS or TRACE. It sends neither
@@ -92,32 +192,53 @@ impl Request {
 
     /// Add Accept-Encoding header with supported values, unless user has
     /// already set this header or is requesting a specific byte-range.
-    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    #[cfg(any(
+        feature = "gzip",
+        feature = "brotli",
+        feature = "deflate",
+        feature = "zstd"
+    ))]
     fn add_accept_encoding(&mut self) {
         let should_add = !self.headers.iter().map(|h| h.name()).any(|name| {
             name.eq_ignore_ascii_case("accept-encoding") || name.eq_ignore_ascii_case("range")
         });
         if should_add {
-            const GZ: bool = cfg!(feature = "gzip");
-            const BR: bool = cfg!(feature = "brotli");
-            const ACCEPT: &str = match (GZ, BR) {
-                (true, true) => "gzip, br",
-                (true, false) => "gzip",
-                (false, true) => "br",
-                (false, false) => "identity", // unreachable due to cfg feature on this fn
-            };
-            self.headers.push(Header::new("accept-encoding", ACCEPT));
+            let accept: Vec<&str> = [
+                (cfg!(feature = "gzip"), "gzip"),
+                (cfg!(feature = "deflate"), "deflate"),
+                (cfg!(feature = "brotli"), "br"),
+                (cfg!(feature = "zstd"), "zstd"),
+            ]
+            .iter()
+            .filter(|(enabled, _)| *enabled)
+            .map(|(_, coding)| *coding)
+            .collect();
+            self.headers
+                .push(Header::new("accept-encoding", &accept.join(", ")));
         }
     }
 
-    #[cfg_attr(not(any(feature = "gzip", feature = "brotli")), allow(unused_mut))]
+    #[cfg_attr(
+        not(any(
+            feature = "gzip",
+            feature = "brotli",
+            feature = "deflate",
+            feature = "zstd"
+        )),
+        allow(unused_mut)
+    )]
     fn do_call(mut self, payload: Payload) -> Result<Response> {
         for h in &self.headers {
             h.validate()?;
         }
         let url = self.parse_url()?;
 
-        #[cfg(any(feature = "gzip", feature = "brotli"))]
+        #[cfg(any(
+            feature = "gzip",
+            feature = "brotli",
+            feature = "deflate",
+            feature = "zstd"
+        ))]
         self.add_accept_encoding();
 
         let deadline = match self.timeout.or(self.agent.config.timeout) {
@@ -136,21 +257,65 @@ impl Request {
             }
         };
 
+        let response = match &self.agent.config.retry {
+            Some(policy) if policy.allows_method(&self.method) => {
+                let start = time::Instant::now();
+                let mut payload = payload;
+                let mut attempt = 1;
+                loop {
+                    // Bodies read from a stream can only be sent once.
+                    let replay = payload.try_clone();
+                    let result = self.clone().call_chain(payload, &url, deadline);
+                    let (delay, replay) =
+                        match (policy.delay(&result, attempt, start, deadline), replay) {
+                            (Some(delay), Some(replay)) => (delay, replay),
+                            _ => break result,
+                        };
+                    drop(result);
+                    debug!("retrying {} {} in {:?}", self.method, url, delay);
+                    thread::sleep(delay);
+                    payload = replay;
+                    attempt += 1;
+                }
+            }
+            _ => self.call_chain(payload, &url, deadline),
+        }?;
+
+        if response.status() >= 400 {
+            Err(Error::Status(response.status(), response))
+        } else {
+            Ok(response)
+        }
+    }
+
+    /// Send the request through the middleware chain, once.
+    fn call_chain(
+        self,
+        payload: Payload,
+        url: &Url,
+        deadline: Option<time::Instant>,
+    ) -> Result<Response> {
         let request_fn = |req: Request| {
-            let reader = payload.into_read();
-            let unit = Unit::new(
-                &req.agent,
-                &req.method,
-                &url,
-                req.headers,
-                &reader,
-                deadline,
-            );
+            #[cfg_attr(not(any(feature = "gzip", feature = "brotli")), allow(unused_mut))]
+            let mut headers = req.headers;
+            #[cfg_attr(not(any(feature = "gzip", feature = "brotli")), allow(unused_mut))]
+            let mut reader = payload.into_read();
+
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            if let Some(encoding) = req.compress.unwrap_or(req.agent.config.compress) {
+                reader = body::compress(reader, encoding, &mut headers)?;
+            }
+
+            let mut unit = Unit::new(&req.agent, &req.method, url, headers, &reader, deadline);
+            if let Some(proxy) = req.proxy {
+                unit.override_proxy(proxy);
+            }
+            unit.limits = req.limits.or(unit.limits);
 
             unit::connect(unit, true, reader).map_err(|e| e.url(url.clone()))
         };
 
-        let response = if !self.agent.state.middleware.is_empty() {
+        if !self.agent.state.middleware.is_empty() {
             // Clone agent to get a local copy with same lifetime as Payload
             let agent = self.agent.clone();
             let chain = &mut agent.state.middleware.iter().map(|mw| mw.as_ref());
@@ -160,16 +325,10 @@ impl Request {
             let next = MiddlewareNext { chain, request_fn };
 
             // // Run middleware chain
-            next.handle(self)?
+            next.handle(self)
         } else {
             // Run the request_fn without any further indirection.
-            request_fn(self)?
-        };
-
-        if response.status() >= 400 {
-            Err(Error::Status(response.status(), response))
-        } else {
-            Ok(response)
+            request_fn(self)
         }
     }
 
@@ -272,6 +431,100 @@ impl Request {
         self.do_call(Payload::Bytes(&encoded.into_bytes()))
     }
 
+    /// Send a `multipart/form-data` body, see [`Multipart`](crate::Multipart).
+    ///
+    /// The `Content-Type` header is implicitly set to multipart/form-data with the
+    /// form's boundary. The `Content-Length` header is implicitly set when the size
+    /// of every part is known, otherwise the chunked transfer encoding is used.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let form = ureq::Multipart::new()
+    ///     .text("name", "ureq")
+    ///     .part("logo", ureq::Part::bytes(b"\x89PNG").file_name("logo.png"));
+    /// let resp = ureq::post("http://httpbin.org/post")
+    ///     .send_multipart(form)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_multipart(mut self, form: Multipart<'_>) -> Result<Response> {
+        if self.header("Content-Type").is_none() {
+            self = self.set("Content-Type", &form.content_type());
+        }
+        self.do_call(Payload::Multipart(form))
+    }
+
+    /// Open a WebSocket connection with this request, see
+    /// [`Agent::websocket()`](crate::Agent::websocket).
+    ///
+    /// The URL may use the `ws` and `wss` schemes. Headers set on the request, such as
+    /// `Sec-WebSocket-Protocol`, are sent with the handshake.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["websocket"] }`
+    #[cfg(feature = "websocket")]
+    pub fn websocket(mut self) -> Result<WebSocket> {
+        let mut url = self.parse_url()?;
+        let scheme = match url.scheme() {
+            "ws" => Some("http"),
+            "wss" => Some("https"),
+            _ => None,
+        };
+        if let Some(scheme) = scheme {
+            // Both are special schemes, so this can't fail.
+            url.set_scheme(scheme).ok();
+            self.url = url.to_string();
+        }
+        crate::websocket::handshake(self)
+    }
+
+    /// Download the response body to `writer`, resuming it when the connection is lost.
+    ///
+    /// When reading the body fails, the request is made again with `Range: bytes=N-` for
+    /// the bytes not yet written, and with `If-Range` when the response had a strong
+    /// `ETag` or a `Last-Modified` date. A `206 Partial Content` response is appended to
+    /// `writer` once its `Content-Range` is checked. A server that ignores the range sends
+    /// the whole body again, and the bytes already written are skipped. If the resource
+    /// changed meanwhile, `writer` can't start over, and an error is returned; see
+    /// [`download_to_file()`](Request::download_to_file) which handles that case.
+    ///
+    /// Up to 3 attempts in a row may fail before giving up. The body is requested without
+    /// `Content-Encoding` unless the request sets `Accept-Encoding`. Returns the number of
+    /// bytes written.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let mut body = vec![];
+    /// let len = ureq::get("http://example.com/artifact.tar")
+    ///     .download(&mut body)?;
+    /// assert_eq!(len as usize, body.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn download(self, writer: impl Write) -> Result<u64> {
+        download::download(self, &mut download::Append(writer))
+    }
+
+    /// Download the response body to the file at `path`, resuming it when the connection
+    /// is lost.
+    ///
+    /// The file is created, or truncated if it exists. This works like
+    /// [`download()`](Request::download), except that the download starts over if the
+    /// resource changed while it was interrupted.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// let len = ureq::get("http://example.com/artifact.tar")
+    ///     .download_to_file("artifact.tar")?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn download_to_file(self, path: impl AsRef<Path>) -> Result<u64> {
+        let mut file = File::create(path)?;
+        download::download(self, &mut file)
+    }
+
     /// Send data from a reader.
     ///
     /// If no Content-Length and Transfer-Encoding header has been set, it uses the [chunked transfer encoding](https://tools.ietf.org/html/rfc7230#section-4.1).
@@ -295,6 +548,91 @@ impl Request {
         self.do_call(Payload::Reader(Box::new(reader)))
     }
 
+    /// Send data from a reader that can seek, such as a [`File`](std::fs::File).
+    ///
+    /// The body runs from the current position of the reader to its end, and is sent with
+    /// a Content-Length. Unlike [`send()`](Request::send), the reader is rewound to send
+    /// the body again, when following a 307 or 308 redirect, or when retrying the request.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let read = Cursor::new(vec![0x20; 100]);
+    /// let resp = ureq::put("http://httpbin.org/put")
+    ///     .send_seekable(read)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_seekable<'a>(self, reader: impl Read + Seek + 'a) -> Result<Response> {
+        let body = SeekBody::new(reader)?;
+        self.do_call(Payload::Seekable(body))
+    }
+
+    /// Send data from a reader, followed by trailer fields.
+    ///
+    /// `trailers` is called once the reader is exhausted, so the trailers can carry values
+    /// computed while sending the body, like a checksum. `names` are the fields it will
+    /// return, announced in the `Trailer` header unless that is already set.
+    ///
+    /// Over HTTP/1.1, trailers are part of the
+    /// [chunked transfer encoding](https://tools.ietf.org/html/rfc7230#section-4.1.2), so
+    /// the body is always chunked. Setting a Content-Length, or a Transfer-Encoding that
+    /// doesn't end in `chunked`, is an error of kind [`ErrorKind::BadHeader`].
+    ///
+    /// ```
+    /// use std::cell::Cell;
+    /// use std::io::{self, Cursor, Read};
+    ///
+    /// // Counts the bytes read through it.
+    /// struct Counting<'a, R>(R, &'a Cell<usize>);
+    ///
+    /// impl<R: Read> Read for Counting<'_, R> {
+    ///     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    ///         let n = self.0.read(buf)?;
+    ///         self.1.set(self.1.get() + n);
+    ///         Ok(n)
+    ///     }
+    /// }
+    ///
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let sent = Cell::new(0);
+    /// let read = Counting(Cursor::new(vec![0x20; 100]), &sent);
+    ///
+    /// let resp = ureq::post("http://httpbin.org/post")
+    ///     .send_with_trailers(read, &["X-Sent"], || {
+    ///         vec![ureq::Header::new("X-Sent", &sent.get().to_string())]
+    ///     })?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn send_with_trailers<'a>(
+        self,
+        reader: impl Read + 'a,
+        names: &[&str],
+        trailers: impl FnOnce() -> Vec<Header> + 'a,
+    ) -> Result<Response> {
+        let chunked = self.header("transfer-encoding").map(|enc| {
+            let last = enc.rsplit(',').next().unwrap_or_default();
+            last.trim().eq_ignore_ascii_case("chunked")
+        });
+        if chunked == Some(false) || (chunked.is_none() && self.has("content-length")) {
+            return Err(ErrorKind::BadHeader.msg(
+                "trailers need a chunked body, without Content-Length or another Transfer-Encoding",
+            ));
+        }
+        let request = if names.is_empty() || self.has("trailer") {
+            self
+        } else {
+            self.set("Trailer", &names.join(", "))
+        };
+        request.do_call(Payload::ReaderWithTrailers(
+            Box::new(reader),
+            Box::new(trailers),
+        ))
+    }
+
     /// Set a header field.
     ///
     /// ```
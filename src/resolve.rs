@@ -1,13 +1,36 @@
 use std::fmt;
 use std::io;
 use std::net::{SocketAddr, ToSocketAddrs};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Instant;
+
+use crate::stream::{io_err_timeout, time_until_deadline};
 
 /// A custom resolver to override the default DNS behavior.
 pub trait Resolver: Send + Sync {
     fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>>;
+
+    /// Like [`Resolver::resolve`], but giving up at `deadline`, if there is one.
+    ///
+    /// This is what ureq calls, with the deadline from the connect timeout or the overall
+    /// timeout of the request. A resolver that gives up should return an error of kind
+    /// [`io::ErrorKind::TimedOut`]. The default implementation ignores the deadline,
+    /// and calls [`Resolver::resolve`].
+    fn resolve_with_deadline(
+        &self,
+        netloc: &str,
+        deadline: Option<Instant>,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let _ = deadline;
+        self.resolve(netloc)
+    }
 }
 
+/// The default resolver, using [`ToSocketAddrs`].
+///
+/// The lookup can't be interrupted, so to honor a deadline it is done on a helper
+/// thread, which is abandoned if the deadline passes.
 #[derive(Debug)]
 pub(crate) struct StdResolver;
 
@@ -15,6 +38,40 @@ impl Resolver for StdResolver {
     fn resolve(&self, netloc: &str) -> io::Result<Vec<SocketAddr>> {
         ToSocketAddrs::to_socket_addrs(netloc).map(|iter| iter.collect())
     }
+
+    fn resolve_with_deadline(
+        &self,
+        netloc: &str,
+        deadline: Option<Instant>,
+    ) -> io::Result<Vec<SocketAddr>> {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return self.resolve(netloc),
+        };
+        // ip addresses don't need a lookup, and can't block.
+        if let Ok(addr) = netloc.parse::<SocketAddr>() {
+            return Ok(vec![addr]);
+        }
+
+        let timeout = time_until_deadline(deadline)?;
+        let (tx, rx) = mpsc::channel();
+        let owned = netloc.to_string();
+        thread::spawn(move || {
+            // the receiver is gone if the deadline passed.
+            let _ = tx.send(StdResolver.resolve(&owned));
+        });
+        match rx.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(mpsc::RecvTimeoutError::Timeout) => Err(io_err_timeout(format!(
+                "timed out after {:?} resolving {}",
+                timeout, netloc
+            ))),
+            Err(mpsc::RecvTimeoutError::Disconnected) => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "resolver thread panicked",
+            )),
+        }
+    }
 }
 
 impl<F> Resolver for F
@@ -52,3 +109,32 @@ impl std::ops::Deref for ArcResolver {
         self.0.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn std_resolver_with_deadline() {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let addrs = StdResolver
+            .resolve_with_deadline("localhost:80", Some(deadline))
+            .unwrap();
+        assert!(!addrs.is_empty());
+
+        let addrs = StdResolver
+            .resolve_with_deadline("127.0.0.1:80", Some(deadline))
+            .unwrap();
+        assert_eq!(addrs, vec!["127.0.0.1:80".parse().unwrap()]);
+    }
+
+    #[test]
+    fn std_resolver_deadline_passed() {
+        let deadline = Instant::now() - Duration::from_millis(1);
+        let err = StdResolver
+            .resolve_with_deadline("localhost:80", Some(deadline))
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
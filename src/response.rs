@@ -2,6 +2,8 @@ use std::io::{self, Cursor, Read};
 use std::net::SocketAddr;
 use std::num::NonZeroUsize;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::{fmt, io::BufRead};
 
 use log::debug;
@@ -12,12 +14,17 @@ use crate::chunked::Decoder as ChunkDecoder;
 use crate::error::{Error, ErrorKind::BadStatus};
 use crate::header::{get_all_headers, get_header, Header, HeaderLine};
 use crate::pool::{PoolReturnRead, PoolReturner};
-use crate::stream::{DeadlineStream, ReadOnlyStream, Stream};
+use crate::sse::Events;
+use crate::stream::{DeadlineStream, ReadOnlyStream, ReadWrite, Stream};
 use crate::unit::Unit;
 use crate::{stream, Agent, ErrorKind};
 
 #[cfg(feature = "json")]
 use serde::de::DeserializeOwned;
+#[cfg(feature = "json")]
+use std::io::BufReader;
+#[cfg(feature = "json")]
+use std::marker::PhantomData;
 
 #[cfg(feature = "charset")]
 use encoding_rs::Encoding;
@@ -28,13 +35,16 @@ use flate2::read::MultiGzDecoder;
 #[cfg(feature = "brotli")]
 use brotli_decompressor::Decompressor as BrotliDecoder;
 
+#[cfg(feature = "zstd")]
+use zstd::stream::read::Decoder as ZstdDecoder;
+
 pub const DEFAULT_CONTENT_TYPE: &str = "text/plain";
 pub const DEFAULT_CHARACTER_SET: &str = "utf-8";
 const INTO_STRING_LIMIT: usize = 10 * 1_024 * 1_024;
 // Follow the example of curl and limit a single header to 100kB:
 // https://curl.se/libcurl/c/CURLOPT_HEADERFUNCTION.html
-const MAX_HEADER_SIZE: usize = 100 * 1_024;
-const MAX_HEADER_COUNT: usize = 100;
+pub(crate) const MAX_HEADER_SIZE: usize = 100 * 1_024;
+pub(crate) const MAX_HEADER_COUNT: usize = 100;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 enum ConnectionOption {
@@ -49,6 +59,26 @@ enum BodyType {
     CloseDelimited,
 }
 
+/// Limits on the size of responses, see [`AgentBuilder::max_body_size()`](crate::AgentBuilder::max_body_size)
+/// and friends. `None` means no limit.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub max_body_size: Option<u64>,
+    pub max_compression_ratio: Option<u32>,
+    pub max_header_size: Option<usize>,
+}
+
+impl Limits {
+    /// These limits, with any unset limit taken from `other`.
+    pub(crate) fn or(self, other: Limits) -> Limits {
+        Limits {
+            max_body_size: self.max_body_size.or(other.max_body_size),
+            max_compression_ratio: self.max_compression_ratio.or(other.max_compression_ratio),
+            max_header_size: self.max_header_size.or(other.max_header_size),
+        }
+    }
+}
+
 /// Response instances are created as results of firing off requests.
 ///
 /// The `Response` is used to read response headers and decide what to do with the body.
@@ -92,6 +122,55 @@ pub struct Response {
     ///
     /// If this response was not redirected, the history is empty.
     pub(crate) history: Vec<Url>,
+    /// Trailer fields, filled in once the body has been read to the end.
+    pub(crate) trailers: Trailers,
+    /// The connection, after a `101 Switching Protocols`.
+    pub(crate) upgraded: Option<Box<dyn ReadWrite>>,
+}
+
+/// The trailer fields of a response.
+///
+/// Trailers are header fields sent after the body, at the end of a chunked
+/// HTTP/1.1 response or in a final HEADERS frame in HTTP/2. They are only known once
+/// the body has been read to the end, so this is a handle that can be kept around
+/// while the [`Response`] is consumed by [`into_reader()`](Response::into_reader)
+/// or [`into_string()`](Response::into_string).
+///
+/// Until the end of the body is reached, and for responses without trailers,
+/// the handle is empty.
+#[derive(Clone, Default)]
+pub struct Trailers(Arc<Mutex<Vec<Header>>>);
+
+impl Trailers {
+    pub(crate) fn set(&self, trailers: Vec<Header>) {
+        *self.0.lock().unwrap() = trailers;
+    }
+
+    /// The value of the named trailer field, if present. Case insensitive.
+    pub fn header(&self, name: &str) -> Option<String> {
+        get_header(&self.0.lock().unwrap(), name).map(|v| v.to_string())
+    }
+
+    /// All values of the named trailer field. Case insensitive.
+    pub fn all(&self, name: &str) -> Vec<String> {
+        get_all_headers(&self.0.lock().unwrap(), name)
+            .into_iter()
+            .map(|v| v.to_string())
+            .collect()
+    }
+
+    /// All trailer fields, in the order they were received.
+    pub fn headers(&self) -> Vec<Header> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl fmt::Debug for Trailers {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.lock().unwrap().iter())
+            .finish()
+    }
 }
 
 /// index into status_line where we split: HTTP/1.1 200 OK
@@ -245,6 +324,29 @@ impl Response {
         self.local_addr
     }
 
+    /// A handle to the trailer fields of this response.
+    ///
+    /// The trailers are only filled in once the body has been read to the end, so
+    /// take the handle before consuming the response.
+    ///
+    /// ```
+    /// # fn main() -> Result<(), ureq::Error> {
+    /// # ureq::is_test(true);
+    /// let resp = ureq::get("http://example.com/").call()?;
+    /// let trailers = resp.trailers();
+    ///
+    /// let body = resp.into_string()?;
+    ///
+    /// if let Some(status) = trailers.header("grpc-status") {
+    ///     println!("grpc-status: {}", status);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn trailers(&self) -> Trailers {
+        self.trailers.clone()
+    }
+
     /// Turn this response into a `impl Read` of the body.
     ///
     /// 1. If `Transfer-Encoding: chunked`, the returned reader will unchunk it
@@ -285,6 +387,64 @@ impl Response {
         self.reader
     }
 
+    /// Take over the connection after a `101 Switching Protocols` response.
+    ///
+    /// The request must ask for the upgrade itself, with the `Connection: Upgrade`
+    /// and `Upgrade` headers, and is then sent over HTTP/1.1 even when the `http2`
+    /// feature is enabled. The returned stream starts with any bytes the server
+    /// sent right after the response head, and is never returned to the connection
+    /// pool. Fails with [`ErrorKind::BadStatus`] for any other response.
+    ///
+    /// ```no_run
+    /// use std::io::{Read, Write};
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # ureq::is_test(true);
+    /// let resp = ureq::get("http://example.com/chat")
+    ///     .set("Connection", "Upgrade")
+    ///     .set("Upgrade", "my-protocol")
+    ///     .call()?;
+    ///
+    /// let mut stream = resp.into_upgraded()?;
+    /// stream.write_all(b"hello")?;
+    /// let mut reply = [0; 5];
+    /// stream.read_exact(&mut reply)?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_upgraded(self) -> Result<Box<dyn ReadWrite>, Error> {
+        match self.upgraded {
+            Some(stream) => Ok(stream),
+            None => Err(BadStatus.msg(format!(
+                "expected 101 Switching Protocols, got {}",
+                self.status
+            ))),
+        }
+    }
+
+    /// Turn this response into an iterator over Server-Sent Events.
+    ///
+    /// The body is read as a `text/event-stream` while iterating. See
+    /// [`Agent::event_source()`](crate::Agent::event_source) for a stream that
+    /// reconnects when the connection is lost.
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # ureq::is_test(true);
+    /// let events = ureq::get("http://example.com/stream")
+    ///     .set("Accept", "text/event-stream")
+    ///     .call()?
+    ///     .into_sse();
+    /// for event in events {
+    ///     let event = event?;
+    ///     println!("{}: {}", event.event(), event.data());
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn into_sse(self) -> Events {
+        Events::new(self.into_reader())
+    }
+
     // Determine what to do with the connection after we've read the body.
     fn connection_option(
         response_version: &str,
@@ -354,8 +514,9 @@ impl Response {
         mut stream: DeadlineStream,
         unit: &Unit,
         body_type: BodyType,
-        compression: Option<Compression>,
+        compression: &[Compression],
         connection_option: ConnectionOption,
+        trailers: &Trailers,
     ) -> Box<dyn Read + Send + Sync + 'static> {
         if connection_option == ConnectionOption::Close {
             stream.inner_mut().set_unpoolable();
@@ -373,7 +534,8 @@ impl Response {
             // to the connection pool.
             BodyType::Chunked => {
                 debug!("Chunked body in response");
-                Box::new(PoolReturnRead::new(ChunkDecoder::new(stream)))
+                let decoder = ChunkDecoder::with_trailers(stream, trailers.clone());
+                Box::new(PoolReturnRead::new(decoder))
             }
             // Responses with a content-length header means we should limit the reading
             // of the body to the number of bytes in the header. Once done, we can
@@ -415,10 +577,7 @@ impl Response {
             }
         };
 
-        match compression {
-            None => body_reader,
-            Some(c) => c.wrap_reader(body_reader),
-        }
+        decode_body(body_reader, compression, &unit.limits)
     }
 
     /// Turn this response into a String of the response body. By default uses `utf-8`,
@@ -530,22 +689,50 @@ impl Response {
     /// ```
     #[cfg(feature = "json")]
     pub fn into_json<T: DeserializeOwned>(self) -> io::Result<T> {
-        use crate::stream::io_err_timeout;
-
         let reader = self.into_reader();
-        serde_json::from_reader(reader).map_err(|e| {
-            // This is to unify TimedOut io::Error in the API.
-            if let Some(kind) = e.io_error_kind() {
-                if kind == io::ErrorKind::TimedOut {
-                    return io_err_timeout(e.to_string());
-                }
-            }
+        serde_json::from_reader(reader).map_err(json_error)
+    }
 
-            io::Error::new(
-                io::ErrorKind::InvalidData,
-                format!("Failed to read JSON: {}", e),
-            )
-        })
+    /// Turn this response into an iterator over the records of a newline-delimited
+    /// JSON body, also known as NDJSON or JSON Lines.
+    ///
+    /// Records are read and deserialized one line at a time, so the body does not
+    /// need to fit in memory. Blank lines are skipped. A record that fails to
+    /// deserialize is yielded as an error, and iteration can continue with the next
+    /// line. The connection is returned to the pool once the body has been read
+    /// to the end.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["json"] }`
+    ///
+    /// ```no_run
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # ureq::is_test(true);
+    /// use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct LogLine {
+    ///     level: String,
+    ///     message: String,
+    /// }
+    ///
+    /// let records = ureq::get("http://example.com/logs.ndjson")
+    ///     .call()?
+    ///     .into_json_stream::<LogLine>();
+    /// for record in records {
+    ///     let record = record?;
+    ///     println!("{}: {}", record.level, record.message);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn into_json_stream<T: DeserializeOwned>(self) -> JsonStream<T> {
+        JsonStream {
+            reader: BufReader::new(self.into_reader()),
+            line: vec![],
+            done: false,
+            _record: PhantomData,
+        }
     }
 
     /// Create a response from a Read trait impl.
@@ -562,41 +749,36 @@ impl Response {
     ///
     /// assert_eq!(resp.status(), 401);
     pub(crate) fn do_from_stream(stream: Stream, unit: Unit) -> Result<Response, Error> {
-        let remote_addr = stream.remote_addr;
+        //
+        // HTTP/1.1 200 OK\r\n
+        let mut stream = stream::DeadlineStream::new(stream, unit.deadline);
+        let (status_line, _) = read_status_line(&mut stream, false)?;
+        Self::do_from_status_line(stream, unit, status_line)
+    }
+
+    /// Read the rest of a response, after its status line was read off the stream.
+    pub(crate) fn do_from_status_line(
+        mut stream: DeadlineStream,
+        unit: Unit,
+        status_line: String,
+    ) -> Result<Response, Error> {
+        let remote_addr = stream.inner_ref().remote_addr;
 
-        let local_addr = match stream.socket() {
+        let local_addr = match stream.inner_ref().socket() {
             Some(sock) => sock.local_addr().map_err(Error::from)?,
             None => std::net::SocketAddrV4::new(std::net::Ipv4Addr::new(127, 0, 0, 1), 0).into(),
         };
 
-        //
-        // HTTP/1.1 200 OK\r\n
-        let mut stream = stream::DeadlineStream::new(stream, unit.deadline);
-
-        // The status line we can ignore non-utf8 chars and parse as_str_lossy().
-        let status_line = read_next_line(&mut stream, "the status line")?.into_string_lossy();
         let (index, status) = parse_status_line(status_line.as_str())?;
         let http_version = &status_line.as_str()[0..index.http_version];
 
-        let mut headers: Vec<Header> = Vec::new();
-        while headers.len() <= MAX_HEADER_COUNT {
-            let line = read_next_line(&mut stream, "a header")?;
-            if line.is_empty() {
-                break;
-            }
-            if let Ok(header) = line.into_header() {
-                headers.push(header);
-            }
-        }
-
-        if headers.len() > MAX_HEADER_COUNT {
-            return Err(ErrorKind::BadHeader.msg(
-                format!("more than {} header fields in response", MAX_HEADER_COUNT).as_str(),
-            ));
-        }
+        let mut headers = read_headers(
+            &mut stream,
+            status_line.len() + 2,
+            unit.limits.max_header_size,
+        )?;
 
-        let compression =
-            get_header(&headers, "content-encoding").and_then(Compression::from_header_value);
+        let compression = Compression::from_headers(&headers);
 
         let connection_option =
             Self::connection_option(http_version, get_header(&headers, "connection"));
@@ -604,12 +786,30 @@ impl Response {
         let body_type = Self::body_type(&unit.method, status, http_version, &headers);
 
         // remove Content-Encoding and length due to automatic decompression
-        if compression.is_some() {
+        if !compression.is_empty() {
             headers.retain(|h| !h.is_name("content-encoding") && !h.is_name("content-length"));
         }
 
-        let reader =
-            Self::stream_to_reader(stream, &unit, body_type, compression, connection_option);
+        let trailers = Trailers::default();
+        let mut upgraded: Option<Box<dyn ReadWrite>> = None;
+        let reader: Box<dyn Read + Send + Sync + 'static> = if status == 101 {
+            // The connection now speaks another protocol, and is never pooled.
+            let mut stream: Stream = stream.into();
+            stream.set_unpoolable();
+            stream.set_read_timeout(unit.agent.config.timeout_read)?;
+            stream.set_write_timeout(unit.agent.config.timeout_write)?;
+            upgraded = Some(Box::new(stream));
+            Box::new(io::empty())
+        } else {
+            Self::stream_to_reader(
+                stream,
+                &unit,
+                body_type,
+                &compression,
+                connection_option,
+                &trailers,
+            )
+        };
 
         let url = unit.url.clone();
 
@@ -623,10 +823,91 @@ impl Response {
             remote_addr,
             local_addr,
             history: vec![],
+            trailers,
+            upgraded,
         };
         Ok(response)
     }
 
+    /// Build a response from the header block and body of an HTTP/2 stream.
+    #[cfg(feature = "http2")]
+    pub(crate) fn from_http2(
+        unit: &Unit,
+        status: u16,
+        mut headers: Vec<Header>,
+        body: Box<dyn Read + Send + Sync + 'static>,
+        trailers: Trailers,
+        remote_addr: SocketAddr,
+        local_addr: SocketAddr,
+    ) -> Result<Response, Error> {
+        // HTTP/2 has no reason phrase, so the status text is always empty.
+        let status_line = format!("HTTP/2.0 {}", status);
+        let (index, status) = parse_status_line(&status_line)?;
+
+        if headers.len() > MAX_HEADER_COUNT {
+            return Err(ErrorKind::BadHeader.msg(
+                format!("more than {} header fields in response", MAX_HEADER_COUNT).as_str(),
+            ));
+        }
+
+        if let Some(max) = unit.limits.max_header_size {
+            let size: usize = headers
+                .iter()
+                .map(|h| h.name().len() + h.value_raw().len() + 4)
+                .sum();
+            if size > max {
+                return Err(header_size_exceeded(max));
+            }
+        }
+
+        let compression = Compression::from_headers(&headers);
+
+        // remove Content-Encoding and length due to automatic decompression
+        if !compression.is_empty() {
+            headers.retain(|h| !h.is_name("content-encoding") && !h.is_name("content-length"));
+        }
+
+        let reader = decode_body(body, &compression, &unit.limits);
+
+        Ok(Response {
+            url: unit.url.clone(),
+            status_line,
+            index,
+            status,
+            headers,
+            reader,
+            remote_addr,
+            local_addr,
+            history: vec![],
+            trailers,
+            upgraded: None,
+        })
+    }
+
+    /// Build a response from one stored by a [`Cache`](crate::Cache).
+    pub(crate) fn from_cache(
+        url: Url,
+        status_line: String,
+        headers: Vec<Header>,
+        body: Box<dyn Read + Send + Sync + 'static>,
+    ) -> Result<Response, Error> {
+        let (index, status) = parse_status_line(&status_line)?;
+        let unknown_addr: SocketAddr = "0.0.0.0:0".parse().unwrap();
+        Ok(Response {
+            url,
+            status_line,
+            index,
+            status,
+            headers,
+            reader: body,
+            remote_addr: unknown_addr,
+            local_addr: unknown_addr,
+            history: vec![],
+            trailers: Trailers::default(),
+            upgraded: None,
+        })
+    }
+
     #[cfg(test)]
     pub fn set_url(&mut self, url: Url) {
         self.url = url;
@@ -645,20 +926,53 @@ pub(crate) enum Compression {
     Brotli,
     #[cfg(feature = "gzip")]
     Gzip,
+    #[cfg(feature = "deflate")]
+    Deflate,
+    #[cfg(feature = "zstd")]
+    Zstd,
 }
 
 impl Compression {
-    /// Convert a string like "br" to an enum value
+    /// Convert a content coding like "br" to an enum value
     fn from_header_value(value: &str) -> Option<Compression> {
         match value {
             #[cfg(feature = "brotli")]
             "br" => Some(Compression::Brotli),
             #[cfg(feature = "gzip")]
             "gzip" | "x-gzip" => Some(Compression::Gzip),
+            #[cfg(feature = "deflate")]
+            "deflate" => Some(Compression::Deflate),
+            #[cfg(feature = "zstd")]
+            "zstd" => Some(Compression::Zstd),
             _ => None,
         }
     }
 
+    /// The codings applied to a body, in the order they were applied, according to its
+    /// `Content-Encoding` headers.
+    ///
+    /// If any of the codings is not supported, the body can't be decoded and the list is
+    /// empty.
+    fn from_headers(headers: &[Header]) -> Vec<Compression> {
+        let mut codings = vec![];
+        for value in get_all_headers(headers, "content-encoding") {
+            for coding in value.split(',') {
+                let coding = coding.trim().to_ascii_lowercase();
+                if coding.is_empty() || coding == "identity" {
+                    continue;
+                }
+                match Compression::from_header_value(&coding) {
+                    Some(c) => codings.push(c),
+                    None => {
+                        debug!("unsupported content coding: {}", coding);
+                        return vec![];
+                    }
+                }
+            }
+        }
+        codings
+    }
+
     /// Wrap the raw reader with a decompressing reader
     #[allow(unused_variables)] // when no features enabled, reader is unused (unreachable)
     pub(crate) fn wrap_reader(
@@ -670,8 +984,141 @@ impl Compression {
             Compression::Brotli => Box::new(BrotliDecoder::new(reader, 4096)),
             #[cfg(feature = "gzip")]
             Compression::Gzip => Box::new(MultiGzDecoder::new(reader)),
+            #[cfg(feature = "deflate")]
+            Compression::Deflate => Box::new(DeflateDecoder::new(reader)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => match ZstdDecoder::new(reader) {
+                Ok(decoder) => Box::new(decoder),
+                Err(e) => Box::new(ErrorReader(e)),
+            },
         }
     }
+
+    /// Undo all `codings`, the last one applied first.
+    fn wrap_reader_all(
+        codings: &[Compression],
+        reader: Box<dyn Read + Send + Sync + 'static>,
+    ) -> Box<dyn Read + Send + Sync + 'static> {
+        codings
+            .iter()
+            .rev()
+            .fold(reader, |reader, c| c.wrap_reader(reader))
+    }
+}
+
+/// Decodes `Content-Encoding: deflate`.
+///
+/// The coding is specified as the zlib format, but some servers send a raw deflate
+/// stream instead. The first read looks for a zlib header to tell them apart.
+#[cfg(feature = "deflate")]
+struct DeflateDecoder {
+    reader: Option<io::BufReader<Box<dyn Read + Send + Sync + 'static>>>,
+    decoder: Option<Box<dyn Read + Send + Sync + 'static>>,
+}
+
+#[cfg(feature = "deflate")]
+impl DeflateDecoder {
+    fn new(reader: Box<dyn Read + Send + Sync + 'static>) -> Self {
+        DeflateDecoder {
+            reader: Some(io::BufReader::new(reader)),
+            decoder: None,
+        }
+    }
+}
+
+#[cfg(feature = "deflate")]
+impl Read for DeflateDecoder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(reader) = self.reader.as_mut() {
+            let head = reader.fill_buf()?;
+            // https://www.rfc-editor.org/rfc/rfc1950#section-2.2
+            let is_zlib = head.len() >= 2
+                && head[0] & 0x0f == 8
+                && u16::from_be_bytes([head[0], head[1]]) % 31 == 0;
+            let reader = self.reader.take().unwrap();
+            self.decoder = Some(if is_zlib {
+                Box::new(flate2::bufread::ZlibDecoder::new(reader))
+            } else {
+                Box::new(flate2::bufread::DeflateDecoder::new(reader))
+            });
+        }
+        self.decoder.as_mut().unwrap().read(buf)
+    }
+}
+
+/// Read the status line of the next response, skipping over interim (1xx) responses.
+///
+/// `101 Switching Protocols` is not skipped, since HTTP/1.1 ends with it, and neither is
+/// `100 Continue` when `stop_at_continue` is set. Interim responses have no body, so
+/// their headers are consumed along with them.
+pub(crate) fn read_status_line(
+    reader: &mut impl BufRead,
+    stop_at_continue: bool,
+) -> Result<(String, u16), Error> {
+    loop {
+        // The status line we can ignore non-utf8 chars and parse as_str_lossy().
+        let status_line = read_next_line(reader, "the status line")?.into_string_lossy();
+        let (_, status) = parse_status_line(status_line.as_str())?;
+        if !(100..200).contains(&status) || status == 101 {
+            return Ok((status_line, status));
+        }
+
+        let mut count = 0;
+        while !read_next_line(reader, "a header")?.is_empty() {
+            count += 1;
+            if count > MAX_HEADER_COUNT {
+                return Err(ErrorKind::BadHeader.msg(
+                    format!("more than {} header fields in response", MAX_HEADER_COUNT).as_str(),
+                ));
+            }
+        }
+
+        if status == 100 && stop_at_continue {
+            return Ok((status_line, status));
+        }
+        debug!("skipping interim response: {}", status_line);
+    }
+}
+
+/// Read the header fields of a response, up to and including the empty line.
+///
+/// `size` is the number of bytes of the header block read so far, which counts
+/// towards `max_size`.
+fn read_headers(
+    reader: &mut impl BufRead,
+    mut size: usize,
+    max_size: Option<usize>,
+) -> Result<Vec<Header>, Error> {
+    let mut headers: Vec<Header> = Vec::new();
+    while headers.len() <= MAX_HEADER_COUNT {
+        let line = read_next_line(reader, "a header")?;
+        if line.is_empty() {
+            break;
+        }
+        size += line.len() + 2;
+        if let Some(max) = max_size {
+            if size > max {
+                return Err(header_size_exceeded(max));
+            }
+        }
+        if let Ok(header) = line.into_header() {
+            headers.push(header);
+        }
+    }
+
+    if headers.len() > MAX_HEADER_COUNT {
+        return Err(ErrorKind::BadHeader
+            .msg(format!("more than {} header fields in response", MAX_HEADER_COUNT).as_str()));
+    }
+    Ok(headers)
+}
+
+/// Read the status and headers of a response that is not handed to the user,
+/// such as the answer of a proxy to `CONNECT`. The body, if any, is left unread.
+pub(crate) fn read_head(reader: &mut impl BufRead) -> Result<(u16, Vec<Header>), Error> {
+    let (_, status) = read_status_line(reader, false)?;
+    let headers = read_headers(reader, 0, None)?;
+    Ok((status, headers))
 }
 
 /// parse a line like: HTTP/1.1 200 OK\r\n
@@ -757,6 +1204,8 @@ impl FromStr for Response {
         let request_reader = SizedReader {
             size: crate::body::BodySize::Empty,
             reader: Box::new(std::io::empty()),
+            trailers: None,
+            replay: None,
         };
         let unit = Unit::new(
             &Agent::new(),
@@ -815,6 +1264,179 @@ fn read_next_line(reader: &mut impl BufRead, context: &str) -> io::Result<Header
     Ok(buf.into())
 }
 
+fn header_size_exceeded(max: usize) -> Error {
+    ErrorKind::LimitExceeded.msg(format!("response header block larger than {} bytes", max))
+}
+
+/// Unify the errors of deserializing JSON from a body.
+#[cfg(feature = "json")]
+fn json_error(e: serde_json::Error) -> io::Error {
+    use crate::stream::io_err_timeout;
+
+    // This is to unify TimedOut io::Error in the API.
+    if let Some(kind) = e.io_error_kind() {
+        if kind == io::ErrorKind::TimedOut {
+            return io_err_timeout(e.to_string());
+        }
+    }
+
+    if e.is_io() {
+        let err = io::Error::from(e);
+        if is_limit_exceeded(&err) {
+            return err;
+        }
+        return io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Failed to read JSON: {}", err),
+        );
+    }
+
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("Failed to read JSON: {}", e),
+    )
+}
+
+/// Iterator over the records of a newline-delimited JSON body.
+///
+/// Created by [`Response::into_json_stream()`].
+#[cfg(feature = "json")]
+pub struct JsonStream<T> {
+    reader: BufReader<Box<dyn Read + Send + Sync + 'static>>,
+    line: Vec<u8>,
+    done: bool,
+    _record: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "json")]
+impl<T: DeserializeOwned> Iterator for JsonStream<T> {
+    type Item = io::Result<T>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            self.line.clear();
+            match self.reader.read_until(b'\n', &mut self.line) {
+                Ok(0) => self.done = true,
+                Ok(_) if self.line.iter().all(u8::is_ascii_whitespace) => {}
+                Ok(_) => return Some(serde_json::from_slice(&self.line).map_err(json_error)),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(feature = "json")]
+impl<T> fmt::Debug for JsonStream<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JsonStream")
+            .field("done", &self.done)
+            .finish()
+    }
+}
+
+/// An error for a body that exceeds a limit, wrapping an [`ErrorKind::LimitExceeded`].
+fn limit_exceeded(msg: String) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        ErrorKind::LimitExceeded.msg(msg),
+    )
+}
+
+#[cfg(feature = "json")]
+fn is_limit_exceeded(err: &io::Error) -> bool {
+    err.get_ref()
+        .and_then(|e| e.downcast_ref::<Error>())
+        .map(|e| e.kind() == ErrorKind::LimitExceeded)
+        .unwrap_or(false)
+}
+
+/// The compression ratio limit is only checked once this much has been decoded, so
+/// small, highly compressible bodies are not affected.
+const MIN_DECODED_FOR_RATIO: u64 = 1024 * 1024;
+
+/// Undo the `codings` of a body and apply `limits` to it.
+fn decode_body(
+    reader: Box<dyn Read + Send + Sync + 'static>,
+    codings: &[Compression],
+    limits: &Limits,
+) -> Box<dyn Read + Send + Sync + 'static> {
+    let max_ratio = limits.max_compression_ratio.filter(|_| !codings.is_empty());
+    if limits.max_body_size.is_none() && max_ratio.is_none() {
+        return Compression::wrap_reader_all(codings, reader);
+    }
+
+    let encoded = Arc::new(AtomicU64::new(0));
+    let reader: Box<dyn Read + Send + Sync> = match max_ratio {
+        Some(_) => Box::new(CountingRead {
+            reader,
+            count: encoded.clone(),
+        }),
+        None => reader,
+    };
+
+    Box::new(BodyLimitRead {
+        reader: Compression::wrap_reader_all(codings, reader),
+        decoded: 0,
+        encoded,
+        max_body_size: limits.max_body_size,
+        max_ratio,
+    })
+}
+
+/// Counts the bytes read from a body before it is decoded.
+struct CountingRead<R> {
+    reader: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R: Read> Read for CountingRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// Fails reading a decoded body that is too large, or too large compared to the encoded body.
+struct BodyLimitRead<R> {
+    reader: R,
+    decoded: u64,
+    encoded: Arc<AtomicU64>,
+    max_body_size: Option<u64>,
+    max_ratio: Option<u32>,
+}
+
+impl<R: Read> Read for BodyLimitRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.reader.read(buf)?;
+        self.decoded += n as u64;
+        if let Some(max) = self.max_body_size {
+            if self.decoded > max {
+                return Err(limit_exceeded(format!(
+                    "response body larger than {} bytes",
+                    max
+                )));
+            }
+        }
+        if let Some(ratio) = self.max_ratio {
+            let encoded = self.encoded.load(Ordering::Relaxed);
+            if self.decoded > MIN_DECODED_FOR_RATIO
+                && self.decoded > encoded.saturating_mul(ratio as u64)
+            {
+                return Err(limit_exceeded(format!(
+                    "response body compression ratio exceeds {}",
+                    ratio
+                )));
+            }
+        }
+        Ok(n)
+    }
+}
+
 /// Limits a `Read` to a content size (as set by a "Content-Length" header).
 pub(crate) struct LimitedRead<R> {
     reader: Option<R>,
@@ -993,6 +1615,23 @@ mod tests {
         assert_eq!("utf-8", resp.charset());
     }
 
+    #[test]
+    fn skips_interim_responses() {
+        let s = "HTTP/1.1 100 Continue\r\n\
+                 \r\n\
+                 HTTP/1.1 103 Early Hints\r\n\
+                 Link: </style.css>; rel=preload\r\n\
+                 \r\n\
+                 HTTP/1.1 200 OK\r\n\
+                 Content-Length: 2\r\n\
+                 \r\n\
+                 OK";
+        let resp = s.parse::<Response>().unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(!resp.has("link"));
+        assert_eq!(resp.into_string().unwrap(), "OK");
+    }
+
     #[test]
     fn chunked_transfer() {
         let s = "HTTP/1.1 200 OK\r\n\
@@ -1141,6 +1780,8 @@ mod tests {
         let request_reader = SizedReader {
             size: crate::body::BodySize::Empty,
             reader: Box::new(std::io::empty()),
+            trailers: None,
+            replay: None,
         };
         let unit = Unit::new(
             &Agent::new(),
@@ -0,0 +1,276 @@
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::{Error, ErrorKind};
+use crate::header::parse_http_date;
+use crate::random::random_u64;
+use crate::response::Response;
+
+/// When and how often an [`Agent`](crate::Agent) retries a failed request.
+///
+/// A request is retried when it fails with one of the configured [`ErrorKind`]s, or when
+/// the response has one of the configured status codes. The delay between attempts grows
+/// exponentially, and is randomized with "full jitter" so that clients do not retry in
+/// lockstep. For `429 Too Many Requests` and `503 Service Unavailable`, a `Retry-After`
+/// header from the server takes precedence.
+///
+/// Only idempotent methods are retried by default, and only when the request body can be
+/// sent again: bodies sent with [`Request::send()`](crate::Request::send) are read once
+/// and never retried, unlike buffered bodies and those sent with
+/// [`Request::send_seekable()`](crate::Request::send_seekable). Each attempt runs through
+/// the whole [middleware](crate::Middleware) chain.
+///
+/// ```
+/// # fn main() -> Result<(), ureq::Error> {
+/// # ureq::is_test(true);
+/// use std::time::Duration;
+/// use ureq::{ErrorKind, RetryPolicy};
+///
+/// let policy = RetryPolicy::new()
+///     .max_attempts(5)
+///     .backoff(Duration::from_millis(200), Duration::from_secs(5))
+///     .retry_on_errors(&[ErrorKind::ConnectionFailed, ErrorKind::Dns, ErrorKind::Io])
+///     .retry_on_statuses(&[429, 503])
+///     .budget(Duration::from_secs(20));
+///
+/// let agent = ureq::builder()
+///     .retry(policy)
+///     .build();
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter: bool,
+    error_kinds: Vec<ErrorKind>,
+    statuses: Vec<u16>,
+    budget: Option<Duration>,
+    non_idempotent: bool,
+}
+
+impl RetryPolicy {
+    /// A policy making up to 3 attempts.
+    ///
+    /// The backoff starts at 100 milliseconds and is capped at 10 seconds, with jitter.
+    /// Connection failures, I/O errors and the statuses 429, 502, 503 and 504 are retried,
+    /// within a budget of 30 seconds.
+    pub fn new() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            jitter: true,
+            error_kinds: vec![ErrorKind::ConnectionFailed, ErrorKind::Io],
+            statuses: vec![429, 502, 503, 504],
+            budget: Some(Duration::from_secs(30)),
+            non_idempotent: false,
+        }
+    }
+
+    /// The total number of attempts, the first one included. `1` disables retries.
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = attempts.max(1);
+        self
+    }
+
+    /// The delay before the first retry, doubled for every following one up to `max`.
+    pub fn backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max.max(initial);
+        self
+    }
+
+    /// Whether to pick each delay at random between zero and the backoff. Defaults to true.
+    pub fn jitter(mut self, jitter: bool) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// The kinds of errors to retry, replacing the defaults.
+    pub fn retry_on_errors(mut self, kinds: &[ErrorKind]) -> Self {
+        self.error_kinds = kinds.to_vec();
+        self
+    }
+
+    /// The response statuses to retry, replacing the defaults.
+    pub fn retry_on_statuses(mut self, statuses: &[u16]) -> Self {
+        self.statuses = statuses.to_vec();
+        self
+    }
+
+    /// The longest time spent on a request, retries included.
+    ///
+    /// No retry is made if waiting for it would exceed the budget, or the request's
+    /// [timeout](crate::Request::timeout). Defaults to 30 seconds.
+    pub fn budget(mut self, budget: Duration) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Also retry requests with non-idempotent methods, such as `POST` and `PATCH`.
+    ///
+    /// Beware that the server may already have acted on a request that failed.
+    pub fn retry_non_idempotent(mut self, enabled: bool) -> Self {
+        self.non_idempotent = enabled;
+        self
+    }
+
+    /// Whether requests with this method can be retried.
+    pub(crate) fn allows_method(&self, method: &str) -> bool {
+        self.non_idempotent
+            || matches!(
+                method,
+                "GET" | "HEAD" | "PUT" | "DELETE" | "OPTIONS" | "TRACE"
+            )
+    }
+
+    /// The delay before the next attempt, or `None` if `result` should be returned.
+    ///
+    /// `attempt` is the number of attempts made so far, `start` when the first one began.
+    pub(crate) fn delay(
+        &self,
+        result: &Result<Response, Error>,
+        attempt: u32,
+        start: Instant,
+        deadline: Option<Instant>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts {
+            return None;
+        }
+        let status = match result {
+            Ok(response) if self.statuses.contains(&response.status()) => response.status(),
+            Ok(_) => return None,
+            Err(e) if self.error_kinds.contains(&e.kind()) => 0,
+            Err(_) => return None,
+        };
+
+        let retry_after = match result {
+            Ok(response) if status == 429 || status == 503 => {
+                response.header("retry-after").and_then(parse_retry_after)
+            }
+            _ => None,
+        };
+        let delay = retry_after.unwrap_or_else(|| self.backoff_for(attempt));
+
+        let next = Instant::now().checked_add(delay)?;
+        if let Some(budget) = self.budget {
+            if next > start.checked_add(budget)? {
+                return None;
+            }
+        }
+        if let Some(deadline) = deadline {
+            if next >= deadline {
+                return None;
+            }
+        }
+        Some(delay)
+    }
+
+    /// The backoff after `attempt` attempts, with jitter if enabled.
+    fn backoff_for(&self, attempt: u32) -> Duration {
+        let factor = 1_u32.checked_shl(attempt - 1).unwrap_or(u32::MAX);
+        let backoff = self
+            .initial_backoff
+            .checked_mul(factor)
+            .unwrap_or(self.max_backoff)
+            .min(self.max_backoff);
+        if self.jitter {
+            backoff.mul_f64(random_fraction())
+        } else {
+            backoff
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy::new()
+    }
+}
+
+/// The delay of a `Retry-After` header, either seconds or an HTTP-date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = parse_http_date(value)?;
+    // A date in the past means no delay.
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// A random number in `[0, 1)`.
+fn random_fraction() -> f64 {
+    (random_u64() >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(status: u16, retry_after: Option<&str>) -> Result<Response, Error> {
+        let mut s = format!("HTTP/1.1 {} Whatever\r\n", status);
+        if let Some(v) = retry_after {
+            s.push_str(&format!("Retry-After: {}\r\n", v));
+        }
+        s.push_str("\r\n");
+        Ok(s.parse::<Response>().unwrap())
+    }
+
+    #[test]
+    fn backoff_grows_to_cap() {
+        let policy = RetryPolicy::new()
+            .jitter(false)
+            .backoff(Duration::from_millis(100), Duration::from_millis(350));
+        let delays: Vec<_> = (1..=4).map(|a| policy.backoff_for(a)).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 350, 350].map(Duration::from_millis).to_vec()
+        );
+        assert_eq!(policy.backoff_for(100), Duration::from_millis(350));
+    }
+
+    #[test]
+    fn jitter_stays_below_backoff() {
+        let policy = RetryPolicy::new().backoff(Duration::from_secs(1), Duration::from_secs(1));
+        for _ in 0..100 {
+            assert!(policy.backoff_for(1) < Duration::from_secs(1));
+        }
+    }
+
+    #[test]
+    fn retryable_results() {
+        let policy = RetryPolicy::new().jitter(false).max_attempts(3);
+        let start = Instant::now();
+        assert!(policy.delay(&status(503, None), 1, start, None).is_some());
+        assert!(policy.delay(&status(503, None), 3, start, None).is_none());
+        assert!(policy.delay(&status(500, None), 1, start, None).is_none());
+        assert!(policy.delay(&status(200, None), 1, start, None).is_none());
+        let err = ErrorKind::ConnectionFailed.new();
+        assert!(policy.delay(&Err(err), 1, start, None).is_some());
+        let err = ErrorKind::InvalidUrl.new();
+        assert!(policy.delay(&Err(err), 1, start, None).is_none());
+    }
+
+    #[test]
+    fn retry_after() {
+        let policy = RetryPolicy::new();
+        let start = Instant::now();
+        let delay = policy.delay(&status(429, Some("2")), 1, start, None);
+        assert_eq!(delay, Some(Duration::from_secs(2)));
+        let past = "Sun, 06 Nov 1994 08:49:37 GMT";
+        let delay = policy.delay(&status(503, Some(past)), 1, start, None);
+        assert_eq!(delay, Some(Duration::ZERO));
+        // Not honoured for other statuses.
+        let delay = policy.delay(&status(502, Some("2")), 1, start, None);
+        assert!(delay.unwrap() < Duration::from_secs(2));
+        // Beyond the budget.
+        let delay = policy.delay(&status(503, Some("60")), 1, start, None);
+        assert_eq!(delay, None);
+    }
+}
@@ -33,6 +33,15 @@ impl ReadWrite for RustlsStream {
     fn socket(&self) -> Option<&TcpStream> {
         self.0.get_ref().socket()
     }
+
+    #[cfg(unix)]
+    fn unix_socket(&self) -> Option<&std::os::unix::net::UnixStream> {
+        self.0.get_ref().unix_socket()
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.0.conn.alpn_protocol()
+    }
 }
 
 // TODO: After upgrading to rustls 0.20 or higher, we can remove these Read
@@ -109,42 +118,83 @@ fn root_certs() -> rustls::RootCertStore {
     root_store
 }
 
+#[cfg(feature = "http2")]
+fn alpn_protocols() -> Vec<Vec<u8>> {
+    vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+}
+
 impl TlsConnector for Arc<rustls::ClientConfig> {
-    fn connect(
+    fn connect(&self, dns_name: &str, io: Box<dyn ReadWrite>) -> Result<Box<dyn ReadWrite>, Error> {
+        // Offer HTTP/2, unless the config explicitly asks for other protocols.
+        #[cfg(feature = "http2")]
+        let config = if self.alpn_protocols.is_empty() {
+            let mut config = (**self).clone();
+            config.alpn_protocols = alpn_protocols();
+            Arc::new(config)
+        } else {
+            self.clone()
+        };
+        #[cfg(not(feature = "http2"))]
+        let config = self.clone();
+
+        connect_tls(config, dns_name, io)
+    }
+
+    fn connect_http1(
         &self,
         dns_name: &str,
-        mut io: Box<dyn ReadWrite>,
+        io: Box<dyn ReadWrite>,
     ) -> Result<Box<dyn ReadWrite>, Error> {
-        let dns_name = if dns_name.starts_with('[') && dns_name.ends_with(']') {
-            // rustls doesn't like ipv6 addresses with brackets
-            &dns_name[1..dns_name.len() - 1]
+        let config = if self.alpn_protocols.iter().any(|p| p == b"h2") {
+            let mut config = (**self).clone();
+            config.alpn_protocols = vec![b"http/1.1".to_vec()];
+            Arc::new(config)
         } else {
-            dns_name
+            self.clone()
         };
-
-        let sni = rustls::ServerName::try_from(dns_name)
-            .map_err(|e| ErrorKind::Dns.msg(format!("parsing '{}'", dns_name)).src(e))?;
-
-        let mut sess = rustls::ClientConnection::new(self.clone(), sni)
-            .map_err(|e| ErrorKind::Io.msg("tls connection creation failed").src(e))?;
-
-        sess.complete_io(&mut io).map_err(|e| {
-            ErrorKind::ConnectionFailed
-                .msg("tls connection init failed")
-                .src(e)
-        })?;
-        let stream = rustls::StreamOwned::new(sess, io);
-
-        Ok(Box::new(RustlsStream(stream)))
+        connect_tls(config, dns_name, io)
     }
 }
 
+fn connect_tls(
+    config: Arc<rustls::ClientConfig>,
+    dns_name: &str,
+    mut io: Box<dyn ReadWrite>,
+) -> Result<Box<dyn ReadWrite>, Error> {
+    let dns_name = if dns_name.starts_with('[') && dns_name.ends_with(']') {
+        // rustls doesn't like ipv6 addresses with brackets
+        &dns_name[1..dns_name.len() - 1]
+    } else {
+        dns_name
+    };
+
+    let sni = rustls::ServerName::try_from(dns_name)
+        .map_err(|e| ErrorKind::Dns.msg(format!("parsing '{}'", dns_name)).src(e))?;
+
+    let mut sess = rustls::ClientConnection::new(config, sni)
+        .map_err(|e| ErrorKind::Io.msg("tls connection creation failed").src(e))?;
+
+    sess.complete_io(&mut io).map_err(|e| {
+        ErrorKind::ConnectionFailed
+            .msg("tls connection init failed")
+            .src(e)
+    })?;
+    let stream = rustls::StreamOwned::new(sess, io);
+
+    Ok(Box::new(RustlsStream(stream)))
+}
+
 pub fn default_tls_config() -> Arc<dyn TlsConnector> {
     static TLS_CONF: Lazy<Arc<dyn TlsConnector>> = Lazy::new(|| {
-        let config = rustls::ClientConfig::builder()
+        #[allow(unused_mut)]
+        let mut config = rustls::ClientConfig::builder()
             .with_safe_defaults()
             .with_root_certificates(root_certs())
             .with_no_client_auth();
+        #[cfg(feature = "http2")]
+        {
+            config.alpn_protocols = alpn_protocols();
+        }
         Arc::new(Arc::new(config))
     });
     TLS_CONF.clone()
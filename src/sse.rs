@@ -0,0 +1,378 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read};
+use std::thread;
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::request::Request;
+
+/// The reconnection delay until the server sets one with a `retry` field.
+const DEFAULT_RETRY: Duration = Duration::from_secs(3);
+
+/// A single Server-Sent Event.
+///
+/// See [`Response::into_sse()`](crate::Response::into_sse).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: String,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// The last event ID seen on the stream, if any.
+    ///
+    /// The ID carries over to later events until the server sends a new one.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// The event type, `"message"` unless the server set one with an `event` field.
+    pub fn event(&self) -> &str {
+        &self.event
+    }
+
+    /// The data of the event. The lines of multiple `data` fields are joined with `\n`.
+    pub fn data(&self) -> &str {
+        &self.data
+    }
+
+    /// The reconnection delay, if the event had a `retry` field.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+}
+
+/// Iterator over the events of a `text/event-stream` response.
+///
+/// Created by [`Response::into_sse()`](crate::Response::into_sse). The iterator
+/// ends when the body does; an event that is not terminated by a blank line is
+/// discarded, as the specification requires.
+/// <https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation>
+pub struct Events {
+    reader: BufReader<Box<dyn Read + Send + Sync + 'static>>,
+    /// The last line ended with CR, so a LF that follows belongs to it.
+    skip_lf: bool,
+    at_start: bool,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl fmt::Debug for Events {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Events")
+            .field("last_event_id", &self.last_event_id)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl Events {
+    pub(crate) fn new(reader: Box<dyn Read + Send + Sync + 'static>) -> Self {
+        Events {
+            reader: BufReader::new(reader),
+            skip_lf: false,
+            at_start: true,
+            last_event_id: None,
+            retry: None,
+        }
+    }
+
+    /// The last event ID sent by the server, even if no event was dispatched with it yet.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    /// The last reconnection delay sent by the server.
+    pub fn retry(&self) -> Option<Duration> {
+        self.retry
+    }
+
+    /// Read a line terminated by CRLF, LF or CR. `None` at the end of the stream.
+    fn read_line(&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut line = vec![];
+        loop {
+            let buf = match self.reader.fill_buf() {
+                Ok(buf) => buf,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            if buf.is_empty() {
+                return Ok(None);
+            }
+            if self.skip_lf {
+                self.skip_lf = false;
+                if buf[0] == b'\n' {
+                    self.reader.consume(1);
+                    continue;
+                }
+            }
+            match buf.iter().position(|b| *b == b'\n' || *b == b'\r') {
+                Some(i) => {
+                    line.extend_from_slice(&buf[..i]);
+                    self.skip_lf = buf[i] == b'\r';
+                    self.reader.consume(i + 1);
+                    break;
+                }
+                None => {
+                    let n = buf.len();
+                    line.extend_from_slice(buf);
+                    self.reader.consume(n);
+                }
+            }
+        }
+        if self.at_start {
+            self.at_start = false;
+            if line.starts_with(b"\xEF\xBB\xBF") {
+                line.drain(..3);
+            }
+        }
+        Ok(Some(line))
+    }
+}
+
+impl Iterator for Events {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut event = Event::default();
+        let mut data = String::new();
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            let line = String::from_utf8_lossy(&line);
+
+            if line.is_empty() {
+                if data.is_empty() {
+                    event = Event::default();
+                    continue;
+                }
+                data.pop();
+                event.data = data;
+                event.id = self.last_event_id.clone();
+                if event.event.is_empty() {
+                    event.event = "message".to_string();
+                }
+                return Some(Ok(event));
+            }
+
+            let (field, value) = match line.find(':') {
+                // A comment.
+                Some(0) => continue,
+                Some(i) => {
+                    let value = &line[i + 1..];
+                    (&line[..i], value.strip_prefix(' ').unwrap_or(value))
+                }
+                None => (&line[..], ""),
+            };
+            match field {
+                "event" => event.event = value.to_string(),
+                "data" => {
+                    data.push_str(value);
+                    data.push('\n');
+                }
+                "id" if !value.contains('\0') => {
+                    self.last_event_id = Some(value.to_string()).filter(|id| !id.is_empty());
+                }
+                "retry" if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => {
+                    if let Ok(millis) = value.parse() {
+                        let retry = Duration::from_millis(millis);
+                        self.retry = Some(retry);
+                        event.retry = Some(retry);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A Server-Sent Events stream that reconnects when the connection is lost.
+///
+/// Created by [`Agent::event_source()`](crate::Agent::event_source). Each
+/// reconnection waits for the delay most recently sent by the server in a `retry`
+/// field, and sends the last event ID in a `Last-Event-ID` header so the server
+/// can resume the stream.
+///
+/// Connection and read errors are yielded, and the next call to `next()` reconnects.
+/// The iterator ends after an HTTP status error, which is yielded, or when the
+/// server answers `204 No Content`.
+pub struct EventSource {
+    request: Request,
+    events: Option<Events>,
+    last_event_id: Option<String>,
+    retry: Duration,
+    connected: bool,
+    done: bool,
+}
+
+impl fmt::Debug for EventSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventSource")
+            .field("request", &self.request)
+            .field("last_event_id", &self.last_event_id)
+            .field("retry", &self.retry)
+            .finish()
+    }
+}
+
+impl EventSource {
+    pub(crate) fn new(request: Request) -> Self {
+        EventSource {
+            request: request.set("Accept", "text/event-stream"),
+            events: None,
+            last_event_id: None,
+            retry: DEFAULT_RETRY,
+            connected: false,
+            done: false,
+        }
+    }
+
+    /// Set a header field sent with every connection attempt.
+    pub fn set(mut self, header: &str, value: &str) -> Self {
+        self.request = self.request.set(header, value);
+        self
+    }
+
+    /// The delay before reconnecting, until the server sends one. Defaults to 3 seconds.
+    pub fn retry(mut self, delay: Duration) -> Self {
+        self.retry = delay;
+        self
+    }
+
+    /// The last event ID sent by the server.
+    pub fn last_event_id(&self) -> Option<&str> {
+        self.last_event_id.as_deref()
+    }
+
+    fn update(&mut self, events: &Events) {
+        self.last_event_id = events.last_event_id.clone();
+        if let Some(retry) = events.retry() {
+            self.retry = retry;
+        }
+    }
+}
+
+impl Iterator for EventSource {
+    type Item = Result<Event, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.done {
+                return None;
+            }
+            let mut events = match self.events.take() {
+                Some(events) => events,
+                None => {
+                    if self.connected {
+                        thread::sleep(self.retry);
+                    }
+                    self.connected = true;
+                    let mut request = self.request.clone();
+                    if let Some(id) = &self.last_event_id {
+                        request = request.set("Last-Event-ID", id);
+                    }
+                    match request.call() {
+                        Ok(response) if response.status() == 204 => {
+                            self.done = true;
+                            return None;
+                        }
+                        Ok(response) => {
+                            let mut events = response.into_sse();
+                            events.last_event_id = self.last_event_id.clone();
+                            events
+                        }
+                        Err(e @ Error::Status(..)) => {
+                            self.done = true;
+                            return Some(Err(e));
+                        }
+                        Err(e) => return Some(Err(e)),
+                    }
+                }
+            };
+            let next = events.next();
+            self.update(&events);
+            // Reconnect after the stream ends or fails.
+            if let Some(Ok(_)) = next {
+                self.events = Some(events);
+            }
+            match next {
+                Some(Ok(event)) => return Some(Ok(event)),
+                Some(Err(e)) => return Some(Err(e.into())),
+                None => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn events(body: &'static str) -> Vec<Event> {
+        Events::new(Box::new(body.as_bytes()))
+            .collect::<io::Result<_>>()
+            .unwrap()
+    }
+
+    fn event(id: Option<&str>, event: &str, data: &str) -> Event {
+        Event {
+            id: id.map(String::from),
+            event: event.to_string(),
+            data: data.to_string(),
+            retry: None,
+        }
+    }
+
+    #[test]
+    fn fields() {
+        let events = events(
+            "\u{feff}: comment\n\
+             data: first\n\
+             data:second\n\
+             \n\
+             id: 7\n\
+             event: update\n\
+             data\n\
+             \n\
+             data: no id change\n\
+             \n\
+             id\n\
+             unknown: field\n\
+             data: \n\
+             \n\
+             data: unterminated\n",
+        );
+        assert_eq!(
+            events,
+            vec![
+                event(None, "message", "first\nsecond"),
+                event(Some("7"), "update", ""),
+                event(Some("7"), "message", "no id change"),
+                event(None, "message", ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_endings() {
+        let events = events("data: a\r\ndata: b\rdata: c\n\r\r\n");
+        assert_eq!(events, vec![event(None, "message", "a\nb\nc")]);
+    }
+
+    #[test]
+    fn retry() {
+        let mut events = Events::new(Box::new(
+            &b"retry: 10\n\nretry: x\ndata: a\n\nretry: 20\ndata: b\n\n"[..],
+        ));
+        assert_eq!(events.next().unwrap().unwrap().retry(), None);
+        assert_eq!(events.retry(), Some(Duration::from_millis(10)));
+        let b = events.next().unwrap().unwrap();
+        assert_eq!(b.data(), "b");
+        assert_eq!(b.retry(), Some(Duration::from_millis(20)));
+    }
+}
@@ -2,24 +2,48 @@ use log::debug;
 use std::io::{self, BufRead, BufReader, Read, Write};
 use std::net::SocketAddr;
 use std::net::TcpStream;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+#[cfg(unix)]
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 use std::{fmt, io::Cursor};
 
+use url::Url;
+
 #[cfg(feature = "socks-proxy")]
 use socks::{TargetAddr, ToTargetAddr};
 
 use crate::chunked::Decoder as ChunkDecoder;
 use crate::error::ErrorKind;
+use crate::header::Header;
 use crate::pool::{PoolKey, PoolReturner};
 use crate::proxy::Proxy;
+use crate::response;
 use crate::unit::Unit;
-use crate::Response;
 use crate::{error::Error, proxy::Proto};
 
 /// Trait for things implementing [std::io::Read] + [std::io::Write]. Used in [TlsConnector].
 pub trait ReadWrite: Read + Write + Send + Sync + fmt::Debug + 'static {
     fn socket(&self) -> Option<&TcpStream>;
+
+    /// The Unix domain socket the stream is built on, if any.
+    ///
+    /// Like [`ReadWrite::socket`], this is used to apply timeouts.
+    #[cfg(unix)]
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        None
+    }
+
+    /// The application protocol negotiated via ALPN, such as `b"h2"`.
+    ///
+    /// Returns `None` for streams that are not TLS, or where no protocol was agreed.
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        None
+    }
 }
 
 impl ReadWrite for TcpStream {
@@ -28,12 +52,88 @@ impl ReadWrite for TcpStream {
     }
 }
 
+#[cfg(unix)]
+impl ReadWrite for UnixStream {
+    fn socket(&self) -> Option<&TcpStream> {
+        None
+    }
+
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        Some(self)
+    }
+}
+
 pub trait TlsConnector: Send + Sync {
     fn connect(
         &self,
         dns_name: &str,
         io: Box<dyn ReadWrite>,
     ) -> Result<Box<dyn ReadWrite>, crate::error::Error>;
+
+    /// Like [`connect()`](TlsConnector::connect), for a connection that must speak
+    /// HTTP/1.1, such as the one to an HTTPS proxy, or one a request upgrades to
+    /// another protocol.
+    ///
+    /// A connector offering `h2` via ALPN should only offer `http/1.1` here. The default
+    /// calls `connect()`.
+    fn connect_http1(
+        &self,
+        dns_name: &str,
+        io: Box<dyn ReadWrite>,
+    ) -> Result<Box<dyn ReadWrite>, crate::error::Error> {
+        self.connect(dns_name, io)
+    }
+}
+
+/// Opens connections for an agent.
+///
+/// By default, an agent opens a TCP connection to the host, or to its
+/// [`Proxy`](crate::Proxy), and adds TLS with its [`TlsConnector`] for `https` urls.
+/// Set a connector with [`AgentBuilder::connector()`](crate::AgentBuilder::connector) to
+/// replace all of that, for instance to send requests over in-process pipes, vsock, or
+/// sockets handed over by a supervisor.
+///
+/// The stream returned by a connector is used as is. For `https` urls, it must already
+/// be encrypted: a connector can add TLS itself, for instance by calling
+/// [`TlsConnector::connect()`] on its transport. Proxies are not used with a connector.
+/// Connections are pooled, as they are for TCP, so a stream is reused for later requests
+/// to the same scheme, host and port.
+///
+/// A `Fn(&Url, &str, u16) -> Result<Box<dyn ReadWrite>, Error>` is a valid connector.
+pub trait Connector: Send + Sync {
+    /// Open a connection for a request to `url`. The `host` and `port` are those of the
+    /// url, with the port defaulting to the one of the scheme.
+    fn connect(&self, url: &Url, host: &str, port: u16) -> Result<Box<dyn ReadWrite>, Error>;
+}
+
+impl<F> Connector for F
+where
+    F: Fn(&Url, &str, u16) -> Result<Box<dyn ReadWrite>, Error>,
+    F: Send + Sync,
+{
+    fn connect(&self, url: &Url, host: &str, port: u16) -> Result<Box<dyn ReadWrite>, Error> {
+        self(url, host, port)
+    }
+}
+
+/// The connector of an agent without a custom one: TCP to the host or the proxy of
+/// the unit, with TLS for https.
+pub(crate) struct DefaultConnector<'a> {
+    pub(crate) unit: &'a Unit,
+}
+
+impl Connector for DefaultConnector<'_> {
+    fn connect(&self, url: &Url, host: &str, port: u16) -> Result<Box<dyn ReadWrite>, Error> {
+        let (stream, _) = connect_host(self.unit, host, port)?;
+        if url.scheme() == "https" {
+            let tls_conf = &self.unit.agent.config.tls_config;
+            if self.unit.is_upgrade() {
+                return tls_conf.connect_http1(host, stream);
+            }
+            return tls_conf.connect(host, stream);
+        }
+        Ok(stream)
+    }
 }
 
 pub(crate) struct Stream {
@@ -47,6 +147,15 @@ impl<T: ReadWrite + ?Sized> ReadWrite for Box<T> {
     fn socket(&self) -> Option<&TcpStream> {
         ReadWrite::socket(self.as_ref())
     }
+
+    #[cfg(unix)]
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        ReadWrite::unix_socket(self.as_ref())
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        ReadWrite::alpn_protocol(self.as_ref())
+    }
 }
 
 // DeadlineStream wraps a stream such that read() will return an error
@@ -83,10 +192,8 @@ impl BufRead for DeadlineStream {
     fn fill_buf(&mut self) -> io::Result<&[u8]> {
         if let Some(deadline) = self.deadline {
             let timeout = time_until_deadline(deadline)?;
-            if let Some(socket) = self.stream.socket() {
-                socket.set_read_timeout(Some(timeout))?;
-                socket.set_write_timeout(Some(timeout))?;
-            }
+            self.stream.set_read_timeout(Some(timeout))?;
+            self.stream.set_write_timeout(Some(timeout))?;
         }
         self.stream.fill_buf().map_err(|e| {
             // On unix-y platforms set_read_timeout and set_write_timeout
@@ -131,7 +238,7 @@ impl Read for DeadlineStream {
 
 // If the deadline is in the future, return the remaining time until
 // then. Otherwise return a TimedOut error.
-fn time_until_deadline(deadline: Instant) -> io::Result<Duration> {
+pub(crate) fn time_until_deadline(deadline: Instant) -> io::Result<Duration> {
     let now = Instant::now();
     match deadline.checked_duration_since(now) {
         None => Err(io_err_timeout("timed out reading response".to_string())),
@@ -259,24 +366,40 @@ impl Stream {
     pub(crate) fn reset(&mut self) -> io::Result<()> {
         // When we are turning this back into a regular, non-deadline Stream,
         // remove any timeouts we set.
-        if let Some(socket) = self.socket() {
-            socket.set_read_timeout(None)?;
-            socket.set_write_timeout(None)?;
-        }
-
-        Ok(())
+        self.set_read_timeout(None)?;
+        self.set_write_timeout(None)
     }
 
     pub(crate) fn socket(&self) -> Option<&TcpStream> {
         self.inner.get_ref().socket()
     }
 
+    /// Whether the server agreed to speak HTTP/2 on this stream.
+    #[cfg(feature = "http2")]
+    pub(crate) fn is_http2(&self) -> bool {
+        self.inner.get_ref().alpn_protocol() == Some(b"h2")
+    }
+
     pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
         if let Some(socket) = self.socket() {
-            socket.set_read_timeout(timeout)
-        } else {
-            Ok(())
+            return socket.set_read_timeout(timeout);
+        }
+        #[cfg(unix)]
+        if let Some(socket) = self.inner.get_ref().unix_socket() {
+            return socket.set_read_timeout(timeout);
         }
+        Ok(())
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        if let Some(socket) = self.socket() {
+            return socket.set_write_timeout(timeout);
+        }
+        #[cfg(unix)]
+        if let Some(socket) = self.inner.get_ref().unix_socket() {
+            return socket.set_write_timeout(timeout);
+        }
+        Ok(())
     }
 }
 
@@ -315,56 +438,346 @@ impl Write for Stream {
     }
 }
 
+/// An upgraded stream is handed out as a [`ReadWrite`], see
+/// [`Response::into_upgraded()`](crate::Response::into_upgraded). Reading it first
+/// yields anything already buffered.
+impl ReadWrite for Stream {
+    fn socket(&self) -> Option<&TcpStream> {
+        Stream::socket(self)
+    }
+
+    #[cfg(unix)]
+    fn unix_socket(&self) -> Option<&UnixStream> {
+        self.inner.get_ref().unix_socket()
+    }
+
+    fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.inner.get_ref().alpn_protocol()
+    }
+}
+
 impl Drop for Stream {
     fn drop(&mut self) {
         debug!("dropping stream: {:?}", self);
     }
 }
 
-pub(crate) fn connect_http(unit: &Unit, hostname: &str) -> Result<Stream, Error> {
-    //
-    let port = unit.url.port().unwrap_or(80);
-    let pool_key = PoolKey::from_parts("http", hostname, port);
+/// Open a connection for the unit with `connector`.
+pub(crate) fn connect(
+    unit: &Unit,
+    connector: &dyn Connector,
+    hostname: &str,
+) -> Result<Stream, Error> {
+    let scheme = unit.url.scheme();
+    let port = unit
+        .url
+        .port_or_known_default()
+        .unwrap_or(if scheme == "https" { 443 } else { 80 });
+
+    let stream = connector.connect(&unit.url, hostname, port)?;
+    let remote_addr = match stream.socket() {
+        Some(socket) => socket.peer_addr()?,
+        None => std::net::SocketAddrV4::new(std::net::Ipv4Addr::new(127, 0, 0, 1), 0).into(),
+    };
+    // The same key as the pool is searched with, so connections to a proxy are only
+    // reused through that proxy.
+    let pool_key = PoolKey::new(&unit.url, unit.proxy.clone());
     let pool_returner = PoolReturner::new(&unit.agent, pool_key);
-    connect_host(unit, hostname, port).map(|(t, r)| Stream::new(t, r, pool_returner))
+    Ok(Stream::new(stream, remote_addr, pool_returner))
 }
 
-pub(crate) fn connect_https(unit: &Unit, hostname: &str) -> Result<Stream, Error> {
-    let port = unit.url.port().unwrap_or(443);
+/// Connect to a Unix domain socket instead of a host, for http over the socket, or
+/// https if the url asks for it.
+#[cfg(unix)]
+pub(crate) fn connect_unix(unit: &Unit, path: &Path) -> Result<Stream, Error> {
+    debug!("connecting to unix socket {}", path.display());
+    let stream = UnixStream::connect(path).map_err(|e| {
+        ErrorKind::ConnectionFailed
+            .msg(format!("Connect error to unix socket {}", path.display()))
+            .src(e)
+    })?;
 
-    let (sock, remote_addr) = connect_host(unit, hostname, port)?;
+    if let Some(deadline) = unit.deadline {
+        stream.set_read_timeout(Some(time_until_deadline(deadline)?))?;
+        stream.set_write_timeout(Some(time_until_deadline(deadline)?))?;
+    } else {
+        stream.set_read_timeout(unit.agent.config.timeout_read)?;
+        stream.set_write_timeout(unit.agent.config.timeout_write)?;
+    }
 
-    let tls_conf = &unit.agent.config.tls_config;
-    let https_stream = tls_conf.connect(hostname, Box::new(sock))?;
-    let pool_key = PoolKey::from_parts("https", hostname, port);
+    // A unix socket has no address, use the same placeholder as for the local address
+    // of a stream without a socket.
+    let remote_addr = std::net::SocketAddrV4::new(std::net::Ipv4Addr::new(127, 0, 0, 1), 0);
+    let pool_key = PoolKey::from_unix_socket(path, &unit.url);
     let pool_returner = PoolReturner::new(&unit.agent, pool_key);
-    Ok(Stream::new(https_stream, remote_addr, pool_returner))
+
+    if unit.url.scheme() == "https" {
+        let hostname = unit.url.host_str().unwrap_or("localhost");
+        let tls_conf = &unit.agent.config.tls_config;
+        let https_stream = tls_conf.connect(hostname, Box::new(stream))?;
+        return Ok(Stream::new(https_stream, remote_addr.into(), pool_returner));
+    }
+    Ok(Stream::new(stream, remote_addr.into(), pool_returner))
 }
 
-/// If successful, returns a `TcpStream` and the remote address it is connected to.
-pub(crate) fn connect_host(
+/// Connect through a SOCKS proxy, trying each of its addresses in turn.
+fn connect_socks_addrs(
     unit: &Unit,
+    proxy: Proxy,
+    connect_deadline: Option<Instant>,
+    sock_addrs: Vec<SocketAddr>,
     hostname: &str,
     port: u16,
 ) -> Result<(TcpStream, SocketAddr), Error> {
+    let mut any_err = None;
+    // Find the first sock_addr that accepts a connection
+    for sock_addr in sock_addrs {
+        // ensure connect timeout or overall timeout aren't yet hit.
+        if let Some(deadline) = connect_deadline {
+            time_until_deadline(deadline)?;
+        }
+
+        debug!(
+            "connecting to {}:{} at {}",
+            proxy.server, proxy.port, &sock_addr
+        );
+
+        let stream = connect_socks(
+            unit,
+            proxy.clone(),
+            connect_deadline,
+            sock_addr,
+            hostname,
+            port,
+            proxy.proto,
+        );
+
+        match stream {
+            Ok(stream) => return Ok((stream, sock_addr)),
+            Err(err) => any_err = Some(err),
+        }
+    }
+
+    match any_err {
+        Some(e) => Err(ErrorKind::ConnectionFailed.msg("Connect error").src(e)),
+        None => panic!("shouldn't happen: failed to connect to all IPs, but no error"),
+    }
+}
+
+/// How long to wait for a connection attempt before starting one to the next address.
+/// <https://www.rfc-editor.org/rfc/rfc8305#section-5>
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// Reorder addresses so that IPv6 and IPv4 alternate, starting with the family of the
+/// address the resolver put first. <https://www.rfc-editor.org/rfc/rfc8305#section-4>
+fn interleave_families(sock_addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let first_is_ipv6 = match sock_addrs.first() {
+        Some(addr) => addr.is_ipv6(),
+        None => return sock_addrs,
+    };
+    let (preferred, other): (Vec<_>, Vec<_>) = sock_addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_is_ipv6);
+
+    let mut interleaved = Vec::with_capacity(preferred.len() + other.len());
+    let mut preferred = preferred.into_iter();
+    let mut other = other.into_iter();
+    loop {
+        match (preferred.next(), other.next()) {
+            (None, None) => return interleaved,
+            (a, b) => interleaved.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// The most connection attempts to run at the same time.
+const MAX_CONCURRENT_ATTEMPTS: usize = 8;
+
+/// How long a connection attempt may take when there is no deadline.
+const MAX_ATTEMPT_DURATION: Duration = Duration::from_secs(30);
+
+/// Connect to the first of `sock_addrs` that accepts a connection, Happy Eyeballs style.
+/// <https://www.rfc-editor.org/rfc/rfc8305>
+///
+/// Attempts are started in order, each one `CONNECTION_ATTEMPT_DELAY` after the previous,
+/// or as soon as the previous one failed, with at most `MAX_CONCURRENT_ATTEMPTS` running
+/// at once. The first connection to succeed is used. A blocking connect can't be
+/// interrupted, so the attempts still in flight run on their own threads until they
+/// finish, at which point their sockets are closed. Each attempt is bounded by the
+/// deadline, or by `MAX_ATTEMPT_DURATION` without one, which also bounds those threads.
+fn connect_racing(
+    sock_addrs: &[SocketAddr],
+    deadline: Option<Instant>,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    race_attempts(
+        sock_addrs,
+        deadline,
+        MAX_CONCURRENT_ATTEMPTS,
+        MAX_ATTEMPT_DURATION,
+    )
+}
+
+fn race_attempts(
+    sock_addrs: &[SocketAddr],
+    deadline: Option<Instant>,
+    max_concurrent: usize,
+    max_duration: Duration,
+) -> io::Result<(TcpStream, SocketAddr)> {
+    // no need for threads when there is nothing to race.
+    if let [sock_addr] = sock_addrs {
+        return connect_addr(*sock_addr, deadline).map(|stream| (stream, *sock_addr));
+    }
+
+    let (tx, rx) = mpsc::channel();
+    let mut remaining = sock_addrs.iter();
+    let mut in_flight = 0;
+    let mut any_err = None;
+    loop {
+        if in_flight < max_concurrent {
+            if let Some(&sock_addr) = remaining.next() {
+                let tx = tx.clone();
+                let deadline = deadline.or_else(|| Instant::now().checked_add(max_duration));
+                thread::spawn(move || {
+                    let result = connect_addr(sock_addr, deadline);
+                    // the receiver is gone if another attempt won. dropping the stream
+                    // closes it.
+                    let _ = tx.send((sock_addr, result));
+                });
+                in_flight += 1;
+            }
+        }
+        if in_flight == 0 {
+            break;
+        }
+
+        // the attempts are bounded by their deadline themselves, so only the next
+        // attempt needs a timer.
+        let result = if in_flight < max_concurrent && !remaining.as_slice().is_empty() {
+            match rx.recv_timeout(CONNECTION_ATTEMPT_DELAY) {
+                Ok(result) => result,
+                Err(_) => continue,
+            }
+        } else {
+            match rx.recv() {
+                Ok(result) => result,
+                Err(_) => break,
+            }
+        };
+        in_flight -= 1;
+
+        match result {
+            (sock_addr, Ok(stream)) => return Ok((stream, sock_addr)),
+            (sock_addr, Err(err)) => {
+                debug!("failed to connect to {}: {}", sock_addr, err);
+                any_err = Some(err);
+            }
+        }
+    }
+
+    Err(any_err
+        .unwrap_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no addresses to connect to")))
+}
+
+/// Connect to one address, within the deadline if there is one.
+fn connect_addr(sock_addr: SocketAddr, deadline: Option<Instant>) -> io::Result<TcpStream> {
+    match deadline {
+        Some(deadline) => TcpStream::connect_timeout(&sock_addr, time_until_deadline(deadline)?),
+        None => TcpStream::connect(sock_addr),
+    }
+}
+
+/// If successful, returns the connection and the remote address it is connected to.
+///
+/// When going through an HTTP proxy to an https url, the returned connection is a tunnel
+/// to the host. With an HTTPS proxy, the connection to the proxy is itself over TLS.
+pub(crate) fn connect_host(
+    unit: &Unit,
+    hostname: &str,
+    port: u16,
+) -> Result<(Box<dyn ReadWrite>, SocketAddr), Error> {
+    let (mut stream, mut remote_addr) = connect_host_or_proxy(unit, hostname, port)?;
+
+    let proxy = match &unit.proxy {
+        Some(proxy) if proxy.is_http() && unit.url.scheme() == "https" => proxy,
+        _ => return Ok((stream, remote_addr)),
+    };
+
+    let (mut status, mut headers) = send_connect(unit, &mut stream, proxy, hostname, port, None)?;
+
+    // Answer an authentication challenge the credentials sent upfront didn't satisfy,
+    // i.e. Digest. The proxy may close the connection after a 407, so use a new one.
+    if status == 407 {
+        let uri = format!("{}:{}", hostname, port);
+        let authorization = proxy.authorization(&headers, "CONNECT", &uri);
+        if authorization.is_some() && authorization != proxy.basic_authorization() {
+            debug!("retrying CONNECT to {} with proxy authentication", uri);
+            (stream, remote_addr) = connect_host_or_proxy(unit, hostname, port)?;
+            (status, headers) = send_connect(
+                unit,
+                &mut stream,
+                proxy,
+                hostname,
+                port,
+                authorization.as_deref(),
+            )?;
+        }
+    }
+
+    proxy.verify_response(status, &headers)?;
+    Ok((stream, remote_addr))
+}
+
+/// Ask an HTTP proxy for a tunnel to `hostname`, and read its answer.
+fn send_connect(
+    unit: &Unit,
+    stream: &mut Box<dyn ReadWrite>,
+    proxy: &Proxy,
+    hostname: &str,
+    port: u16,
+    authorization: Option<&str>,
+) -> Result<(u16, Vec<Header>), Error> {
+    let user_agent = &unit.agent.config.user_agent;
+    write!(
+        stream,
+        "{}",
+        proxy.connect(hostname, port, user_agent, authorization)
+    )?;
+    stream.flush()?;
+
+    // Read the answer a byte at a time, so nothing of the tunneled connection
+    // ends up in a buffer.
+    response::read_head(&mut BufReader::with_capacity(1, stream))
+}
+
+/// Connect to the proxy of the unit if it has one, or else to the host.
+fn connect_host_or_proxy(
+    unit: &Unit,
+    hostname: &str,
+    port: u16,
+) -> Result<(Box<dyn ReadWrite>, SocketAddr), Error> {
     let connect_deadline: Option<Instant> =
         if let Some(timeout_connect) = unit.agent.config.timeout_connect {
             Instant::now().checked_add(timeout_connect)
         } else {
             unit.deadline
         };
-    let proxy: Option<Proxy> = unit.agent.config.proxy.clone();
+    let proxy: Option<Proxy> = unit.proxy.clone();
     let netloc = match proxy {
         Some(ref proxy) => format!("{}:{}", proxy.server, proxy.port),
         None => format!("{}:{}", hostname, port),
     };
 
-    // TODO: Find a way to apply deadline to DNS lookup.
-    let sock_addrs = unit.resolver().resolve(&netloc).map_err(|e| {
-        ErrorKind::Dns
-            .msg(format!("resolve dns name '{}'", netloc))
-            .src(e)
-    })?;
+    let sock_addrs = unit
+        .resolver()
+        .resolve_with_deadline(&netloc, connect_deadline)
+        .map_err(|e| {
+            let msg = if e.kind() == io::ErrorKind::TimedOut {
+                format!("timed out resolving dns name '{}'", netloc)
+            } else {
+                format!("resolve dns name '{}'", netloc)
+            };
+            ErrorKind::Dns.msg(msg).src(e)
+        })?;
 
     if sock_addrs.is_empty() {
         return Err(ErrorKind::Dns.msg(format!("No ip address for {}", hostname)));
@@ -372,50 +785,25 @@ pub(crate) fn connect_host(
 
     let proto = proxy.as_ref().map(|proxy| proxy.proto);
 
-    let mut any_err = None;
-    let mut any_stream_and_addr = None;
-    // Find the first sock_addr that accepts a connection
-    for sock_addr in sock_addrs {
+    let is_http_proxy = matches!(proxy, Some(ref p) if p.is_http());
+    let (stream, remote_addr) = if proto.is_some() && !is_http_proxy {
+        connect_socks_addrs(
+            unit,
+            proxy.clone().unwrap(),
+            connect_deadline,
+            sock_addrs,
+            hostname,
+            port,
+        )?
+    } else {
         // ensure connect timeout or overall timeout aren't yet hit.
-        let timeout = match connect_deadline {
-            Some(deadline) => Some(time_until_deadline(deadline)?),
-            None => None,
-        };
-
-        debug!("connecting to {} at {}", netloc, &sock_addr);
-
-        // connect with a configured timeout.
-        #[allow(clippy::unnecessary_unwrap)]
-        let stream = if proto.is_some() && Some(Proto::HTTP) != proto {
-            connect_socks(
-                unit,
-                proxy.clone().unwrap(),
-                connect_deadline,
-                sock_addr,
-                hostname,
-                port,
-                proto.unwrap(),
-            )
-        } else if let Some(timeout) = timeout {
-            TcpStream::connect_timeout(&sock_addr, timeout)
-        } else {
-            TcpStream::connect(sock_addr)
-        };
-
-        if let Ok(stream) = stream {
-            any_stream_and_addr = Some((stream, sock_addr));
-            break;
-        } else if let Err(err) = stream {
-            any_err = Some(err);
+        if let Some(deadline) = connect_deadline {
+            time_until_deadline(deadline)?;
         }
-    }
-
-    let (mut stream, remote_addr) = if let Some(stream_and_addr) = any_stream_and_addr {
-        stream_and_addr
-    } else if let Some(e) = any_err {
-        return Err(ErrorKind::ConnectionFailed.msg("Connect error").src(e));
-    } else {
-        panic!("shouldn't happen: failed to connect to all IPs, but no error");
+        debug!("connecting to {} at {:?}", netloc, sock_addrs);
+        let sock_addrs = interleave_families(sock_addrs);
+        connect_racing(&sock_addrs, connect_deadline)
+            .map_err(|e| ErrorKind::ConnectionFailed.msg("Connect error").src(e))?
     };
 
     stream.set_nodelay(unit.agent.config.no_delay)?;
@@ -432,24 +820,19 @@ pub(crate) fn connect_host(
         stream.set_write_timeout(unit.agent.config.timeout_write)?;
     }
 
-    if proto == Some(Proto::HTTP) && unit.url.scheme() == "https" {
-        if let Some(ref proxy) = proxy {
-            write!(
-                stream,
-                "{}",
-                proxy.connect(hostname, port, &unit.agent.config.user_agent)
-            )
-            .unwrap();
-            stream.flush()?;
-
-            let s = stream.try_clone()?;
-            let pool_key = PoolKey::from_parts(unit.url.scheme(), hostname, port);
-            let pool_returner = PoolReturner::new(&unit.agent, pool_key);
-            let s = Stream::new(s, remote_addr, pool_returner);
-            let response = Response::do_from_stream(s, unit.clone())?;
-            Proxy::verify_response(&response)?;
+    // Requests and CONNECT are sent to the proxy in HTTP/1.1, whatever the protocol
+    // spoken with the host.
+    let stream: Box<dyn ReadWrite> = match proxy {
+        Some(ref proxy) if proxy.proto == Proto::HTTPS => {
+            let tls_conf = &unit.agent.config.tls_config;
+            let stream = tls_conf.connect_http1(&proxy.server, Box::new(stream))?;
+            if stream.alpn_protocol() == Some(b"h2") {
+                return Err(ErrorKind::ProxyConnect.msg("HTTPS proxy negotiated HTTP/2"));
+            }
+            stream
         }
-    }
+        _ => Box::new(stream),
+    };
 
     Ok((stream, remote_addr))
 }
@@ -459,10 +842,11 @@ fn socks_local_nslookup(
     unit: &Unit,
     hostname: &str,
     port: u16,
+    deadline: Option<Instant>,
 ) -> Result<TargetAddr, std::io::Error> {
     let addrs: Vec<SocketAddr> = unit
         .resolver()
-        .resolve(&format!("{}:{}", hostname, port))
+        .resolve_with_deadline(&format!("{}:{}", hostname, port), deadline)
         .map_err(|e| {
             std::io::Error::new(io::ErrorKind::NotFound, format!("DNS failure: {}.", e))
         })?;
@@ -495,20 +879,18 @@ fn connect_socks(
     port: u16,
     proto: Proto,
 ) -> Result<TcpStream, std::io::Error> {
-    use socks::TargetAddr::Domain;
-    use std::net::{Ipv4Addr, Ipv6Addr};
-    use std::str::FromStr;
-
-    let host_addr = if Ipv4Addr::from_str(host).is_ok()
-        || Ipv6Addr::from_str(host).is_ok()
-        || proto == Proto::SOCKS4
-    {
-        match socks_local_nslookup(unit, host, port) {
-            Ok(addr) => addr,
-            Err(err) => return Err(err),
-        }
+    use std::net::IpAddr;
+
+    // Only SOCKS4 can't leave resolving the host name to the proxy.
+    let remote_dns = proto != Proto::SOCKS4;
+    // IPv6 addresses in urls are in brackets.
+    let ip = host.trim_start_matches('[').trim_end_matches(']');
+    let host_addr = if let Ok(ip) = ip.parse::<IpAddr>() {
+        TargetAddr::Ip(SocketAddr::new(ip, port))
+    } else if remote_dns {
+        TargetAddr::Domain(String::from(host), port)
     } else {
-        Domain(String::from(host), port)
+        socks_local_nslookup(unit, host, port, deadline)?
     };
 
     // Since SocksXStream doesn't support set_read_timeout, a suboptimal one is implemented via
@@ -534,7 +916,7 @@ fn connect_socks(
         thread::spawn(move || {
             let (lock, cvar) = &*slave_signal;
             if tx // try to get a socks stream and send it to the parent thread's rx
-                .send(if proto == Proto::SOCKS5 {
+                .send(if matches!(proto, Proto::SOCKS5 | Proto::SOCKS5H) {
                     get_socks5_stream(&proxy, &proxy_addr, host_addr)
                 } else {
                     get_socks4_stream(&proxy_addr, host_addr)
@@ -554,7 +936,11 @@ fn connect_socks(
         let done = lock.lock().unwrap();
 
         let timeout_connect = time_until_deadline(deadline)?;
-        let done_result = cvar.wait_timeout(done, timeout_connect).unwrap();
+        // the thread may be done before we wait, so check the flag rather than
+        // relying on the notification.
+        let done_result = cvar
+            .wait_timeout_while(done, timeout_connect, |done| !*done)
+            .unwrap();
         let done = done_result.0;
         if *done {
             rx.recv().unwrap()?
@@ -566,7 +952,7 @@ fn connect_socks(
                 timeout_connect.as_millis()
             )));
         }
-    } else if proto == Proto::SOCKS5 {
+    } else if matches!(proto, Proto::SOCKS5 | Proto::SOCKS5H) {
         get_socks5_stream(&proxy, &proxy_addr, host_addr)?
     } else {
         get_socks4_stream(&proxy_addr, host_addr)?
@@ -705,4 +1091,67 @@ mod tests {
         assert_eq!(reads[0], 8192);
         assert_eq!(reads[1], 8192);
     }
+
+    #[test]
+    fn interleave_address_families() {
+        let addrs: Vec<SocketAddr> = ["[::1]:1", "[::2]:1", "[::3]:1", "10.0.0.1:1", "10.0.0.2:1"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let interleaved = interleave_families(addrs.clone());
+        let expected = vec![addrs[0], addrs[3], addrs[1], addrs[4], addrs[2]];
+        assert_eq!(interleaved, expected);
+
+        let addrs: Vec<SocketAddr> = ["10.0.0.1:1", "[::1]:1"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        assert_eq!(interleave_families(addrs.clone()), addrs);
+        assert_eq!(interleave_families(vec![]), vec![]);
+    }
+
+    // An address nothing listens on.
+    fn refused_addr() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    #[test]
+    fn connect_racing_skips_failed_addresses() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap();
+        // TEST-NET-1, which is either unreachable or never answers.
+        let blackhole: SocketAddr = "192.0.2.1:80".parse().unwrap();
+        let addrs = [refused_addr(), blackhole, good];
+
+        let start = Instant::now();
+        let deadline = start + Duration::from_secs(10);
+        let (_stream, addr) = connect_racing(&addrs, Some(deadline)).unwrap();
+        assert_eq!(addr, good);
+        // the blackhole doesn't hold up the attempt after it for long.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn connect_racing_bounds_attempts() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let good = listener.local_addr().unwrap();
+        let blackhole: SocketAddr = "192.0.2.1:80".parse().unwrap();
+        let addrs = [blackhole, good];
+
+        // one attempt at a time, and no deadline: the blackhole, if it never answers, is
+        // given up on after the longest duration of an attempt.
+        let start = Instant::now();
+        let max = Duration::from_millis(300);
+        let (_stream, addr) = race_attempts(&addrs, None, 1, max).unwrap();
+        assert_eq!(addr, good);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn connect_racing_all_fail() {
+        let addrs = [refused_addr(), refused_addr()];
+        let err = connect_racing(&addrs, None).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
 }
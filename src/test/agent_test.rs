@@ -4,6 +4,8 @@ use crate::error::Error;
 use crate::testserver::{read_request, TestServer};
 use std::io::{self, Read, Write};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
 use std::thread;
 use std::time::Duration;
 
@@ -57,6 +59,30 @@ fn connection_reuse() {
     assert_eq!(resp.status(), 200);
 }
 
+#[cfg(feature = "json")]
+fn ndjson_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 16\r\n\r\n{\"n\":1}\n{\"n\":2}\n")
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn json_stream_returns_connection() {
+    let testserver = TestServer::new(ndjson_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::new();
+    let mut records = agent
+        .get(&url)
+        .call()
+        .unwrap()
+        .into_json_stream::<serde_json::Value>();
+
+    assert_eq!(records.next().unwrap().unwrap()["n"], 1);
+    assert_eq!(records.next().unwrap().unwrap()["n"], 2);
+    assert!(records.next().is_none());
+    assert_eq!(agent.state.pool.len(), 1);
+}
+
 #[test]
 fn connection_reuse_with_408() {
     let testserver = TestServer::new(idle_timeout_handler_408);
@@ -207,3 +233,438 @@ fn dirty_streams_not_returned() -> Result<(), Error> {
     let _resp_to_succeed = agent.get(&url).call()?;
     Ok(())
 }
+
+#[test]
+fn custom_connector() -> Result<(), Error> {
+    use crate::test::TestStream;
+    use std::io::Cursor;
+    use std::sync::{Arc, Mutex};
+    use url::Url;
+
+    let connects = Arc::new(Mutex::new(vec![]));
+    let connects2 = connects.clone();
+    let agent = builder()
+        .connector(
+            move |url: &Url, host: &str, port: u16| -> Result<Box<dyn ReadWrite>, Error> {
+                connects2
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}:{}", url, host, port));
+                // Two responses, so the second request can reuse the connection.
+                let responses = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nfirst\
+                                 HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecond";
+                Ok(Box::new(TestStream::new(
+                    Cursor::new(responses),
+                    io::sink(),
+                )))
+            },
+        )
+        .build();
+
+    let body = agent.get("http://example.com/a").call()?.into_string()?;
+    assert_eq!(body, "first");
+    let body = agent.get("http://example.com/b").call()?.into_string()?;
+    assert_eq!(body, "second");
+
+    // The connection was pooled, so the connector was only asked once.
+    let connects = connects.lock().unwrap();
+    assert_eq!(*connects, vec!["http://example.com/a example.com:80"]);
+    Ok(())
+}
+
+#[test]
+fn custom_connector_returns_final_stream() -> Result<(), Error> {
+    use crate::test::{Recorder, TestStream};
+    use std::io::Cursor;
+    use url::Url;
+
+    let recorder = Recorder::default();
+    let recorder2 = recorder.clone();
+    let agent = builder()
+        .proxy(Proxy::new("http://proxy.invalid:3128")?)
+        .connector(
+            move |_url: &Url, _host: &str, _port: u16| -> Result<Box<dyn ReadWrite>, Error> {
+                let response = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nplain";
+                Ok(Box::new(TestStream::new(
+                    Cursor::new(response),
+                    recorder2.clone(),
+                )))
+            },
+        )
+        .build();
+
+    // No TLS is added on top of the stream, and the proxy is not used.
+    let body = agent.get("https://example.com/a").call()?.into_string()?;
+    assert_eq!(body, "plain");
+    assert!(recorder.contains("GET /a HTTP/1.1\r\n"));
+    assert!(!recorder.contains("proxy"));
+    Ok(())
+}
+
+// Handler that answers with the request target, which is in absolute form when
+// the request was sent through a proxy.
+fn request_target_handler(mut stream: TcpStream) -> io::Result<()> {
+    let headers = read_request(&stream);
+    let target = headers.path();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        target.len(),
+        target
+    )
+}
+
+#[test]
+fn proxy_selector() -> Result<(), Error> {
+    use url::Url;
+
+    let testserver = TestServer::new(request_target_handler);
+    let proxy = Proxy::new(format!("127.0.0.1:{}", testserver.port))?;
+    // Nothing listens on the port of this one.
+    let dead_port = std::net::TcpListener::bind("127.0.0.1:0")?
+        .local_addr()?
+        .port();
+    let dead_proxy = Proxy::new(format!("127.0.0.1:{}", dead_port))?;
+
+    let agent = builder()
+        .proxy_selector(move |url: &Url| match url.host_str() {
+            Some("localhost") => vec![],
+            _ => vec![dead_proxy.clone(), proxy.clone()],
+        })
+        .build();
+
+    let direct = format!("http://localhost:{}/direct", testserver.port);
+    assert_eq!(agent.get(&direct).call()?.into_string()?, "/direct");
+
+    // The first proxy can't be reached, so the second one is used.
+    let proxied = "http://example.invalid/proxied";
+    assert_eq!(agent.get(proxied).call()?.into_string()?, proxied);
+    Ok(())
+}
+
+#[test]
+fn request_proxy_override() -> Result<(), Error> {
+    use url::Url;
+
+    let testserver = TestServer::new(request_target_handler);
+    let proxy = Proxy::new(format!("127.0.0.1:{}", testserver.port))?;
+    let agent = builder()
+        .proxy_selector(|_: &Url| vec![Proxy::new("proxy.invalid").unwrap()])
+        .build();
+
+    let url = format!("http://127.0.0.1:{}/override", testserver.port);
+    let body = agent.get(&url).proxy(None).call()?.into_string()?;
+    assert_eq!(body, "/override");
+    let body = agent.get(&url).proxy(Some(proxy)).call()?.into_string()?;
+    assert_eq!(body, url);
+    Ok(())
+}
+
+static KEEP_ALIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Handler like request_target_handler, that keeps the connection open for more
+// requests and prefixes the target with the number of the connection.
+fn keep_alive_target_handler(mut stream: TcpStream) -> io::Result<()> {
+    let connection = KEEP_ALIVE_CONNECTIONS.fetch_add(1, Ordering::SeqCst) + 1;
+    loop {
+        let headers = read_request(&stream);
+        stream.set_nonblocking(false)?;
+        let target = headers.path();
+        if target.is_empty() {
+            return Ok(());
+        }
+        let body = format!("{}|{}", connection, target);
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )?;
+    }
+}
+
+#[test]
+fn proxied_connections_pooled_apart() -> Result<(), Error> {
+    let testserver = TestServer::new(keep_alive_target_handler);
+    let proxy = Proxy::new(format!("127.0.0.1:{}", testserver.port))?;
+    let agent = builder().build();
+    let url = format!("http://127.0.0.1:{}/a", testserver.port);
+
+    let first = agent.get(&url).proxy(Some(proxy.clone())).call()?;
+    let first = first.into_string()?;
+    let (connection, target) = first.split_once('|').unwrap();
+    assert_eq!(target, url);
+
+    // A direct request must not be sent over the connection to the proxy.
+    let direct = agent.get(&url).call()?.into_string()?;
+    assert_ne!(direct, format!("{}|/a", connection));
+    assert!(direct.ends_with("|/a"));
+
+    // The connection to the proxy is reused for requests through it.
+    let again = agent.get(&url).proxy(Some(proxy)).call()?.into_string()?;
+    assert_eq!(again, first);
+    Ok(())
+}
+
+// Handler for a proxy that accepts CONNECT, then answers the tunneled request
+// like request_target_handler.
+fn connect_proxy_handler(mut stream: TcpStream) -> io::Result<()> {
+    let headers = read_request(&stream);
+    if headers.method() == "CONNECT" {
+        stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+        // read_request() leaves the stream non-blocking.
+        stream.set_nonblocking(false)?;
+        return request_target_handler(stream);
+    }
+    let target = headers.path();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        target.len(),
+        target
+    )
+}
+
+// Records the names TLS is set up for, without actually encrypting anything.
+struct PlainTls(std::sync::Mutex<Vec<String>>);
+
+impl TlsConnector for PlainTls {
+    fn connect(&self, dns_name: &str, io: Box<dyn ReadWrite>) -> Result<Box<dyn ReadWrite>, Error> {
+        self.0.lock().unwrap().push(dns_name.to_string());
+        Ok(io)
+    }
+}
+
+#[test]
+fn https_proxy() -> Result<(), Error> {
+    use std::sync::{Arc, Mutex};
+
+    let testserver = TestServer::new(connect_proxy_handler);
+    let proxy = Proxy::new(format!("https://127.0.0.1:{}", testserver.port))?;
+    let tls = Arc::new(PlainTls(Mutex::new(vec![])));
+    let agent = builder().proxy(proxy).tls_connector(tls.clone()).build();
+
+    // http urls are requested from the proxy in absolute form.
+    let body = agent
+        .get("http://example.invalid/plain")
+        .call()?
+        .into_string()?;
+    assert_eq!(body, "http://example.invalid/plain");
+    assert_eq!(*tls.0.lock().unwrap(), vec!["127.0.0.1"]);
+
+    // https urls are tunneled with CONNECT, with TLS to the host inside TLS to the proxy.
+    let body = agent
+        .get("https://example.invalid/tunnel")
+        .call()?
+        .into_string()?;
+    assert_eq!(body, "https://example.invalid/tunnel");
+    assert_eq!(
+        *tls.0.lock().unwrap(),
+        vec!["127.0.0.1", "127.0.0.1", "example.invalid"]
+    );
+    Ok(())
+}
+
+// Handler for a proxy that requires Digest authentication, then acts like
+// connect_proxy_handler. The tunneled request is answered with the authorization.
+fn digest_proxy_handler(mut stream: TcpStream) -> io::Result<()> {
+    let headers = read_request(&stream);
+    let authorization = headers
+        .headers()
+        .iter()
+        .find_map(|h| h.strip_prefix("Proxy-Authorization: Digest "))
+        .map(|a| a.to_string());
+    let authorization = match authorization {
+        Some(a) => a,
+        None => {
+            return stream.write_all(
+                b"HTTP/1.1 407 Proxy Authentication Required\r\n\
+                Proxy-Authenticate: Digest realm=\"squid\", nonce=\"abc\", qop=\"auth\"\r\n\
+                Content-Length: 0\r\n\r\n",
+            );
+        }
+    };
+    if headers.method() == "CONNECT" {
+        stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")?;
+        stream.set_nonblocking(false)?;
+        read_request(&stream);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        authorization.len(),
+        authorization
+    )
+}
+
+#[test]
+#[cfg(feature = "digest-auth")]
+fn proxy_digest_auth() -> Result<(), Error> {
+    use std::sync::{Arc, Mutex};
+
+    let testserver = TestServer::new(digest_proxy_handler);
+    let proxy = Proxy::new(format!("user:secret@127.0.0.1:{}", testserver.port))?;
+    let agent = builder()
+        .proxy(proxy)
+        .tls_connector(Arc::new(PlainTls(Mutex::new(vec![]))))
+        .build();
+
+    let body = agent
+        .get("http://example.invalid/a?b=c")
+        .call()?
+        .into_string()?;
+    assert!(body.starts_with(r#"username="user", realm="squid", nonce="abc""#));
+    assert!(body.contains(r#"uri="http://example.invalid/a?b=c""#));
+
+    let body = agent
+        .get("https://example.invalid/")
+        .call()?
+        .into_string()?;
+    assert!(body.contains(r#"uri="example.invalid:443""#));
+    assert!(body.contains("qop=auth, nc=00000001"));
+    Ok(())
+}
+
+#[test]
+fn proxy_auth_required() {
+    use std::sync::{Arc, Mutex};
+
+    let testserver = TestServer::new(digest_proxy_handler);
+    let proxy = Proxy::new(format!("127.0.0.1:{}", testserver.port)).unwrap();
+    let agent = builder()
+        .proxy(proxy)
+        .tls_connector(Arc::new(PlainTls(Mutex::new(vec![]))))
+        .build();
+
+    // Without credentials, the challenge is in the error.
+    let err = agent.get("https://example.invalid/").call().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::ProxyUnauthorized);
+    assert!(err
+        .to_string()
+        .contains(r#"Digest realm="squid", nonce="abc", qop="auth""#));
+
+    // Plain http requests get the 407 response.
+    let err = agent.get("http://example.invalid/").call().unwrap_err();
+    assert!(matches!(err, Error::Status(407, _)));
+}
+
+// Switches to a protocol that greets, then echoes five bytes.
+fn upgrade_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request(&stream);
+    stream.write_all(
+        b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: echo\r\n\r\nhi",
+    )?;
+    stream.set_nonblocking(false)?;
+    let mut buf = [0; 5];
+    stream.read_exact(&mut buf)?;
+    stream.write_all(&buf)
+}
+
+#[test]
+fn upgrade() {
+    let testserver = TestServer::new(upgrade_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::new();
+    let resp = agent
+        .get(&url)
+        .set("Connection", "Upgrade")
+        .set("Upgrade", "echo")
+        .call()
+        .unwrap();
+    assert_eq!(resp.status(), 101);
+
+    let mut stream = resp.into_upgraded().unwrap();
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).unwrap();
+    assert_eq!(&greeting, b"hi");
+    stream.write_all(b"hello").unwrap();
+    let mut echo = [0; 5];
+    stream.read_exact(&mut echo).unwrap();
+    assert_eq!(&echo, b"hello");
+
+    drop(stream);
+    assert_eq!(agent.state.pool.len(), 0);
+}
+
+#[test]
+fn upgrade_refused() {
+    let testserver = TestServer::new(idle_timeout_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let resp = Agent::new().get(&url).call().unwrap();
+    let err = resp.into_upgraded().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadStatus);
+}
+
+static RETRY_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+// Handler that answers 503 with a Retry-After of zero seconds to the first
+// two requests, and 200 to the following ones.
+fn unavailable_twice_handler(mut stream: TcpStream) -> io::Result<()> {
+    // Skip the connection made by TestServer::new() to check it is up.
+    if read_request(&stream).path().is_empty() {
+        return Ok(());
+    }
+    if RETRY_ATTEMPTS.fetch_add(1, Ordering::SeqCst) < 2 {
+        stream.write_all(UNAVAILABLE)?;
+    } else {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+    Ok(())
+}
+
+#[test]
+fn retry_unavailable() {
+    let testserver = TestServer::new(unavailable_twice_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let chained = Arc::new(AtomicUsize::new(0));
+    let counter = chained.clone();
+    let agent = builder()
+        .retry(RetryPolicy::new().backoff(Duration::from_secs(60), Duration::from_secs(60)))
+        .middleware(move |req: Request, next: MiddlewareNext| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            next.handle(req)
+        })
+        .build();
+    let resp = agent.put(&url).send_string("body").unwrap();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    assert_eq!(RETRY_ATTEMPTS.load(Ordering::SeqCst), 3);
+    // Every attempt goes through the middleware chain.
+    assert_eq!(chained.load(Ordering::SeqCst), 3);
+}
+
+const UNAVAILABLE: &[u8] = b"HTTP/1.1 503 Service Unavailable\r\nRetry-After: 0\r\n\
+    Connection: close\r\nContent-Length: 0\r\n\r\n";
+
+fn unavailable_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request(&stream);
+    stream.write_all(UNAVAILABLE)
+}
+
+#[test]
+fn retry_exhausted() {
+    let testserver = TestServer::new(unavailable_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = builder().retry(RetryPolicy::new().max_attempts(2)).build();
+    let err = agent.get(&url).call().unwrap_err();
+    assert!(matches!(err, Error::Status(503, _)));
+}
+
+static STREAMED_ATTEMPTS: AtomicUsize = AtomicUsize::new(0);
+
+fn count_unavailable_handler(mut stream: TcpStream) -> io::Result<()> {
+    if read_request(&stream).path().is_empty() {
+        return Ok(());
+    }
+    STREAMED_ATTEMPTS.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(UNAVAILABLE)
+}
+
+#[test]
+fn retry_skips_streamed_body() {
+    let testserver = TestServer::new(count_unavailable_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = builder().retry(RetryPolicy::new()).build();
+    // Depending on timing, this is the 503 or an error sending the body.
+    agent.put(&url).send(&b"body"[..]).unwrap_err();
+    assert_eq!(STREAMED_ATTEMPTS.load(Ordering::SeqCst), 1);
+}
@@ -63,6 +63,28 @@ fn ignore_content_length_when_chunked() {
     assert_eq!(text, "hello world!!!");
 }
 
+#[test]
+fn chunked_trailers() {
+    test::set_handler("/chunked_trailers", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked", "Trailer: grpc-status"],
+            "5\r\nhello\r\n0\r\ngrpc-status: 0\r\nServer-Timing: db;dur=53\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let resp = get("test://host/chunked_trailers").call().unwrap();
+    let trailers = resp.trailers();
+    let mut reader = resp.into_reader();
+    let mut text = String::new();
+    reader.read_to_string(&mut text).unwrap();
+    assert_eq!(text, "hello");
+    assert_eq!(trailers.header("grpc-status"), Some("0".to_string()));
+    assert_eq!(trailers.all("server-timing"), vec!["db;dur=53"]);
+}
+
 #[test]
 fn no_reader_on_head() {
     test::set_handler("/no_reader_on_head", |_unit| {
@@ -128,3 +150,188 @@ fn brotli_text() {
     let text = resp.into_string().unwrap();
     assert_eq!(text, "hello world ".repeat(14).trim_end());
 }
+
+#[cfg(feature = "deflate")]
+fn encode(mut encoder: impl std::io::Write, data: &[u8]) {
+    encoder.write_all(data).unwrap();
+}
+
+#[cfg(feature = "deflate")]
+#[test]
+fn deflate_text() {
+    use flate2::write::{DeflateEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    let text = "hello world ".repeat(14);
+    let mut zlib = vec![];
+    encode(
+        ZlibEncoder::new(&mut zlib, Compression::best()),
+        text.as_bytes(),
+    );
+    // Some servers send raw deflate, without the zlib wrapper.
+    let mut raw = vec![];
+    encode(
+        DeflateEncoder::new(&mut raw, Compression::best()),
+        text.as_bytes(),
+    );
+
+    for (path, body) in [("/deflate_zlib", zlib), ("/deflate_raw", raw)] {
+        test::set_handler(path, move |_unit| {
+            test::make_response(200, "OK", vec!["content-encoding: deflate"], body.clone())
+        });
+        let resp = get(&format!("test://host{}", path)).call().unwrap();
+        assert_eq!(resp.header("content-encoding"), None);
+        assert_eq!(resp.into_string().unwrap(), text);
+    }
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn zstd_text() {
+    let text = "hello world ".repeat(14);
+    let body = zstd::encode_all(text.as_bytes(), 0).unwrap();
+
+    test::set_handler("/zstd_text", move |unit| {
+        let accept = unit.header("accept-encoding").unwrap_or_default();
+        assert!(accept.split(", ").any(|coding| coding == "zstd"));
+        test::make_response(200, "OK", vec!["content-encoding: zstd"], body.clone())
+    });
+    let resp = get("test://host/zstd_text").call().unwrap();
+    assert_eq!(resp.header("content-encoding"), None);
+    assert_eq!(resp.into_string().unwrap(), text);
+}
+
+#[cfg(all(feature = "gzip", feature = "deflate"))]
+#[test]
+fn stacked_content_encoding() {
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use flate2::Compression;
+
+    let text = "hello world ".repeat(14);
+    let mut deflated = vec![];
+    encode(
+        ZlibEncoder::new(&mut deflated, Compression::best()),
+        text.as_bytes(),
+    );
+    let mut body = vec![];
+    encode(GzEncoder::new(&mut body, Compression::best()), &deflated);
+
+    test::set_handler("/stacked_content_encoding", move |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec![
+                "content-encoding: deflate, identity",
+                "content-encoding: GZIP",
+            ],
+            body.clone(),
+        )
+    });
+    let resp = get("test://host/stacked_content_encoding").call().unwrap();
+    assert_eq!(resp.header("content-encoding"), None);
+    assert_eq!(resp.into_string().unwrap(), text);
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn unsupported_content_encoding() {
+    test::set_handler("/unsupported_content_encoding", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["content-length: 5", "content-encoding: gzip, unknown"],
+            b"hello".to_vec(),
+        )
+    });
+    let resp = get("test://host/unsupported_content_encoding")
+        .call()
+        .unwrap();
+    // The body can't be decoded, so it is passed along as is.
+    assert_eq!(resp.header("content-encoding"), Some("gzip, unknown"));
+    assert_eq!(resp.header("content-length"), Some("5"));
+    assert_eq!(resp.into_string().unwrap(), "hello");
+}
+
+fn is_limit_exceeded(err: &std::io::Error) -> bool {
+    let err = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+    err.map(|e| e.kind()) == Some(ErrorKind::LimitExceeded)
+}
+
+#[test]
+fn max_body_size() {
+    test::set_handler("/max_body_size", |_unit| {
+        test::make_response(200, "OK", vec!["content-length: 100"], vec![b'a'; 100])
+    });
+    let resp = get("test://host/max_body_size")
+        .max_body_size(100)
+        .call()
+        .unwrap();
+    assert_eq!(resp.into_string().unwrap().len(), 100);
+
+    test::set_handler("/max_body_size", |_unit| {
+        test::make_response(200, "OK", vec!["content-length: 100"], vec![b'a'; 100])
+    });
+    let agent = builder().max_body_size(99).build();
+    let resp = agent.get("test://host/max_body_size").call().unwrap();
+    let mut body = vec![];
+    let err = resp.into_reader().read_to_end(&mut body).unwrap_err();
+    assert!(is_limit_exceeded(&err));
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn max_compression_ratio() {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    let mut encoder = GzEncoder::new(vec![], Compression::best());
+    encoder.write_all(&vec![0; 4 * 1024 * 1024]).unwrap();
+    let bomb = encoder.finish().unwrap();
+
+    let handler = move |_unit: &test::Unit| {
+        let len = format!("content-length: {}", bomb.len());
+        test::make_response(
+            200,
+            "OK",
+            vec![&len, "content-encoding: gzip"],
+            bomb.clone(),
+        )
+    };
+    test::set_handler("/max_compression_ratio", handler.clone());
+    let resp = get("test://host/max_compression_ratio")
+        .max_compression_ratio(100)
+        .call()
+        .unwrap();
+    let mut body = vec![];
+    let err = resp.into_reader().read_to_end(&mut body).unwrap_err();
+    assert!(is_limit_exceeded(&err));
+
+    // Without the limit, the body decodes fine.
+    test::set_handler("/max_compression_ratio", handler);
+    let resp = get("test://host/max_compression_ratio").call().unwrap();
+    let mut body = vec![];
+    resp.into_reader().read_to_end(&mut body).unwrap();
+    assert_eq!(body.len(), 4 * 1024 * 1024);
+}
+
+#[test]
+fn max_header_size() {
+    test::set_handler("/max_header_size", |_unit| {
+        test::make_response(200, "OK", vec!["x-big: aaaaaaaaaaaaaaaaaaaa"], vec![])
+    });
+    let err = get("test://host/max_header_size")
+        .max_header_size(30)
+        .call()
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::LimitExceeded);
+
+    test::set_handler("/max_header_size", |_unit| {
+        test::make_response(200, "OK", vec!["x-big: aaaaaaaaaaaaaaaaaaaa"], vec![])
+    });
+    let resp = get("test://host/max_header_size")
+        .max_header_size(100)
+        .call()
+        .unwrap();
+    assert_eq!(resp.header("x-big"), Some("aaaaaaaaaaaaaaaaaaaa"));
+}
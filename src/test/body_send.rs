@@ -1,4 +1,11 @@
-use crate::test::Recorder;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::pool::PoolReturner;
+use crate::stream::{remote_addr_for_test, Stream};
+use crate::test::{self, Recorder, TestStream};
+use crate::testserver::TestServer;
 
 use super::super::*;
 
@@ -47,6 +54,174 @@ fn content_length_and_chunked() {
     assert!(!recorder.contains("\r\nContent-Length:\r\n"));
 }
 
+#[test]
+fn chunked_trailers() {
+    let recorder = Recorder::register("/chunked_trailers");
+    post("test://host/chunked_trailers")
+        .send_with_trailers(Cursor::new(b"Hello World!!!"), &["X-Checksum"], || {
+            vec![Header::new("X-Checksum", "abc")]
+        })
+        .unwrap();
+    assert!(recorder.contains("Trailer: X-Checksum\r\n"));
+    assert!(recorder.contains("Transfer-Encoding: chunked\r\n"));
+    assert!(recorder.contains("\r\nHello World!!!\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+}
+
+#[test]
+fn chunked_trailers_any_case() {
+    let recorder = Recorder::register("/chunked_trailers_case");
+    post("test://host/chunked_trailers_case")
+        .set("Transfer-Encoding", "Chunked")
+        .send_with_trailers(Cursor::new(b"Hello World!!!"), &["X-Checksum"], || {
+            vec![Header::new("X-Checksum", "abc")]
+        })
+        .unwrap();
+    assert!(recorder.contains("\r\nHello World!!!\r\n0\r\nX-Checksum: abc\r\n\r\n"));
+}
+
+#[test]
+fn trailers_need_chunked_body() {
+    let err = post("test://host/trailers_sized")
+        .set("Content-Length", "14")
+        .send_with_trailers(Cursor::new(b"Hello World!!!"), &["X-Checksum"], Vec::new)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadHeader);
+
+    let err = post("test://host/trailers_sized")
+        .set("Transfer-Encoding", "gzip")
+        .send_with_trailers(Cursor::new(b"Hello World!!!"), &["X-Checksum"], Vec::new)
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadHeader);
+}
+
+#[cfg(feature = "gzip")]
+fn gunzip_body(recorded: &[u8]) -> String {
+    let start = recorded.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let mut body = String::new();
+    flate2::read::GzDecoder::new(&recorded[start..])
+        .read_to_string(&mut body)
+        .unwrap();
+    body
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compress_str() {
+    let recorder = Recorder::register("/compress_str");
+    post("test://host/compress_str")
+        .set("Content-Length", "14")
+        .compress(Some(ContentEncoding::Gzip))
+        .send_string("Hello World!!!")
+        .unwrap();
+    assert!(recorder.contains("\r\nContent-Encoding: gzip\r\n"));
+    assert!(!recorder.contains("\r\nContent-Length: 14\r\n"));
+    assert!(!recorder.contains("Transfer-Encoding"));
+    let recorded = recorder.to_vec();
+    assert_eq!(gunzip_body(&recorded), "Hello World!!!");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compress_reader() {
+    let recorder = Recorder::register("/compress_reader");
+    let agent = builder().compress(ContentEncoding::Gzip).build();
+    agent
+        .post("test://host/compress_reader")
+        .send(Cursor::new(b"Hello World!!!"))
+        .unwrap();
+    assert!(recorder.contains("\r\nContent-Encoding: gzip\r\n"));
+    assert!(recorder.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!recorder.contains("Hello World!!!"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compress_seekable_streams() {
+    let recorder = Recorder::register("/compress_seekable");
+    post("test://host/compress_seekable")
+        .compress(Some(ContentEncoding::Gzip))
+        .send_seekable(Cursor::new(b"Hello World!!!"))
+        .unwrap();
+    assert!(recorder.contains("\r\nContent-Encoding: gzip\r\n"));
+    assert!(recorder.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!recorder.contains("Content-Length"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn compress_opt_out() {
+    let recorder = Recorder::register("/compress_opt_out");
+    let agent = builder().compress(ContentEncoding::Gzip).build();
+    agent
+        .post("test://host/compress_opt_out")
+        .compress(None)
+        .send_string("Hello World!!!")
+        .unwrap();
+    assert!(!recorder.contains("Content-Encoding"));
+    assert!(recorder.contains("\r\nContent-Length: 14\r\n"));
+
+    // Bodies that are already encoded are left alone.
+    let recorder = Recorder::register("/compress_already_encoded");
+    agent
+        .post("test://host/compress_already_encoded")
+        .set("Content-Encoding", "br")
+        .send_bytes(b"not really br")
+        .unwrap();
+    assert!(recorder.contains("\r\nContent-Encoding: br\r\n"));
+    assert!(recorder.contains("\r\nnot really br"));
+}
+
+#[test]
+#[cfg(feature = "brotli")]
+fn compress_brotli() {
+    let recorder = Recorder::register("/compress_brotli");
+    post("test://host/compress_brotli")
+        .compress(Some(ContentEncoding::Brotli))
+        .send_string("Hello World!!!")
+        .unwrap();
+    assert!(recorder.contains("\r\nContent-Encoding: br\r\n"));
+    let recorded = recorder.to_vec();
+    let start = recorded.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let mut body = String::new();
+    brotli_decompressor::Decompressor::new(&recorded[start..], 4096)
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, "Hello World!!!");
+}
+
+#[test]
+fn multipart_sized() {
+    let recorder = Recorder::register("/multipart_sized");
+    let form = Multipart::new()
+        .text("name", "ureq")
+        .part("data", Part::bytes(b"hello").file_name("hello.txt"));
+    let boundary = form.boundary().to_string();
+    post("test://host/multipart_sized")
+        .send_multipart(form)
+        .unwrap();
+    let content_type = format!(
+        "\r\nContent-Type: multipart/form-data; boundary={}\r\n",
+        boundary
+    );
+    assert!(recorder.contains(&content_type));
+    assert!(recorder.contains("\r\nContent-Length: "));
+    assert!(recorder.contains(&format!(
+        "--{}\r\nContent-Disposition: form-data; name=\"data\"; filename=\"hello.txt\"\r\n\r\nhello\r\n--{}--\r\n",
+        boundary, boundary
+    )));
+}
+
+#[test]
+fn multipart_unsized() {
+    let recorder = Recorder::register("/multipart_unsized");
+    let form = Multipart::new().part("data", Part::reader(Cursor::new(b"hello".to_vec())));
+    post("test://host/multipart_unsized")
+        .send_multipart(form)
+        .unwrap();
+    assert!(recorder.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!recorder.contains("\r\nContent-Length: "));
+}
+
 #[test]
 #[cfg(feature = "charset")]
 fn str_with_encoding() {
@@ -93,3 +268,85 @@ fn content_type_not_overriden_on_json() {
         .unwrap();
     assert!(recorder.contains("\r\ncontent-type: text/plain\r\n"));
 }
+
+// Register a handler that answers with `response`, recording what is sent.
+fn register_expect_continue(path: &str, response: &'static [u8]) -> Recorder {
+    let recorder = Recorder::default();
+    let recorder2 = recorder.clone();
+    test::set_handler(path, move |_unit| {
+        Ok(Stream::new(
+            TestStream::new(Cursor::new(response), recorder.clone()),
+            remote_addr_for_test(),
+            PoolReturner::none(),
+        ))
+    });
+    recorder2
+}
+
+#[test]
+fn expect_continue_sends_body_after_100() {
+    let recorder = register_expect_continue(
+        "/expect_continue_sends_body_after_100",
+        b"HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok",
+    );
+    let resp = put("test://host/expect_continue_sends_body_after_100")
+        .set("Expect", "100-continue")
+        .send_string("Hello World!!!")
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "ok");
+    assert!(recorder.contains("\r\nExpect: 100-continue\r\n"));
+    assert!(recorder.contains("Hello World!!!"));
+}
+
+#[test]
+fn expect_continue_final_status_skips_body() {
+    let recorder = register_expect_continue(
+        "/expect_continue_final_status_skips_body",
+        b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n",
+    );
+    let result = put("test://host/expect_continue_final_status_skips_body")
+        .set("Expect", "100-continue")
+        .send_string("Hello World!!!");
+    assert!(matches!(result, Err(Error::Status(413, _))));
+    assert!(recorder.contains("\r\nExpect: 100-continue\r\n"));
+    assert!(!recorder.contains("Hello World!!!"));
+}
+
+// Reply with the request body, without ever sending 100 Continue.
+fn echo_without_continue(mut stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        if line.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = line.to_ascii_lowercase().strip_prefix("content-length:") {
+            content_length = value.trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(&body)
+}
+
+#[test]
+fn expect_continue_timeout_sends_body() {
+    let server = TestServer::new(echo_without_continue);
+    let agent = builder()
+        .timeout_expect_continue(Duration::from_millis(100))
+        .build();
+    let resp = agent
+        .put(&format!("http://localhost:{}/", server.port))
+        .set("Expect", "100-continue")
+        .send_string("Hello World!!!")
+        .unwrap();
+    assert_eq!(resp.into_string().unwrap(), "Hello World!!!");
+}
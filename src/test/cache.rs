@@ -0,0 +1,170 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::testserver::{read_request, TestServer};
+
+use super::super::*;
+
+// The requests received by `caching_server`, as "METHOD /path".
+static REQUESTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn requests(request: &str) -> usize {
+    REQUESTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| *r == request)
+        .count()
+}
+
+// Answers depending on the first path segment:
+// - /max-age/.. is fresh for a minute.
+// - /etag/.. must be revalidated, and is answered with a 304 when it is.
+// - /vary/.. is fresh for a minute, and varies on Accept.
+// - /stream/.. must be revalidated, and sends its body until the connection is closed,
+//   a second later.
+fn caching_server(mut stream: TcpStream) -> io::Result<()> {
+    let request = read_request(&stream);
+    if request.path().is_empty() {
+        return Ok(());
+    }
+    let path = request.path().to_string();
+    REQUESTS
+        .lock()
+        .unwrap()
+        .push(format!("{} {}", request.method(), path));
+
+    let revalidated = request
+        .headers()
+        .iter()
+        .any(|h| h == "If-None-Match: \"v1\"");
+    if path.starts_with("/stream/") && !revalidated {
+        stream.write_all(
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nETag: \"v1\"\r\n\
+              Cache-Control: no-cache\r\nConnection: close\r\n\r\ndata: 1\n\n",
+        )?;
+        stream.flush()?;
+        std::thread::sleep(Duration::from_secs(1));
+        return Ok(());
+    }
+    let headers = if path.starts_with("/max-age/") {
+        "Cache-Control: max-age=60\r\n"
+    } else if (path.starts_with("/etag/") || path.starts_with("/stream/")) && revalidated {
+        stream.write_all(
+            b"HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\nX-Fresh: yes\r\n\
+              Connection: close\r\n\r\n",
+        )?;
+        return Ok(());
+    } else if path.starts_with("/etag/") {
+        "ETag: \"v1\"\r\nCache-Control: no-cache\r\nX-Fresh: no\r\n"
+    } else {
+        "Vary: Accept\r\nCache-Control: max-age=60\r\n"
+    };
+    let body = format!("body of {}", path);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\n{}Connection: close\r\nContent-Length: {}\r\n\r\n{}",
+        headers,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn cached_agent() -> Agent {
+    builder()
+        .middleware(Cache::new(MemoryStorage::new(10)))
+        .build()
+}
+
+#[test]
+fn cache_fresh_response() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/max-age/fresh", server.port);
+    let agent = cached_agent();
+    for cached in [false, true] {
+        let resp = agent.get(&url).call().unwrap();
+        // Responses from the cache have an Age.
+        assert_eq!(resp.has("age"), cached);
+        assert_eq!(resp.into_string().unwrap(), "body of /max-age/fresh");
+    }
+    assert_eq!(requests("GET /max-age/fresh"), 1);
+}
+
+#[test]
+fn cache_revalidates() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/etag/revalidate", server.port);
+    let agent = cached_agent();
+    let resp = agent.get(&url).call().unwrap();
+    assert_eq!(resp.header("x-fresh"), Some("no"));
+    assert_eq!(resp.into_string().unwrap(), "body of /etag/revalidate");
+
+    // The 304 becomes the stored response, with the updated header fields.
+    let resp = agent.get(&url).call().unwrap();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.header("x-fresh"), Some("yes"));
+    assert_eq!(resp.into_string().unwrap(), "body of /etag/revalidate");
+    assert_eq!(requests("GET /etag/revalidate"), 2);
+}
+
+#[test]
+fn cache_varies() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/vary/accept", server.port);
+    let agent = cached_agent();
+    for accept in ["text/plain", "text/html", "text/html"] {
+        let resp = agent.get(&url).set("Accept", accept).call().unwrap();
+        resp.into_string().unwrap();
+    }
+    assert_eq!(requests("GET /vary/accept"), 2);
+}
+
+#[test]
+fn cache_invalidated_by_post() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/max-age/post", server.port);
+    let agent = cached_agent();
+    agent.get(&url).call().unwrap().into_string().unwrap();
+    agent.post(&url).send_string("data").unwrap();
+    agent.get(&url).call().unwrap().into_string().unwrap();
+    assert_eq!(requests("GET /max-age/post"), 2);
+}
+
+#[test]
+fn cache_on_disk() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/max-age/disk", server.port);
+    let dir = std::env::temp_dir().join(format!("ureq-cache-test-{}", std::process::id()));
+    for _ in 0..2 {
+        // A new agent each time, sharing only the directory.
+        let agent = builder()
+            .middleware(Cache::new(DiskStorage::new(&dir).unwrap()))
+            .build();
+        let resp = agent.get(&url).call().unwrap();
+        assert_eq!(resp.into_string().unwrap(), "body of /max-age/disk");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(requests("GET /max-age/disk"), 1);
+}
+
+#[test]
+fn cache_streams_body() {
+    let server = TestServer::new(caching_server);
+    let url = format!("http://localhost:{}/stream/events", server.port);
+    let agent = cached_agent();
+
+    // The response is returned before the body ends.
+    let start = Instant::now();
+    let resp = agent.get(&url).call().unwrap();
+    assert!(start.elapsed() < Duration::from_millis(500));
+    assert_eq!(resp.into_string().unwrap(), "data: 1\n\n");
+
+    // It was stored once read to the end, and is revalidated.
+    let resp = agent.get(&url).call().unwrap();
+    assert_eq!(resp.header("x-fresh"), Some("yes"));
+    assert_eq!(resp.into_string().unwrap(), "data: 1\n\n");
+    assert_eq!(requests("GET /stream/events"), 2);
+}
@@ -13,11 +13,23 @@ use std::sync::{Arc, Mutex};
 mod agent_test;
 mod body_read;
 mod body_send;
+mod cache;
+#[cfg(feature = "http2")]
+mod http2;
 mod query_string;
 mod range;
 mod redirect;
 mod simple;
+#[cfg(feature = "socks-proxy")]
+mod socks;
+mod sse;
 mod timeout;
+#[cfg(feature = "tls")]
+mod tls;
+#[cfg(unix)]
+mod unix_socket;
+#[cfg(feature = "websocket")]
+mod websocket;
 
 type RequestHandler = dyn Fn(&Unit) -> Result<Stream, Error> + Send + 'static;
 
@@ -89,15 +101,13 @@ impl Recorder {
         recorder2
     }
 
-    #[cfg(feature = "charset")]
+    #[cfg(any(feature = "charset", feature = "gzip", feature = "brotli"))]
     fn to_vec(self) -> Vec<u8> {
         self.contents.lock().unwrap().clone()
     }
 
     fn contains(&self, s: &str) -> bool {
-        std::str::from_utf8(&self.contents.lock().unwrap())
-            .unwrap()
-            .contains(s)
+        String::from_utf8_lossy(&self.contents.lock().unwrap()).contains(s)
     }
 
     fn stream(&self) -> Stream {
@@ -1,3 +1,11 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::Mutex;
+
+use crate::testserver::{read_request, TestServer};
+
+use super::super::*;
+
 #[test]
 #[cfg(feature = "tls")]
 fn read_range_rustls() {
@@ -47,3 +55,116 @@ fn read_range_native_tls() {
         [83, 99, 111, 116, 116, 34, 10, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32]
     )
 }
+
+// The requests received by `download_server`, as "/path Range".
+static REQUESTS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn requests(path: &str) -> Vec<String> {
+    REQUESTS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|r| r.starts_with(path))
+        .cloned()
+        .collect()
+}
+
+fn body(version: u8) -> Vec<u8> {
+    (0..1000).map(|i| (i % 200) as u8 + version).collect()
+}
+
+// Serves a 1000 byte body, and closes the connection after 400 bytes the first time a
+// path is requested. Depending on the first path segment:
+// - /partial/.. answers range requests with the ETag "v1".
+// - /ignored/.. never answers range requests.
+// - /changed/.. has the ETag "v2" and another body after the first request.
+fn download_server(mut stream: TcpStream) -> io::Result<()> {
+    let request = read_request(&stream);
+    if request.path().is_empty() {
+        return Ok(());
+    }
+    let path = request.path().to_string();
+    let header = |name: &str| {
+        request
+            .headers()
+            .iter()
+            .find_map(|h| h.strip_prefix(name))
+            .map(String::from)
+    };
+    let range = header("Range: bytes=");
+    let if_range = header("If-Range: ");
+
+    let first = requests(&path).is_empty();
+    REQUESTS
+        .lock()
+        .unwrap()
+        .push(format!("{} {}", path, range.as_deref().unwrap_or("-")));
+
+    let (etag, body) = if path.starts_with("/changed/") && !first {
+        ("\"v2\"", body(1))
+    } else {
+        ("\"v1\"", body(0))
+    };
+    let start = range
+        .filter(|_| !path.starts_with("/ignored/") && if_range.as_deref() == Some(etag))
+        .map(|r| r.trim_end_matches('-').parse::<usize>().unwrap());
+    if let Some(start) = start {
+        write!(
+            stream,
+            "HTTP/1.1 206 Partial Content\r\nETag: {}\r\nContent-Range: bytes {}-999/1000\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            etag,
+            start,
+            1000 - start
+        )?;
+        return stream.write_all(&body[start..]);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nETag: {}\r\nContent-Length: 1000\r\nConnection: close\r\n\r\n",
+        etag
+    )?;
+    let len = if first { 400 } else { body.len() };
+    stream.write_all(&body[..len])
+}
+
+#[test]
+fn download_resumes() {
+    let server = TestServer::new(download_server);
+    let url = format!("http://localhost:{}/partial/resume", server.port);
+    let mut downloaded = vec![];
+    let len = get(&url).download(&mut downloaded).unwrap();
+    assert_eq!(len, 1000);
+    assert_eq!(downloaded, body(0));
+    assert_eq!(
+        requests("/partial/resume"),
+        ["/partial/resume -", "/partial/resume 400-"]
+    );
+}
+
+#[test]
+fn download_skips_ignored_range() {
+    let server = TestServer::new(download_server);
+    let url = format!("http://localhost:{}/ignored/skip", server.port);
+    let mut downloaded = vec![];
+    get(&url).download(&mut downloaded).unwrap();
+    assert_eq!(downloaded, body(0));
+    assert_eq!(requests("/ignored/skip").len(), 2);
+}
+
+#[test]
+fn download_changed_resource() {
+    let server = TestServer::new(download_server);
+    let url = format!("http://localhost:{}/changed/writer", server.port);
+    let err = get(&url).download(io::sink()).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Io);
+
+    // A file starts over.
+    let url = format!("http://localhost:{}/changed/file", server.port);
+    let path = std::env::temp_dir().join(format!("ureq-download-test-{}", std::process::id()));
+    let len = get(&url).download_to_file(&path).unwrap();
+    let downloaded = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(len, 1000);
+    assert_eq!(downloaded, body(1));
+}
@@ -1,12 +1,12 @@
 use std::{
-    io::{self, Write},
+    io::{self, Cursor, Write},
     net::TcpStream,
     thread,
     time::Duration,
 };
 use testserver::{self, TestServer};
 
-use crate::{error::Error, test};
+use crate::{error::Error, test, test::Recorder};
 
 use super::super::*;
 
@@ -163,6 +163,61 @@ fn redirect_post_with_data() {
     assert_eq!(resp.status(), 200);
 }
 
+#[test]
+fn redirect_307_resends_body() {
+    test::set_handler("/redirect_307_d1", |_| {
+        test::make_response(307, "Go here", vec!["Location: /redirect_307_d2"], vec![])
+    });
+    let recorder = Recorder::register("/redirect_307_d2");
+    let resp = post("test://host/redirect_307_d1")
+        .send_string("data")
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(recorder.contains("POST /redirect_307_d2 HTTP/1.1\r\n"));
+    assert!(recorder.contains("Content-Length: 4\r\n"));
+    assert!(recorder.contains("\r\n\r\ndata"));
+}
+
+#[test]
+fn redirect_308_resends_seekable_body() {
+    test::set_handler("/redirect_308_s1", |_| {
+        test::make_response(308, "Go here", vec!["Location: /redirect_308_s2"], vec![])
+    });
+    let recorder = Recorder::register("/redirect_308_s2");
+    let mut body = Cursor::new(b"skip data".to_vec());
+    body.set_position(5);
+    let resp = put("test://host/redirect_308_s1")
+        .send_seekable(body)
+        .unwrap();
+    assert_eq!(resp.status(), 200);
+    assert!(recorder.contains("PUT /redirect_308_s2 HTTP/1.1\r\n"));
+    assert!(recorder.contains("Content-Length: 4\r\n"));
+    assert!(recorder.contains("\r\n\r\ndata"));
+}
+
+#[test]
+fn redirect_307_with_reader() {
+    test::set_handler("/redirect_307_r1", |_| {
+        test::make_response(307, "Go here", vec!["Location: /redirect_307_r2"], vec![])
+    });
+    // The body can't be sent again, so the redirect is not followed.
+    let resp = post("test://host/redirect_307_r1")
+        .send(&b"data"[..])
+        .unwrap();
+    assert_eq!(resp.status(), 307);
+}
+
+#[test]
+fn redirect_307_delete() {
+    test::set_handler("/redirect_307_del1", |_| {
+        test::make_response(307, "Go here", vec!["Location: /redirect_307_del2"], vec![])
+    });
+    let recorder = Recorder::register("/redirect_307_del2");
+    let resp = delete("test://host/redirect_307_del1").call().unwrap();
+    assert_eq!(resp.status(), 307);
+    assert!(!recorder.contains("DELETE /redirect_307_del2"));
+}
+
 #[cfg(feature = "cookies")]
 #[test]
 fn redirect_post_with_cookies() {
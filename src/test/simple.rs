@@ -102,6 +102,28 @@ fn body_as_json_deserialize() {
     assert_eq!(json.hello, "world");
 }
 
+#[test]
+#[cfg(feature = "json")]
+fn body_as_json_stream() {
+    test::set_handler("/body_as_json_stream", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Type: application/x-ndjson"],
+            "{\"n\":1}\n\n{\"n\":\r\nnot json\n{\"n\":3}"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let resp = get("test://host/body_as_json_stream").call().unwrap();
+    let records: Vec<_> = resp.into_json_stream::<serde_json::Value>().collect();
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[0].as_ref().unwrap()["n"], 1);
+    assert!(records[1].is_err());
+    assert!(records[2].is_err());
+    assert_eq!(records[3].as_ref().unwrap()["n"], 3);
+}
+
 #[test]
 fn body_as_reader() {
     test::set_handler("/body_as_reader", |_unit| {
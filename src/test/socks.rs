@@ -0,0 +1,150 @@
+use crate::error::Error;
+use crate::testserver::{read_request, TestServer};
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+
+use super::super::*;
+
+// Answers the request tunneled through a SOCKS stand-in with the target the client
+// asked the proxy for.
+fn answer_target(mut stream: TcpStream, target: String) -> io::Result<()> {
+    read_request(&stream);
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        target.len(),
+        target
+    )
+}
+
+fn read_until_nul(stream: &mut TcpStream) -> io::Result<String> {
+    let mut bytes = vec![];
+    let mut byte = [0];
+    loop {
+        stream.read_exact(&mut byte)?;
+        if byte[0] == 0 {
+            return Ok(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        bytes.push(byte[0]);
+    }
+}
+
+// A SOCKS5 proxy without authentication.
+fn socks5_handler(mut stream: TcpStream) -> io::Result<()> {
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting)?;
+    let mut methods = vec![0; greeting[1] as usize];
+    stream.read_exact(&mut methods)?;
+    stream.write_all(&[5, 0])?;
+
+    // version, command, reserved, address type
+    let mut request = [0; 4];
+    stream.read_exact(&mut request)?;
+    let host = match request[3] {
+        1 => {
+            let mut addr = [0; 4];
+            stream.read_exact(&mut addr)?;
+            Ipv4Addr::from(addr).to_string()
+        }
+        3 => {
+            let mut len = [0];
+            stream.read_exact(&mut len)?;
+            let mut name = vec![0; len[0] as usize];
+            stream.read_exact(&mut name)?;
+            String::from_utf8_lossy(&name).into_owned()
+        }
+        _ => {
+            let mut addr = [0; 16];
+            stream.read_exact(&mut addr)?;
+            format!("[{}]", Ipv6Addr::from(addr))
+        }
+    };
+    let mut port = [0; 2];
+    stream.read_exact(&mut port)?;
+    stream.write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])?;
+
+    answer_target(stream, format!("{}:{}", host, u16::from_be_bytes(port)))
+}
+
+// A SOCKS4 proxy, which also understands SOCKS4A.
+fn socks4_handler(mut stream: TcpStream) -> io::Result<()> {
+    // version, command, port, address
+    let mut request = [0; 8];
+    stream.read_exact(&mut request)?;
+    let port = u16::from_be_bytes([request[2], request[3]]);
+    let addr = Ipv4Addr::new(request[4], request[5], request[6], request[7]);
+    read_until_nul(&mut stream)?;
+    // SOCKS4A signals a host name with the address 0.0.0.x
+    let host = if addr.octets()[..3] == [0, 0, 0] && addr.octets()[3] != 0 {
+        read_until_nul(&mut stream)?
+    } else {
+        addr.to_string()
+    };
+    stream.write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0])?;
+
+    answer_target(stream, format!("{}:{}", host, port))
+}
+
+// An agent going through `proxy`, with a resolver that only knows example.invalid
+// and the proxy itself.
+fn socks_agent(proxy: &str) -> Agent {
+    builder()
+        .proxy(Proxy::new(proxy).unwrap())
+        .resolver(|netloc: &str| -> io::Result<Vec<SocketAddr>> {
+            match netloc {
+                "example.invalid:80" => Ok(vec!["10.0.0.1:80".parse().unwrap()]),
+                _ => Ok(vec![netloc.parse().unwrap()]),
+            }
+        })
+        .build()
+}
+
+fn get(agent: &Agent, url: &str) -> Result<String, Error> {
+    Ok(agent.get(url).call()?.into_string()?)
+}
+
+#[test]
+fn socks5_sends_host_name() -> Result<(), Error> {
+    let testserver = TestServer::new(socks5_handler);
+    for scheme in ["socks5", "socks"] {
+        let agent = socks_agent(&format!("{}://127.0.0.1:{}", scheme, testserver.port));
+        assert_eq!(
+            get(&agent, "http://example.invalid/")?,
+            "example.invalid:80"
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn socks5h_sends_host_name() -> Result<(), Error> {
+    let testserver = TestServer::new(socks5_handler);
+    let agent = socks_agent(&format!("socks5h://127.0.0.1:{}", testserver.port));
+    assert_eq!(
+        get(&agent, "http://example.invalid/")?,
+        "example.invalid:80"
+    );
+    // Addresses are sent as such.
+    assert_eq!(get(&agent, "http://10.1.2.3:8080/")?, "10.1.2.3:8080");
+    assert_eq!(get(&agent, "http://[fd00::1]/")?, "[fd00::1]:80");
+    Ok(())
+}
+
+#[test]
+fn socks4_resolves_locally() -> Result<(), Error> {
+    let testserver = TestServer::new(socks4_handler);
+    let agent = socks_agent(&format!("socks4://127.0.0.1:{}", testserver.port));
+    assert_eq!(get(&agent, "http://example.invalid/")?, "10.0.0.1:80");
+    Ok(())
+}
+
+#[test]
+fn socks4a_sends_host_name() -> Result<(), Error> {
+    let testserver = TestServer::new(socks4_handler);
+    let agent = socks_agent(&format!("socks4a://127.0.0.1:{}", testserver.port));
+    assert_eq!(
+        get(&agent, "http://example.invalid/")?,
+        "example.invalid:80"
+    );
+    Ok(())
+}
@@ -0,0 +1,84 @@
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+use crate::test;
+use crate::testserver::{read_request, TestServer};
+
+use super::super::*;
+
+#[test]
+fn into_sse() {
+    test::set_handler("/into_sse", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["content-type: text/event-stream"],
+            b"id: 1\nevent: greeting\ndata: hello\ndata: world\n\n: keep-alive\n\ndata: bye\n\n"
+                .to_vec(),
+        )
+    });
+    let events = get("test://host/into_sse")
+        .call()
+        .unwrap()
+        .into_sse()
+        .collect::<io::Result<Vec<_>>>()
+        .unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].id(), Some("1"));
+    assert_eq!(events[0].event(), "greeting");
+    assert_eq!(events[0].data(), "hello\nworld");
+    assert_eq!(events[1].id(), Some("1"));
+    assert_eq!(events[1].event(), "message");
+    assert_eq!(events[1].data(), "bye");
+}
+
+// Sends one event per connection, resuming after the Last-Event-ID sent by
+// the client, and ends the stream with a 204 after the third event.
+fn resuming_stream(mut stream: TcpStream) -> io::Result<()> {
+    let headers = read_request(&stream);
+    let last_id: u32 = headers
+        .headers()
+        .iter()
+        .find_map(|h| h.strip_prefix("Last-Event-ID: "))
+        .map(|id| id.parse().unwrap())
+        .unwrap_or(0);
+    if last_id == 3 {
+        stream.write_all(b"HTTP/1.1 204 No Content\r\n\r\n")?;
+        return Ok(());
+    }
+    stream.write_all(b"HTTP/1.1 200 OK\r\n")?;
+    stream.write_all(b"Content-Type: text/event-stream\r\n")?;
+    stream.write_all(b"Connection: close\r\n\r\n")?;
+    write!(
+        stream,
+        "retry: 10\nid: {0}\ndata: event {0}\n\n",
+        last_id + 1
+    )?;
+    Ok(())
+}
+
+#[test]
+fn event_source_reconnects() {
+    let server = TestServer::new(resuming_stream);
+    let source = agent()
+        .event_source(&format!("http://localhost:{}/", server.port))
+        .retry(Duration::from_secs(60));
+    let data = source
+        .map(|e| e.unwrap().data().to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(data, vec!["event 1", "event 2", "event 3"]);
+}
+
+fn not_found(mut stream: TcpStream) -> io::Result<()> {
+    read_request(&stream);
+    stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+}
+
+#[test]
+fn event_source_status_error() {
+    let server = TestServer::new(not_found);
+    let mut source = agent().event_source(&format!("http://localhost:{}/", server.port));
+    assert!(matches!(source.next(), Some(Err(Error::Status(404, _)))));
+    assert!(source.next().is_none());
+}
@@ -174,3 +174,33 @@ fn overall_timeout_reading_json() {
     }
     .expect("expected timeout but got something else");
 }
+
+// A resolver that never gets an answer in time.
+struct SlowResolver;
+
+impl Resolver for SlowResolver {
+    fn resolve(&self, _netloc: &str) -> io::Result<Vec<std::net::SocketAddr>> {
+        unreachable!("ureq should pass the deadline to the resolver")
+    }
+
+    fn resolve_with_deadline(
+        &self,
+        _netloc: &str,
+        deadline: Option<std::time::Instant>,
+    ) -> io::Result<Vec<std::net::SocketAddr>> {
+        let deadline = deadline.expect("a deadline");
+        thread::sleep(deadline.saturating_duration_since(std::time::Instant::now()));
+        Err(io::Error::new(io::ErrorKind::TimedOut, "too slow"))
+    }
+}
+
+#[test]
+fn dns_timeout() {
+    let agent = builder()
+        .resolver(SlowResolver)
+        .timeout_connect(Duration::from_millis(100))
+        .build();
+    let err = agent.get("http://example.com/").call().unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::Dns);
+    assert!(err.to_string().contains("timed out resolving"), "{}", err);
+}
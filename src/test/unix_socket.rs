@@ -0,0 +1,165 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+
+use super::super::*;
+
+// A server on a unix socket that answers every request with its path and Host
+// header, keeping connections alive. Connections starting with a TLS handshake are
+// served over TLS. Returns the socket path and a count of the accepted connections.
+fn unix_server(name: &str) -> (PathBuf, Arc<AtomicUsize>) {
+    let path = std::env::temp_dir().join(format!("ureq-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    let connections = Arc::new(AtomicUsize::new(0));
+    let connections2 = connections.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            connections2.fetch_add(1, Ordering::SeqCst);
+            thread::spawn(move || accept(stream.unwrap()));
+        }
+    });
+    (path, connections)
+}
+
+// A stream that first yields a byte already read from it.
+struct Peeked(Option<u8>, UnixStream);
+
+impl Read for Peeked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.take() {
+            Some(byte) if !buf.is_empty() => {
+                buf[0] = byte;
+                Ok(1)
+            }
+            _ => self.1.read(buf),
+        }
+    }
+}
+
+impl Write for Peeked {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.1.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.1.flush()
+    }
+}
+
+fn accept(mut stream: UnixStream) {
+    let mut first = [0];
+    if stream.read(&mut first).unwrap_or(0) == 0 {
+        return;
+    }
+    let stream = Peeked(Some(first[0]), stream);
+    // The content type of a TLS handshake record.
+    #[cfg(feature = "tls")]
+    if first[0] == 0x16 {
+        let config = Arc::new(crate::test::tls::server_config(&[]));
+        let conn = rustls::ServerConnection::new(config).unwrap();
+        return serve(rustls::StreamOwned::new(conn, stream));
+    }
+    serve(stream)
+}
+
+fn serve(stream: impl Read + Write) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+            return;
+        }
+        let path = request_line.split(' ').nth(1).unwrap().to_string();
+        let mut host = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim().is_empty() {
+                break;
+            }
+            if let Some(value) = line.to_ascii_lowercase().strip_prefix("host:") {
+                host = value.trim().to_string();
+            }
+        }
+        let body = format!("{}|{}", path, host);
+        let stream = reader.get_mut();
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+        stream.flush().unwrap();
+    }
+}
+
+#[test]
+fn agent_unix_socket() {
+    let (path, connections) = unix_server("agent_unix_socket");
+    let agent = builder().unix_socket(&path).build();
+    for _ in 0..2 {
+        let body = agent
+            .get("http://example.com/hello?x=1")
+            .call()
+            .unwrap()
+            .into_string()
+            .unwrap();
+        assert_eq!(body, "/hello?x=1|example.com");
+    }
+    // the connection was returned to the pool and reused.
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn http_unix_url() {
+    let (path, connections) = unix_server("http_unix_url");
+    let encoded = utf8_percent_encode(path.to_str().unwrap(), NON_ALPHANUMERIC);
+    let url = format!("http+unix://{}/v1.43/info", encoded);
+    let agent = builder().build();
+    for _ in 0..2 {
+        let body = agent.get(&url).call().unwrap().into_string().unwrap();
+        assert_eq!(body, "/v1.43/info|localhost");
+    }
+    assert_eq!(connections.load(Ordering::SeqCst), 1);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn unix_socket_keeps_schemes_apart() {
+    let (path, connections) = unix_server("unix_socket_keeps_schemes_apart");
+    let agent = builder()
+        .unix_socket(&path)
+        .tls_config(Arc::new(crate::test::tls::client_config()))
+        .build();
+    for url in [
+        "http://localhost/a",
+        "https://localhost/b",
+        "http://localhost/c",
+    ] {
+        let body = agent.get(url).call().unwrap().into_string().unwrap();
+        assert_eq!(body, format!("{}|localhost", &url[url.len() - 2..]));
+    }
+    // A pooled plain connection is never used for https, nor the other way around.
+    assert_eq!(connections.load(Ordering::SeqCst), 2);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn unix_socket_connect_error() {
+    let path = std::env::temp_dir().join("ureq-does-not-exist.sock");
+    let result = builder()
+        .unix_socket(&path)
+        .build()
+        .get("http://localhost/")
+        .call();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::ConnectionFailed);
+}
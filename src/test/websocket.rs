@@ -0,0 +1,184 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+use crate::testserver::{read_request, TestServer};
+
+use super::super::*;
+
+fn accept_key(headers: &[String]) -> String {
+    let key = headers
+        .iter()
+        .find_map(|h| h.strip_prefix("Sec-WebSocket-Key: "))
+        .unwrap();
+    let guid = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+    let digest = sha1_smol::Sha1::from(format!("{}{}", key, guid)).digest();
+    BASE64_STANDARD.encode(digest.bytes())
+}
+
+// Read a masked frame from the client, returning opcode and unmasked payload.
+fn read_client_frame(stream: &mut TcpStream) -> io::Result<(u8, Vec<u8>)> {
+    let mut head = [0; 2];
+    stream.read_exact(&mut head)?;
+    assert_eq!(head[1] & 0x80, 0x80, "client frames are masked");
+    let len = (head[1] & 0x7F) as usize;
+    assert!(len < 126);
+    let mut mask = [0; 4];
+    stream.read_exact(&mut mask)?;
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload)?;
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+    Ok((head[0], payload))
+}
+
+// Echoes one text message back in two fragments, then closes.
+fn echo_server(mut stream: TcpStream) -> io::Result<()> {
+    let headers = read_request(&stream);
+    stream.set_nonblocking(false)?;
+    assert!(headers
+        .headers()
+        .contains(&"Upgrade: websocket".to_string()));
+    assert!(headers
+        .headers()
+        .contains(&"Sec-WebSocket-Version: 13".to_string()));
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(headers.headers())
+    )?;
+
+    let (opcode, payload) = read_client_frame(&mut stream)?;
+    assert_eq!(opcode, 0x81);
+    let (first, rest) = payload.split_at(2);
+    stream.write_all(&[0x01, first.len() as u8])?;
+    stream.write_all(first)?;
+    stream.write_all(&[0x89, 0])?;
+    stream.write_all(&[0x80, rest.len() as u8])?;
+    stream.write_all(rest)?;
+
+    // The pong for the ping.
+    assert_eq!(read_client_frame(&mut stream)?, (0x8A, vec![]));
+
+    let (opcode, payload) = read_client_frame(&mut stream)?;
+    assert_eq!(opcode, 0x88);
+    assert_eq!(&payload[..2], &1000_u16.to_be_bytes());
+    stream.write_all(&[0x88, 2, 0x03, 0xE8])?;
+    Ok(())
+}
+
+#[test]
+fn websocket_echo() {
+    let server = TestServer::new(echo_server);
+    let mut socket = agent()
+        .websocket(&format!("ws://localhost:{}/", server.port))
+        .unwrap();
+    socket.send(Message::Text("hello".to_string())).unwrap();
+    assert_eq!(socket.read().unwrap(), Message::Ping(vec![]));
+    assert_eq!(socket.read().unwrap(), Message::Text("hello".to_string()));
+    socket.close(1000, "bye").unwrap();
+}
+
+fn wrong_accept(mut stream: TcpStream) -> io::Result<()> {
+    read_request(&stream);
+    stream.write_all(
+        b"HTTP/1.1 101 Switching Protocols\r\n\
+          Upgrade: websocket\r\n\
+          Connection: Upgrade\r\n\
+          Sec-WebSocket-Accept: bm9wZQ==\r\n\r\n",
+    )
+}
+
+#[test]
+fn websocket_wrong_accept() {
+    let server = TestServer::new(wrong_accept);
+    let err = agent()
+        .websocket(&format!("ws://localhost:{}/", server.port))
+        .unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::BadHeader);
+}
+
+// Answers the handshake over TLS, then sends the protocol negotiated with ALPN.
+#[cfg(feature = "http2")]
+fn wss_server() -> u16 {
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let config = Arc::new(crate::test::tls::server_config(&[b"h2", b"http/1.1"]));
+    std::thread::spawn(move || {
+        for tcp in listener.incoming() {
+            let tcp = match tcp {
+                Ok(tcp) => tcp,
+                Err(_) => break,
+            };
+            let conn = rustls::ServerConnection::new(config.clone()).unwrap();
+            let mut reader = BufReader::new(rustls::StreamOwned::new(conn, tcp));
+            let mut headers = vec![];
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap_or(0) == 0 || line.trim().is_empty() {
+                    break;
+                }
+                headers.push(line.trim_end().to_string());
+            }
+            let alpn = reader.get_ref().conn.alpn_protocol().unwrap_or_default();
+            let alpn = alpn.to_vec();
+            let stream = reader.get_mut();
+            let _ = write!(
+                stream,
+                "HTTP/1.1 101 Switching Protocols\r\n\
+                 Upgrade: websocket\r\n\
+                 Connection: Upgrade\r\n\
+                 Sec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&headers)
+            );
+            let _ = stream.write_all(&[0x81, alpn.len() as u8]);
+            let _ = stream.write_all(&alpn);
+            let _ = stream.flush();
+        }
+    });
+    port
+}
+
+#[test]
+#[cfg(feature = "http2")]
+fn wss_handshake_uses_http1() {
+    // Offers h2, like the default config.
+    let mut tls_config = crate::test::tls::client_config();
+    tls_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let agent = builder()
+        .tls_config(std::sync::Arc::new(tls_config))
+        .timeout(std::time::Duration::from_secs(10))
+        .build();
+    let url = format!("wss://localhost:{}/", wss_server());
+
+    let mut socket = agent.websocket(&url).unwrap();
+    assert_eq!(
+        socket.read().unwrap(),
+        Message::Text("http/1.1".to_string())
+    );
+
+    // Upgrading a response over https works the same.
+    let resp = agent
+        .get(&url.replace("wss:", "https:"))
+        .set("Connection", "Upgrade")
+        .set("Upgrade", "websocket")
+        .set("Sec-WebSocket-Key", "dGhlIHNhbXBsZSBub25jZQ==")
+        .call()
+        .unwrap();
+    assert_eq!(resp.http_version(), "HTTP/1.1");
+    let mut frame = [0; 10];
+    resp.into_upgraded()
+        .unwrap()
+        .read_exact(&mut frame)
+        .unwrap();
+    assert_eq!(&frame, b"\x81\x08http/1.1");
+}
@@ -80,6 +80,15 @@ pub struct TestHeaders(Vec<String>);
 
 #[allow(dead_code)]
 impl TestHeaders {
+    // Return the method for a request, e.g. GET from "GET /foo HTTP/1.1"
+    pub fn method(&self) -> &str {
+        if self.0.is_empty() {
+            ""
+        } else {
+            self.0[0].split(' ').next().unwrap()
+        }
+    }
+
     // Return the path for a request, e.g. /foo from "GET /foo HTTP/1.1"
     pub fn path(&self) -> &str {
         if self.0.is_empty() {
@@ -89,7 +98,6 @@ impl TestHeaders {
         }
     }
 
-    #[cfg(feature = "cookies")]
     pub fn headers(&self) -> &[String] {
         &self.0[1..]
     }
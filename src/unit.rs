@@ -1,6 +1,12 @@
 use std::fmt::{self, Display};
-use std::io::{self, Write};
+use std::io::{self, BufRead, Write};
 use std::ops::Range;
+#[cfg(unix)]
+use std::os::unix::ffi::OsStringExt;
+#[cfg(unix)]
+use std::path::PathBuf;
+#[cfg(feature = "http2")]
+use std::sync::Arc;
 use std::time;
 
 use base64::{prelude::BASE64_STANDARD, Engine};
@@ -15,10 +21,12 @@ use crate::body::{self, BodySize, Payload, SizedReader};
 use crate::error::{Error, ErrorKind};
 use crate::header;
 use crate::header::{get_header, Header};
-use crate::proxy::Proto;
+#[cfg(feature = "http2")]
+use crate::http2;
+use crate::proxy::{Proto, Proxy};
 use crate::resolve::ArcResolver;
-use crate::response::Response;
-use crate::stream::{self, connect_test, Stream};
+use crate::response::{self, Limits, Response};
+use crate::stream::{self, connect_test, DeadlineStream, DefaultConnector, Stream};
 use crate::Agent;
 
 /// A Unit is fully-built Request, ready to execute.
@@ -32,6 +40,18 @@ pub(crate) struct Unit {
     is_chunked: bool,
     headers: Vec<Header>,
     pub deadline: Option<time::Instant>,
+    /// The proxy for this request, if any. Picked per request since proxies taken from
+    /// the environment or a `ProxySelector` depend on the URL.
+    pub proxy: Option<Proxy>,
+    /// Proxies to try, in order, when `proxy` can't be connected to.
+    proxy_fallbacks: Vec<Proxy>,
+    /// Set by `Request::proxy()`, replaces the agent's choice for redirects too.
+    proxy_override: Option<Option<Proxy>>,
+    /// Answer to the proxy's authentication challenge, for requests sent to an
+    /// HTTP proxy in absolute form.
+    proxy_authorization: Option<String>,
+    /// Limits on the response, from the agent unless overridden by the request.
+    pub limits: Limits,
 }
 
 impl Unit {
@@ -53,7 +73,7 @@ impl Unit {
                 let is_transfer_encoding_set = !enc.is_empty();
                 let last_encoding = enc.split(',').last();
                 let is_chunked = last_encoding
-                    .map(|last_enc| last_enc.trim() == "chunked")
+                    .map(|last_enc| last_enc.trim().eq_ignore_ascii_case("chunked"))
                     .unwrap_or(false);
                 (is_transfer_encoding_set, is_chunked)
             })
@@ -101,6 +121,8 @@ impl Unit {
 
         headers.append(&mut extra_headers);
 
+        let mut proxies = select_proxies(agent, url).into_iter();
+
         Unit {
             agent: agent.clone(),
             method: method.to_string(),
@@ -108,13 +130,51 @@ impl Unit {
             is_chunked,
             headers,
             deadline,
+            proxy: proxies.next(),
+            proxy_fallbacks: proxies.collect(),
+            proxy_override: None,
+            proxy_authorization: None,
+            limits: agent.config.limits,
         }
     }
 
+    /// Use `proxy` for this request instead of the one picked by the agent.
+    pub(crate) fn override_proxy(&mut self, proxy: Option<Proxy>) {
+        // An agent with a custom connector leaves proxies to it.
+        let proxy = proxy.filter(|_| self.agent.state.connector.is_none());
+        self.proxy = proxy.clone();
+        self.proxy_fallbacks.clear();
+        self.proxy_override = Some(proxy);
+    }
+
     pub fn resolver(&self) -> ArcResolver {
         self.agent.state.resolver.clone()
     }
 
+    /// The Unix domain socket to connect to, if the request doesn't go over TCP.
+    ///
+    /// An `http+unix` url names the socket as its percent-encoded host, which takes
+    /// precedence over a socket set on the agent.
+    #[cfg(unix)]
+    pub(crate) fn unix_socket(&self) -> Option<PathBuf> {
+        if self.url.scheme() == "http+unix" {
+            let host = self.url.host_str().unwrap_or_default();
+            let path: Vec<u8> = percent_encoding::percent_decode_str(host).collect();
+            return Some(PathBuf::from(std::ffi::OsString::from_vec(path)));
+        }
+        self.agent.config.unix_socket.clone()
+    }
+
+    /// Whether the request asks to upgrade the connection to another protocol, which
+    /// is only possible over HTTP/1.1.
+    pub(crate) fn is_upgrade(&self) -> bool {
+        header::has_header(&self.headers, "upgrade")
+            || header::get_all_headers(&self.headers, "connection")
+                .iter()
+                .flat_map(|v| v.split(','))
+                .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+    }
+
     #[cfg(test)]
     pub fn header(&self, name: &str) -> Option<&str> {
         header::get_header(&self.headers, name)
@@ -128,26 +188,85 @@ impl Unit {
         header::get_all_headers(&self.headers, name)
     }
 
-    // Returns true if this request, with the provided body, is retryable.
-    pub(crate) fn is_retryable(&self, body: &SizedReader) -> bool {
+    /// The request header fields as sent over HTTP/2: (name, value, sensitive).
+    ///
+    /// This is the HTTP/2 counterpart of `send_prelude`. Names are lowercased, the
+    /// request line and Host header become pseudo-header fields, and connection-specific
+    /// headers, which are not allowed in HTTP/2, are dropped.
+    #[cfg(feature = "http2")]
+    pub(crate) fn http2_fields(&self) -> Vec<(String, Vec<u8>, bool)> {
+        let mut path = self.url.path().to_string();
+        if let Some(query) = self.url.query() {
+            path.push('?');
+            path.push_str(query);
+        }
+        let authority = match header::get_header(&self.headers, "host") {
+            Some(host) => host.to_string(),
+            None => host_header(&self.url),
+        };
+
+        let mut fields = vec![
+            (
+                ":method".to_string(),
+                self.method.clone().into_bytes(),
+                false,
+            ),
+            (":scheme".to_string(), self.url.scheme().into(), false),
+            (":authority".to_string(), authority.into_bytes(), false),
+            (":path".to_string(), path.into_bytes(), false),
+        ];
+        if !header::has_header(&self.headers, "user-agent") {
+            let user_agent = self.agent.config.user_agent.clone();
+            fields.push(("user-agent".to_string(), user_agent.into_bytes(), false));
+        }
+        if !header::has_header(&self.headers, "accept") {
+            fields.push(("accept".to_string(), b"*/*".to_vec(), false));
+        }
+
+        for header in &self.headers {
+            let name = header.name().to_ascii_lowercase();
+            let value = match header.value() {
+                Some(v) => v,
+                None => continue,
+            };
+            // https://www.rfc-editor.org/rfc/rfc9113#section-8.2.2
+            let skip = match name.as_str() {
+                "host" | "connection" | "keep-alive" | "proxy-connection" | "transfer-encoding"
+                | "upgrade" => true,
+                "te" => !value.eq_ignore_ascii_case("trailers"),
+                _ => false,
+            };
+            if !skip {
+                let sensitive = is_header_sensitive(header);
+                fields.push((name, value.as_bytes().to_vec(), sensitive));
+            }
+        }
+
+        fields
+    }
+
+    // Returns true if the body should be held back until the server answers "100 Continue".
+    fn expects_continue(&self, body: &SizedReader) -> bool {
+        let has_body = !matches!(body.size, BodySize::Empty | BodySize::Known(0));
+        let expect = header::get_header(&self.headers, "expect");
+        has_body && matches!(expect, Some(v) if v.eq_ignore_ascii_case("100-continue"))
+    }
+
+    // Returns the body to send again if this request is retryable.
+    pub(crate) fn retry_body<'a>(&self, body: &SizedReader<'a>) -> Option<SizedReader<'a>> {
         // Per https://tools.ietf.org/html/rfc7231#section-8.1.3
         // these methods are idempotent.
         let idempotent = match self.method.as_str() {
             "DELETE" | "GET" | "HEAD" | "OPTIONS" | "PUT" | "TRACE" => true,
             _ => false,
         };
-        // Unsized bodies aren't retryable because we can't rewind the reader.
-        // Sized bodies are retryable only if they are zero-length because of
-        // coincidences of the current implementation - the function responsible
-        // for retries doesn't have a way to replay a Payload.
-        let retryable_body = match body.size {
-            BodySize::Unknown => false,
-            BodySize::Known(0) => true,
-            BodySize::Known(_) => false,
-            BodySize::Empty => true,
-        };
-
-        idempotent && retryable_body
+        // Unsized bodies aren't retryable because we can't rewind the reader,
+        // buffered and seekable bodies can be sent again.
+        if idempotent {
+            body.try_clone()
+        } else {
+            None
+        }
     }
 }
 
@@ -159,7 +278,23 @@ pub(crate) fn connect(
 ) -> Result<Response, Error> {
     let mut history = vec![];
     let mut resp = loop {
-        let resp = connect_inner(&unit, use_pooled, body, &history)?;
+        // a copy of the body for 307/308 redirects and proxy authentication, if the
+        // body can be sent twice.
+        let mut replay = body.try_clone();
+        let resp = connect_inner(&mut unit, use_pooled, body, &history)?;
+
+        // answer a proxy authentication challenge once. if the body can't be sent twice,
+        // the 407 is returned as is.
+        if resp.status() == 407 && unit.proxy_authorization.is_none() {
+            if let (Some(authorization), Some(replay)) =
+                (proxy_authorization(&unit, &resp), replay.take())
+            {
+                debug!("retrying {} with proxy authentication", unit.url);
+                unit.proxy_authorization = Some(authorization);
+                body = replay;
+                continue;
+            }
+        }
 
         // handle redirects
         if !(300..399).contains(&resp.status()) || unit.agent.config.redirects == 0 {
@@ -187,21 +322,24 @@ pub(crate) fn connect(
         })?;
 
         // perform the redirect differently depending on 3xx code.
-        let new_method = match resp.status() {
+        let (new_method, new_body) = match resp.status() {
             // this is to follow how curl does it. POST, PUT etc change
             // to GET on a redirect.
             301 | 302 | 303 => match &method[..] {
-                "GET" | "HEAD" => unit.method,
-                _ => "GET".into(),
+                "GET" | "HEAD" => (unit.method, Payload::Empty.into_read()),
+                _ => ("GET".into(), Payload::Empty.into_read()),
             },
-            // never change the method for 307/308
-            // only resend the request if it cannot have a body
+            // never change the method for 307/308, and resend the body.
+            // only redirect if the body can be sent again.
             // NOTE: DELETE is intentionally excluded: https://stackoverflow.com/questions/299628
-            307 | 308 if ["GET", "HEAD", "OPTIONS", "TRACE"].contains(&method.as_str()) => {
-                unit.method
-            }
+            307 | 308 if method == "DELETE" => break resp,
+            307 | 308 => match replay {
+                Some(replay) => (unit.method, replay),
+                None => break resp,
+            },
             _ => break resp,
         };
+        let resend_body = !matches!(new_body.size, BodySize::Empty);
 
         let keep_auth_header = can_propagate_authorization_on_redirect(
             &unit.agent.config.redirect_auth_headers,
@@ -211,20 +349,24 @@ pub(crate) fn connect(
 
         debug!("redirect {} {} -> {}", resp.status(), url, new_url);
         history.push(unit.url);
-        body = Payload::Empty.into_read();
+        body = new_body;
 
         // reuse the previous header vec on redirects.
         let mut headers = unit.headers;
 
-        // on redirects we don't want to keep "content-length". we also might want to
-        // strip away "authorization" and "cookie" to ensure credentials are not leaked.
+        // on redirects we don't want to keep "content-length", which is set again for the
+        // new body, or "content-encoding" unless the body is resent. we also might want
+        // to strip away "authorization" and "cookie" to ensure credentials are not leaked.
         headers.retain(|h| {
             !h.is_name("content-length")
+                && (!h.is_name("content-encoding") || resend_body)
                 && !h.is_name("cookie")
                 && (!h.is_name("authorization") || keep_auth_header)
         });
 
-        // recreate the unit to get a new hostname and cookies for the new host.
+        // recreate the unit to get a new hostname, proxy and cookies for the new host.
+        let proxy_override = unit.proxy_override.take();
+        let limits = unit.limits;
         unit = Unit::new(
             &unit.agent,
             &new_method,
@@ -233,6 +375,10 @@ pub(crate) fn connect(
             &body,
             unit.deadline,
         );
+        unit.limits = limits;
+        if let Some(proxy) = proxy_override {
+            unit.override_proxy(proxy);
+        }
     };
     resp.history = history;
     Ok(resp)
@@ -240,20 +386,38 @@ pub(crate) fn connect(
 
 /// Perform a connection. Does not follow redirects.
 fn connect_inner(
-    unit: &Unit,
+    unit: &mut Unit,
     use_pooled: bool,
     body: SizedReader,
     history: &[Url],
 ) -> Result<Response, Error> {
-    let host = unit
-        .url
-        .host_str()
-        // This unwrap is ok because Request::parse_url() ensure there is always a host present.
-        .unwrap();
+    // an HTTP/2 connection to the host can be shared with other requests, except one
+    // upgrading its connection.
+    #[cfg(feature = "http2")]
+    if use_pooled && unit.url.scheme() == "https" && !unit.is_upgrade() {
+        let pool = &unit.agent.state.pool;
+        if let Some(conn) = pool.try_get_http2(&unit.url, unit.proxy.clone()) {
+            return connect_http2(unit, conn, true, body, history);
+        }
+    }
+
+    // open socket
+    let (mut stream, is_recycled) = connect_socket(unit, use_pooled)?;
     let url = &unit.url;
     let method = &unit.method;
-    // open socket
-    let (mut stream, is_recycled) = connect_socket(unit, host, use_pooled)?;
+
+    // the protocol negotiated with an HTTPS proxy, for an http url, is not that of the host.
+    #[cfg(feature = "http2")]
+    if url.scheme() == "https" && stream.is_http2() {
+        if unit.is_upgrade() {
+            return Err(ErrorKind::ConnectionFailed
+                .msg("server negotiated HTTP/2 for a request upgrading the connection"));
+        }
+        let conn = http2::Connection::handshake(stream)?;
+        let pool = &unit.agent.state.pool;
+        pool.add_http2(url, unit.proxy.clone(), conn.clone());
+        return connect_http2(unit, conn, false, body, history);
+    }
 
     if is_recycled {
         debug!("sending request (reused connection) {} {}", method, url);
@@ -275,16 +439,34 @@ fn connect_inner(
             return Err(err.into());
         }
     }
-    let retryable = unit.is_retryable(&body);
+    let retry_body = unit.retry_body(&body);
 
-    // send the body (which can be empty now depending on redirects)
-    body::send_body(body, unit.is_chunked, &mut stream)?;
+    let final_status = if unit.expects_continue(&body) {
+        await_continue(unit, &mut stream)?
+    } else {
+        None
+    };
 
-    // start reading the response to process cookies and redirects.
     // TODO: this unit.clone() bothers me. At this stage, we're not
     // going to use the unit (much) anymore, and it should be possible
     // to have ownership of it and pass it into the Response.
-    let result = Response::do_from_stream(stream, unit.clone());
+    let result = match final_status {
+        // the server answered without waiting for the body. since the body is
+        // left unsent, the connection can't be reused.
+        Some(status_line) => {
+            debug!("response before body was sent {} {}", method, url);
+            stream.set_unpoolable();
+            let stream = DeadlineStream::new(stream, unit.deadline);
+            Response::do_from_status_line(stream, unit.clone(), status_line)
+        }
+        None => {
+            // send the body (which can be empty now depending on redirects)
+            body::send_body(body, unit.is_chunked, &mut stream)?;
+
+            // start reading the response to process cookies and redirects.
+            Response::do_from_stream(stream, unit.clone())
+        }
+    };
 
     // https://tools.ietf.org/html/rfc7230#section-6.3.1
     // When an inbound connection is closed prematurely, a client MAY
@@ -296,15 +478,14 @@ fn connect_inner(
     // from the ConnectionPool, since those are most likely to have
     // reached a server-side timeout. Note that this means we may do
     // up to N+1 total tries, where N is max_idle_connections_per_host.
-    let resp = match result {
-        Err(err) if err.connection_closed() && retryable && is_recycled => {
+    let resp = match (result, retry_body) {
+        (Err(err), Some(body)) if err.connection_closed() && is_recycled => {
             debug!("retrying request {} {}: {}", method, url, err);
-            let empty = Payload::Empty.into_read();
             // NOTE: this recurses at most once because `use_pooled` is `false`.
-            return connect_inner(unit, false, empty, history);
+            return connect_inner(unit, false, body, history);
         }
-        Err(e) => return Err(e),
-        Ok(resp) => resp,
+        (Err(e), _) => return Err(e),
+        (Ok(resp), _) => resp,
     };
 
     // squirrel away cookies
@@ -317,6 +498,98 @@ fn connect_inner(
     Ok(resp)
 }
 
+/// Wait for the server to answer a request sent with `Expect: 100-continue`.
+///
+/// Returns `None` when the body should be sent. That is when the server answers
+/// `100 Continue`, but also when it stays silent for `timeout_expect_continue`, since
+/// servers are not required to support the expectation. Otherwise the server sent a
+/// final response without waiting for the body, and its status line is returned.
+fn await_continue(unit: &Unit, stream: &mut Stream) -> Result<Option<String>, Error> {
+    let config = &unit.agent.config;
+    let mut timeout = config.timeout_expect_continue;
+    if let Some(deadline) = unit.deadline {
+        timeout = timeout.min(stream::time_until_deadline(deadline)?);
+    }
+    if timeout.is_zero() {
+        return Ok(None);
+    }
+
+    stream.set_read_timeout(Some(timeout))?;
+    let waited = stream.fill_buf().map(|_| ());
+
+    // put back the read timeout from connect_host().
+    let timeout_read = match unit.deadline {
+        Some(deadline) => Some(stream::time_until_deadline(deadline)?),
+        None => config.timeout_read,
+    };
+    stream.set_read_timeout(timeout_read)?;
+
+    match waited {
+        Err(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            debug!("no 100 Continue after {:?}, sending body", timeout);
+            return Ok(None);
+        }
+        Err(e) => return Err(e.into()),
+        Ok(()) => {}
+    }
+
+    let (status_line, status) = response::read_status_line(stream, true)?;
+    if status == 100 {
+        debug!("received 100 Continue, sending body");
+        Ok(None)
+    } else {
+        Ok(Some(status_line))
+    }
+}
+
+/// Perform a request over an HTTP/2 connection. Does not follow redirects.
+#[cfg(feature = "http2")]
+fn connect_http2(
+    unit: &mut Unit,
+    conn: Arc<http2::Connection>,
+    is_recycled: bool,
+    body: SizedReader,
+    history: &[Url],
+) -> Result<Response, Error> {
+    let method = &unit.method;
+    let url = &unit.url;
+    if is_recycled {
+        debug!(
+            "sending request (reused HTTP/2 connection) {} {}",
+            method, url
+        );
+    } else {
+        debug!("sending request (HTTP/2) {} {}", method, url);
+    }
+
+    let retry_body = unit.retry_body(&body);
+
+    // Same as for HTTP/1.1, requests on a connection from the pool are retried on a
+    // new connection if the old one turns out to be closed.
+    let resp = match (http2::send_request(&conn, unit, body), retry_body) {
+        (Err(err), Some(body)) if err.connection_closed() && is_recycled => {
+            debug!("retrying request {} {}: {}", method, url, err);
+            // NOTE: this recurses at most once because `use_pooled` is `false`.
+            return connect_inner(unit, false, body, history);
+        }
+        (Err(e), _) => return Err(e),
+        (Ok(resp), _) => resp,
+    };
+
+    // squirrel away cookies
+    #[cfg(feature = "cookies")]
+    save_cookies(unit, &resp);
+
+    debug!("response {} to {} {}", resp.status(), method, url);
+
+    Ok(resp)
+}
+
 #[cfg(feature = "cookies")]
 fn extract_cookies(agent: &Agent, url: &Url) -> Option<Header> {
     let header_value = agent
@@ -343,18 +616,36 @@ fn extract_cookies(agent: &Agent, url: &Url) -> Option<Header> {
 }
 
 /// Connect the socket, either by using the pool or grab a new one.
-fn connect_socket(unit: &Unit, hostname: &str, use_pooled: bool) -> Result<(Stream, bool), Error> {
+fn connect_socket(unit: &mut Unit, use_pooled: bool) -> Result<(Stream, bool), Error> {
     match unit.url.scheme() {
         "http" | "https" | "test" => (),
+        #[cfg(unix)]
+        "http+unix" => (),
         scheme => return Err(ErrorKind::UnknownScheme.msg(format!("unknown scheme '{}'", scheme))),
     };
     if unit.url.scheme() != "https" && unit.agent.config.https_only {
         return Err(ErrorKind::InsecureRequestHttpsOnly
             .msg("can't perform non https request with https_only set"));
     }
+    #[cfg(unix)]
+    if let Some(path) = unit.unix_socket() {
+        if use_pooled {
+            // A unix socket can't be probed for being closed by the server like a
+            // TcpStream, so any stale connection shows up when reading the response.
+            if let Some(stream) = unit
+                .agent
+                .state
+                .pool
+                .try_get_unix_connection(&path, &unit.url)
+            {
+                return Ok((stream, true));
+            }
+        }
+        return Ok((stream::connect_unix(unit, &path)?, false));
+    }
     if use_pooled {
         let pool = &unit.agent.state.pool;
-        let proxy = &unit.agent.config.proxy;
+        let proxy = &unit.proxy;
         // The connection may have been closed by the server
         // due to idle timeout while it was sitting in the pool.
         // Loop until we find one that is still good or run out of connections.
@@ -366,13 +657,61 @@ fn connect_socket(unit: &Unit, hostname: &str, use_pooled: bool) -> Result<(Stre
             debug!("dropping stream from pool; closed by server: {:?}", stream);
         }
     }
-    let stream = match unit.url.scheme() {
-        "http" => stream::connect_http(unit, hostname),
-        "https" => stream::connect_https(unit, hostname),
+    loop {
+        match connect_new(unit) {
+            // try the next proxy when this one can't be reached.
+            Err(e) if unit.proxy.is_some() && !unit.proxy_fallbacks.is_empty() => {
+                if !matches!(
+                    e.kind(),
+                    ErrorKind::Dns | ErrorKind::ConnectionFailed | ErrorKind::ProxyConnect
+                ) {
+                    return Err(e);
+                }
+                let next = unit.proxy_fallbacks.remove(0);
+                debug!("proxy failed, trying {}:{}: {}", next.server, next.port, e);
+                unit.proxy = Some(next);
+            }
+            stream => return Ok((stream?, false)),
+        }
+    }
+}
+
+/// Open a new connection for the unit, through `unit.proxy` if set.
+fn connect_new(unit: &Unit) -> Result<Stream, Error> {
+    let hostname = unit
+        .url
+        .host_str()
+        // This unwrap is ok because Request::parse_url() ensure there is always a host present.
+        .unwrap();
+    let default = DefaultConnector { unit };
+    let connector = match &unit.agent.state.connector {
+        Some(connector) => connector.as_ref(),
+        None => &default,
+    };
+    match unit.url.scheme() {
+        "http" | "https" => stream::connect(unit, connector, hostname),
         "test" => connect_test(unit),
         scheme => Err(ErrorKind::UnknownScheme.msg(format!("unknown scheme {}", scheme))),
-    };
-    Ok((stream?, false))
+    }
+}
+
+/// The proxies for a request to `url`, in the order they should be tried.
+///
+/// An agent with a custom connector leaves proxies to it.
+fn select_proxies(agent: &Agent, url: &Url) -> Vec<Proxy> {
+    if agent.state.connector.is_some() {
+        return vec![];
+    }
+    if let Some(selector) = &agent.state.proxy_selector {
+        return selector.select(url);
+    }
+    if let Some(proxy) = &agent.config.proxy {
+        return vec![proxy.clone()];
+    }
+    match &agent.config.env_proxy {
+        Some(env_proxy) => env_proxy.proxy_for(url).into_iter().collect(),
+        None => vec![],
+    }
 }
 
 fn can_propagate_authorization_on_redirect(
@@ -407,54 +746,14 @@ fn send_prelude(unit: &Unit, stream: &mut Stream) -> io::Result<()> {
     // build into a buffer and send in one go.
     let mut prelude = PreludeBuilder::new();
 
-    let path = if let Some(proxy) = &unit.agent.config.proxy {
-        // HTTP proxies require the path to be in absolute URI form
-        // https://www.rfc-editor.org/rfc/rfc7230#section-5.3.2
-        match proxy.proto {
-            Proto::HTTP => match unit.url.port() {
-                Some(port) => format!(
-                    "{}://{}:{}{}",
-                    unit.url.scheme(),
-                    unit.url.host().unwrap(),
-                    port,
-                    unit.url.path()
-                ),
-                None => format!(
-                    "{}://{}{}",
-                    unit.url.scheme(),
-                    unit.url.host().unwrap(),
-                    unit.url.path()
-                ),
-            },
-            _ => unit.url.path().into(),
-        }
-    } else {
-        unit.url.path().into()
-    };
+    let path = request_target(unit);
 
     // request line
     prelude.write_request_line(&unit.method, &path, unit.url.query().unwrap_or_default())?;
 
     // host header if not set by user.
     if !header::has_header(&unit.headers, "host") {
-        let host = unit.url.host().unwrap();
-        match unit.url.port() {
-            Some(port) => {
-                let scheme_default: u16 = match unit.url.scheme() {
-                    "http" => 80,
-                    "https" => 443,
-                    _ => 0,
-                };
-                if scheme_default != 0 && scheme_default == port {
-                    prelude.write_header("Host", host)?;
-                } else {
-                    prelude.write_header("Host", format_args!("{}:{}", host, port))?;
-                }
-            }
-            None => {
-                prelude.write_header("Host", host)?;
-            }
-        }
+        prelude.write_header("Host", host_header(&unit.url))?;
     }
     if !header::has_header(&unit.headers, "user-agent") {
         prelude.write_header("User-Agent", &unit.agent.config.user_agent)?;
@@ -463,6 +762,17 @@ fn send_prelude(unit: &Unit, stream: &mut Stream) -> io::Result<()> {
         prelude.write_header("Accept", "*/*")?;
     }
 
+    // credentials for a proxy that gets the request in absolute form.
+    if uses_absolute_form(unit) && !header::has_header(&unit.headers, "proxy-authorization") {
+        let authorization = unit.proxy_authorization.clone().or_else(|| {
+            let proxy = unit.proxy.as_ref()?;
+            proxy.basic_authorization()
+        });
+        if let Some(authorization) = authorization {
+            prelude.write_sensitive_header("Proxy-Authorization", authorization)?;
+        }
+    }
+
     // other headers
     for header in &unit.headers {
         if let Some(v) = header.value() {
@@ -484,8 +794,87 @@ fn send_prelude(unit: &Unit, stream: &mut Stream) -> io::Result<()> {
     Ok(())
 }
 
+/// The value of the Host header (or :authority for HTTP/2) for a url.
+fn host_header(url: &Url) -> String {
+    // the host of an http+unix url is the socket path.
+    if url.scheme() == "http+unix" {
+        return "localhost".to_string();
+    }
+    let host = url.host().unwrap();
+    match url.port() {
+        Some(port) => {
+            let scheme_default: u16 = match url.scheme() {
+                "http" => 80,
+                "https" => 443,
+                _ => 0,
+            };
+            if scheme_default != 0 && scheme_default == port {
+                host.to_string()
+            } else {
+                format!("{}:{}", host, port)
+            }
+        }
+        None => host.to_string(),
+    }
+}
+
 fn is_header_sensitive(header: &Header) -> bool {
-    header.is_name("Authorization") || header.is_name("Cookie")
+    header.is_name("Authorization")
+        || header.is_name("Proxy-Authorization")
+        || header.is_name("Cookie")
+}
+
+/// The path of the request line, which is in absolute form for HTTP proxies.
+fn request_target(unit: &Unit) -> String {
+    if let Some(proxy) = &unit.proxy {
+        // HTTP proxies require the path to be in absolute URI form
+        // https://www.rfc-editor.org/rfc/rfc7230#section-5.3.2
+        match proxy.proto {
+            Proto::HTTP | Proto::HTTPS => match unit.url.port() {
+                Some(port) => format!(
+                    "{}://{}:{}{}",
+                    unit.url.scheme(),
+                    unit.url.host().unwrap(),
+                    port,
+                    unit.url.path()
+                ),
+                None => format!(
+                    "{}://{}{}",
+                    unit.url.scheme(),
+                    unit.url.host().unwrap(),
+                    unit.url.path()
+                ),
+            },
+            _ => unit.url.path().into(),
+        }
+    } else {
+        unit.url.path().into()
+    }
+}
+
+/// Whether the request goes to an HTTP proxy as is, rather than through a tunnel.
+fn uses_absolute_form(unit: &Unit) -> bool {
+    matches!(&unit.proxy, Some(proxy) if proxy.is_http()) && unit.url.scheme() == "http"
+}
+
+/// The credentials to answer the challenge in a 407 from an HTTP proxy, if we have
+/// credentials that weren't already rejected.
+fn proxy_authorization(unit: &Unit, resp: &Response) -> Option<String> {
+    if header::has_header(&unit.headers, "proxy-authorization") {
+        return None;
+    }
+    let proxy = unit.proxy.as_ref().filter(|_| uses_absolute_form(unit))?;
+    let mut uri = request_target(unit);
+    if let Some(query) = unit.url.query().filter(|q| !q.is_empty()) {
+        uri.push('?');
+        uri.push_str(query);
+    }
+    let authorization = proxy.authorization(&resp.headers, &unit.method, &uri)?;
+    // Basic credentials were sent upfront, asking again means they are wrong.
+    if Some(&authorization) == proxy.basic_authorization().as_ref() {
+        return None;
+    }
+    Some(authorization)
 }
 
 struct PreludeBuilder {
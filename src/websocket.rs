@@ -0,0 +1,484 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use base64::{prelude::BASE64_STANDARD, Engine};
+
+use crate::error::{Error, ErrorKind};
+use crate::random::random_u64;
+use crate::request::Request;
+use crate::stream::ReadWrite;
+
+/// Appended to the key to compute `Sec-WebSocket-Accept`.
+/// <https://www.rfc-editor.org/rfc/rfc6455#section-1.3>
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Messages larger than this are refused, unless changed with
+/// [`WebSocket::set_max_message_size()`].
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 64 * 1024 * 1024;
+
+// Opcodes, <https://www.rfc-editor.org/rfc/rfc6455#section-5.2>
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+const FIN: u8 = 0x80;
+const RSV: u8 = 0x70;
+const MASK: u8 = 0x80;
+
+/// Control frames carry at most this much payload.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// A message sent or received on a [`WebSocket`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// A UTF-8 text message.
+    Text(String),
+    /// A binary message.
+    Binary(Vec<u8>),
+    /// A ping. Received pings are answered with a pong automatically.
+    Ping(Vec<u8>),
+    /// A pong, in answer to a ping.
+    Pong(Vec<u8>),
+    /// The closing handshake, with the status code and reason if there is one.
+    Close(Option<CloseFrame>),
+}
+
+/// The status code and reason of a close message.
+/// <https://www.rfc-editor.org/rfc/rfc6455#section-7.4>
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    /// The status code, such as 1000 for a normal closure.
+    pub code: u16,
+    /// The reason for closing, can be empty.
+    pub reason: String,
+}
+
+/// A client WebSocket connection, <https://www.rfc-editor.org/rfc/rfc6455>
+///
+/// Created by [`Agent::websocket()`](crate::Agent::websocket) or
+/// [`Request::websocket()`](crate::Request::websocket). The socket is blocking:
+/// [`read()`](WebSocket::read) waits for the next message, using the agent's read
+/// timeout. Fragmented messages are reassembled, and pings are answered while reading.
+///
+/// ```no_run
+/// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// # ureq::is_test(true);
+/// use ureq::Message;
+///
+/// let mut socket = ureq::agent().websocket("wss://echo.example.com/")?;
+/// socket.send(Message::Text("hello".into()))?;
+/// if let Message::Text(reply) = socket.read()? {
+///     println!("{}", reply);
+/// }
+/// socket.close(1000, "done")?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct WebSocket {
+    stream: Box<dyn ReadWrite>,
+    rng: u64,
+    max_message_size: usize,
+    /// A fragmented message being received, interrupted by a control frame.
+    partial: Option<(u8, Vec<u8>)>,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl fmt::Debug for WebSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("stream", &self.stream)
+            .field("close_sent", &self.close_sent)
+            .field("close_received", &self.close_received)
+            .finish()
+    }
+}
+
+/// Perform the opening handshake with `request`.
+/// <https://www.rfc-editor.org/rfc/rfc6455#section-4.1>
+pub(crate) fn handshake(request: Request) -> Result<WebSocket, Error> {
+    let mut rng = seed();
+    let mut key = [0; 16];
+    key[..8].copy_from_slice(&next_random(&mut rng).to_be_bytes());
+    key[8..].copy_from_slice(&next_random(&mut rng).to_be_bytes());
+    let key = BASE64_STANDARD.encode(key);
+
+    let response = request
+        .set("Connection", "Upgrade")
+        .set("Upgrade", "websocket")
+        .set("Sec-WebSocket-Version", "13")
+        .set("Sec-WebSocket-Key", &key)
+        .call()?;
+
+    if response.status() != 101 {
+        return Err(ErrorKind::BadStatus.msg(format!(
+            "WebSocket handshake answered with status {}",
+            response.status()
+        )));
+    }
+    let upgrade = response
+        .header("upgrade")
+        .map(|v| v.eq_ignore_ascii_case("websocket"))
+        .unwrap_or(false);
+    let connection = response
+        .header("connection")
+        .map(|v| {
+            v.split(',')
+                .any(|t| t.trim().eq_ignore_ascii_case("upgrade"))
+        })
+        .unwrap_or(false);
+    if !upgrade || !connection {
+        return Err(ErrorKind::BadHeader.msg("WebSocket handshake was not upgraded to websocket"));
+    }
+    if response.header("sec-websocket-accept") != Some(accept_key(&key).as_str()) {
+        return Err(ErrorKind::BadHeader.msg("Sec-WebSocket-Accept does not match the key"));
+    }
+
+    Ok(WebSocket {
+        stream: response.into_upgraded()?,
+        rng,
+        max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        partial: None,
+        close_sent: false,
+        close_received: false,
+    })
+}
+
+fn accept_key(key: &str) -> String {
+    let digest = sha1_smol::Sha1::from(format!("{}{}", key, ACCEPT_GUID)).digest();
+    BASE64_STANDARD.encode(digest.bytes())
+}
+
+impl WebSocket {
+    /// The underlying connection, for instance to change its timeouts.
+    pub fn get_ref(&self) -> &dyn ReadWrite {
+        self.stream.as_ref()
+    }
+
+    /// The largest message accepted by [`read()`](WebSocket::read). Defaults to 64 megabytes.
+    pub fn set_max_message_size(&mut self, size: usize) {
+        self.max_message_size = size;
+    }
+
+    /// Read the next message.
+    ///
+    /// Pings are answered before they are returned. When the server starts the closing
+    /// handshake, it is answered and [`Message::Close`] is returned; reading after that
+    /// fails with [`io::ErrorKind::NotConnected`].
+    pub fn read(&mut self) -> io::Result<Message> {
+        if self.close_received {
+            return Err(not_connected());
+        }
+        loop {
+            let (fin, opcode, payload) = self.read_frame()?;
+            match opcode {
+                PING => {
+                    if !self.close_sent {
+                        self.write_frame(true, PONG, &payload)?;
+                    }
+                    return Ok(Message::Ping(payload));
+                }
+                PONG => return Ok(Message::Pong(payload)),
+                CLOSE => {
+                    self.close_received = true;
+                    let frame = parse_close(&payload)?;
+                    if !self.close_sent {
+                        // Echo the status code, as the closing handshake asks.
+                        self.close_sent = true;
+                        self.write_frame(true, CLOSE, &payload[..payload.len().min(2)])?;
+                    }
+                    return Ok(Message::Close(frame));
+                }
+                TEXT | BINARY if self.partial.is_none() => self.partial = Some((opcode, payload)),
+                TEXT | BINARY => {
+                    return Err(protocol_error("message started inside a fragmented one"))
+                }
+                CONTINUATION => match &mut self.partial {
+                    Some((_, data)) => {
+                        if data.len() + payload.len() > self.max_message_size {
+                            return Err(too_large(self.max_message_size));
+                        }
+                        data.extend_from_slice(&payload);
+                    }
+                    None => return Err(protocol_error("continuation frame without a message")),
+                },
+                _ => return Err(protocol_error("unknown opcode")),
+            }
+            if fin {
+                if let Some((opcode, data)) = self.partial.take() {
+                    return if opcode == TEXT {
+                        String::from_utf8(data)
+                            .map(Message::Text)
+                            .map_err(|_| protocol_error("text message is not UTF-8"))
+                    } else {
+                        Ok(Message::Binary(data))
+                    };
+                }
+            }
+        }
+    }
+
+    /// Send a message in a single frame.
+    ///
+    /// Sending [`Message::Close`] starts the closing handshake without waiting for the
+    /// server's answer, see [`close()`](WebSocket::close).
+    pub fn send(&mut self, message: Message) -> io::Result<()> {
+        self.send_fragmented(message, usize::MAX)
+    }
+
+    /// Send a message, split in frames of at most `fragment_size` bytes of payload.
+    ///
+    /// Only text and binary messages are fragmented, control messages always go in a
+    /// single frame.
+    pub fn send_fragmented(&mut self, message: Message, fragment_size: usize) -> io::Result<()> {
+        if self.close_sent {
+            return Err(not_connected());
+        }
+        let (opcode, payload) = match message {
+            Message::Text(text) => (TEXT, text.into_bytes()),
+            Message::Binary(data) => (BINARY, data),
+            Message::Ping(data) => return self.write_control(PING, &data),
+            Message::Pong(data) => return self.write_control(PONG, &data),
+            Message::Close(frame) => {
+                let payload = match frame {
+                    Some(frame) => {
+                        let mut payload = frame.code.to_be_bytes().to_vec();
+                        payload.extend_from_slice(frame.reason.as_bytes());
+                        payload
+                    }
+                    None => vec![],
+                };
+                self.write_control(CLOSE, &payload)?;
+                self.close_sent = true;
+                return Ok(());
+            }
+        };
+        let mut chunks = payload.chunks(fragment_size.max(1)).peekable();
+        if chunks.peek().is_none() {
+            return self.write_frame(true, opcode, &[]);
+        }
+        let mut opcode = opcode;
+        while let Some(chunk) = chunks.next() {
+            self.write_frame(chunks.peek().is_none(), opcode, chunk)?;
+            opcode = CONTINUATION;
+        }
+        Ok(())
+    }
+
+    /// Send a ping with `payload`, at most 125 bytes.
+    pub fn ping(&mut self, payload: &[u8]) -> io::Result<()> {
+        self.send(Message::Ping(payload.to_vec()))
+    }
+
+    /// Perform the closing handshake: send a close message with `code` and `reason`,
+    /// then read until the server answers with its own.
+    ///
+    /// Messages received in the meantime are discarded.
+    pub fn close(&mut self, code: u16, reason: &str) -> io::Result<()> {
+        if !self.close_sent {
+            let frame = CloseFrame {
+                code,
+                reason: reason.to_string(),
+            };
+            self.send(Message::Close(Some(frame)))?;
+        }
+        while !self.close_received {
+            match self.read() {
+                Ok(_) => {}
+                // The server may close the connection right after its close message.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> io::Result<(bool, u8, Vec<u8>)> {
+        let mut head = [0; 2];
+        self.stream.read_exact(&mut head)?;
+        let fin = head[0] & FIN != 0;
+        let opcode = head[0] & 0x0F;
+        if head[0] & RSV != 0 {
+            return Err(protocol_error("reserved bits set"));
+        }
+        if head[1] & MASK != 0 {
+            return Err(protocol_error("masked frame from server"));
+        }
+        let len = match head[1] & 0x7F {
+            126 => {
+                let mut len = [0; 2];
+                self.stream.read_exact(&mut len)?;
+                u16::from_be_bytes(len) as u64
+            }
+            127 => {
+                let mut len = [0; 8];
+                self.stream.read_exact(&mut len)?;
+                u64::from_be_bytes(len)
+            }
+            len => len as u64,
+        };
+        if opcode >= CLOSE && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(protocol_error("invalid control frame"));
+        }
+        if len > self.max_message_size as u64 {
+            return Err(too_large(self.max_message_size));
+        }
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        Ok((fin, opcode, payload))
+    }
+
+    fn write_control(&mut self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        if payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "WebSocket control message larger than 125 bytes",
+            ));
+        }
+        self.write_frame(true, opcode, payload)
+    }
+
+    /// Write a frame, masked as frames from clients must be.
+    fn write_frame(&mut self, fin: bool, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        let mut frame = Vec::with_capacity(payload.len() + 14);
+        frame.push(if fin { FIN | opcode } else { opcode });
+        match payload.len() {
+            len if len < 126 => frame.push(MASK | len as u8),
+            len if len <= u16::MAX as usize => {
+                frame.push(MASK | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+            len => {
+                frame.push(MASK | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            }
+        }
+        let mask = (next_random(&mut self.rng) as u32).to_be_bytes();
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        self.stream.write_all(&frame)?;
+        self.stream.flush()
+    }
+}
+
+fn parse_close(payload: &[u8]) -> io::Result<Option<CloseFrame>> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(protocol_error("close frame with a truncated status code")),
+        [hi, lo, reason @ ..] => {
+            let reason = std::str::from_utf8(reason)
+                .map_err(|_| protocol_error("close reason is not UTF-8"))?;
+            Ok(Some(CloseFrame {
+                code: u16::from_be_bytes([*hi, *lo]),
+                reason: reason.to_string(),
+            }))
+        }
+    }
+}
+
+fn protocol_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("WebSocket: {}", msg))
+}
+
+fn too_large(max: usize) -> io::Error {
+    protocol_error(&format!("message larger than {} bytes", max))
+}
+
+fn not_connected() -> io::Error {
+    io::Error::new(io::ErrorKind::NotConnected, "WebSocket is closed")
+}
+
+/// Seed for the keys and masks, which only need to be unpredictable to the network.
+fn seed() -> u64 {
+    random_u64() | 1
+}
+
+/// xorshift64*
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545_F491_4F6C_DD1D)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stream::ReadOnlyStream;
+
+    fn socket(input: Vec<u8>) -> WebSocket {
+        WebSocket {
+            stream: Box::new(ReadOnlyStream::new(input)),
+            rng: seed(),
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            partial: None,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    #[test]
+    fn accept() {
+        // The example from the RFC.
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn fragmented_with_ping() {
+        let mut input = vec![TEXT, 3];
+        input.extend_from_slice(b"Hel");
+        input.extend_from_slice(&[FIN | PING, 1, b'p']);
+        input.extend_from_slice(&[FIN | CONTINUATION, 2]);
+        input.extend_from_slice(b"lo");
+        let mut socket = socket(input);
+        assert_eq!(socket.read().unwrap(), Message::Ping(b"p".to_vec()));
+        assert_eq!(socket.read().unwrap(), Message::Text("Hello".to_string()));
+    }
+
+    #[test]
+    fn extended_length() {
+        let mut input = vec![FIN | BINARY, 126, 0x01, 0x00];
+        input.extend_from_slice(&[7; 256]);
+        let mut socket = socket(input);
+        assert_eq!(socket.read().unwrap(), Message::Binary(vec![7; 256]));
+    }
+
+    #[test]
+    fn close() {
+        let mut input = vec![FIN | CLOSE, 4, 0x03, 0xE8];
+        input.extend_from_slice(b"ok");
+        let mut socket = socket(input);
+        let frame = CloseFrame {
+            code: 1000,
+            reason: "ok".to_string(),
+        };
+        assert_eq!(socket.read().unwrap(), Message::Close(Some(frame)));
+        assert!(socket.close_sent);
+        assert_eq!(
+            socket.read().unwrap_err().kind(),
+            io::ErrorKind::NotConnected
+        );
+    }
+
+    #[test]
+    fn protocol_errors() {
+        // Masked frame from the server.
+        assert!(socket(vec![FIN | TEXT, MASK]).read().is_err());
+        // Fragmented control frame.
+        assert!(socket(vec![PING, 0]).read().is_err());
+        // Continuation without a message.
+        assert!(socket(vec![FIN | CONTINUATION, 0]).read().is_err());
+        // Invalid UTF-8.
+        assert!(socket(vec![FIN | TEXT, 1, 0xFF]).read().is_err());
+        // Too large.
+        let mut socket = socket(vec![FIN | BINARY, 3, 1, 2, 3]);
+        socket.set_max_message_size(2);
+        assert!(socket.read().is_err());
+    }
+}